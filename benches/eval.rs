@@ -0,0 +1,56 @@
+//! Times a few small programs under each engine. Run with `cargo bench`.
+extern crate scheme;
+
+use std::time::{Duration, Instant};
+
+use scheme::{Engine, Interpreter};
+
+const PROGRAMS: &[(&str, &str, &str)] = &[
+    ("calls", "
+        (define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))",
+     "(fib 18)"),
+    ("closures", "
+        (define (make-counter)
+          (define n 0)
+          (lambda () (set! n (+ n 1)) n))
+        (define (run counter i) (if (= i 0) (counter) (begin (counter) (run counter (- i 1)))))",
+     "(run (make-counter) 500)"),
+    ("lists", "
+        (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))
+        (define (sum l) (if (null? l) 0 (+ (car l) (sum (cdr l)))))",
+     "(sum (build 500 '()))"),
+    ("strings", "
+        (define (grow s n) (if (= n 0) s (grow (string-append s s) (- n 1))))
+        (define text (grow \"lorem ipsum dolor sit amet \" 12))
+        (define (pass s n) (if (= n 0) s (pass s (- n 1))))",
+     "(string-length (pass text 500))"),
+    ("quoted data", "
+        (define (table) '((a . 1) (b . 2) (c . 3) (d . 4) (e . 5) (f . 6) (g . 7)))
+        (define (lookups n) (if (= n 0) (length (table)) (begin (table) (lookups (- n 1)))))",
+     "(lookups 500)"),
+];
+
+// Runs the expression repeatedly for about a second, returning the mean time
+fn time(interp: &mut Interpreter, expr: &str) -> Duration {
+    interp.eval_str(expr).unwrap(); // warm up
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        interp.eval_str(expr).unwrap();
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+fn main() {
+    println!("{:<12} {:>14} {:>14}", "", "tree-walker", "bytecode");
+    for &(name, setup, expr) in PROGRAMS {
+        let mut times = vec![];
+        for &engine in &[Engine::TreeWalker, Engine::Bytecode] {
+            let mut interp = Interpreter::with_engine(engine);
+            interp.eval_str(setup).unwrap();
+            times.push(time(&mut interp, expr));
+        }
+        println!("{:<12} {:>14?} {:>14?}", name, times[0], times[1]);
+    }
+}
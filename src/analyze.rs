@@ -0,0 +1,263 @@
+use std::rc::Rc;
+use builtins;
+use eval;
+use eval::{Env, EvalError, Params, SValue};
+use interpreter::Interpreter;
+use parse::Sexp;
+
+/// An expression after analysis: its syntax checked, and its variables
+/// resolved. Those bound by an enclosing lambda are found by `depth` (the
+/// number of frames out) and slot `index`; the rest are free, looked up
+/// by name in the scope the code runs in.
+#[derive(Debug)]
+pub enum Node {
+    Const(SValue),
+    Local(usize, usize),
+    Free(usize, Rc<str>),
+    SetLocal(usize, usize, Box<Node>),
+    SetFree(usize, Rc<str>, Box<Node>),
+    Define(Rc<str>, Box<Node>), // at top level; those in lambdas are SetLocal
+    Lambda(Rc<Lambda>),
+    If(Box<Node>, Box<Node>, Box<Node>),
+    Begin(Vec<Node>),
+    Call(Box<Node>, Vec<Node>),
+    Folded(Box<Fold>),
+}
+
+#[derive(Debug)]
+pub struct Lambda {
+    pub params: Params,
+    pub frame: Vec<Rc<str>>, // the names of the slots: params, then internal defines
+    pub body: Node,
+}
+
+/// A free variable, as in Node::Free, and the primitive it should hold.
+pub type Guard = (usize, Rc<str>, SValue);
+
+/// A call of pure primitives on constants, made during analysis. Its value
+/// stands for the call as long as each guard's variable still holds the
+/// primitive it did then.
+#[derive(Debug)]
+pub struct Fold {
+    pub value: SValue,
+    pub guards: Vec<Guard>,
+    pub call: Node, // made after all if a guard fails
+}
+
+/// Analyzes an expanded top-level expression, to be run in env.
+pub fn analyze(interp: &mut Interpreter, env: &Env, sexp: &Sexp) -> Result<Node, EvalError> {
+    Analyzer { interp, env, scopes: vec![] }.expr(sexp)
+}
+
+fn check_sym(sexp: &Sexp, s: &str) -> bool {
+    match *sexp {
+        Sexp::Symbol(ref sym) => &**sym == s,
+        _ => false,
+    }
+}
+
+// Adds to names each variable a lambda body defines, not counting those
+// of lambdas within it
+fn scan_defines(sexp: &Sexp, names: &mut Vec<Rc<str>>) {
+    if let Sexp::List(ref items) = *sexp {
+        let mut iter = items.iter();
+        match iter.next() {
+            Some(head) if check_sym(head, "quote") || check_sym(head, "lambda") => return,
+            Some(head) if check_sym(head, "define") => {
+                if let Some(Sexp::Symbol(name)) = iter.next() {
+                    if !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+            },
+            Some(head) => scan_defines(head, names),
+            None => {},
+        }
+        for item in iter {
+            scan_defines(item, names);
+        }
+    }
+}
+
+// Whether evaluating a node can have no effect beyond giving its value
+fn is_pure(node: &Node) -> bool {
+    matches!(*node, Node::Const(_) | Node::Lambda(_))
+}
+
+struct Analyzer<'a> {
+    interp: &'a mut Interpreter,
+    env: &'a Env,
+    scopes: Vec<Vec<Rc<str>>>, // the slot names of each enclosing frame, innermost last
+}
+
+enum Address {
+    Local(usize, usize),
+    Free(usize),
+}
+
+impl<'a> Analyzer<'a> {
+    fn resolve(&self, s: &str) -> Address {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(i) = scope.iter().position(|n| &**n == s) {
+                return Address::Local(depth, i);
+            }
+        }
+        Address::Free(self.scopes.len())
+    }
+
+    fn expr(&mut self, sexp: &Sexp) -> Result<Node, EvalError> {
+        Ok(match *sexp {
+            Sexp::Number(f) => Node::Const(SValue::Number(f)),
+            Sexp::Bool(b) => Node::Const(SValue::Bool(b)),
+            Sexp::Char(c) => Node::Const(SValue::Char(c)),
+            Sexp::String(ref s) => Node::Const(SValue::String(s.clone())),
+            Sexp::Vector(_) => Node::Const(eval::quote(sexp)?),
+            Sexp::Symbol(ref s) => match self.resolve(s) {
+                Address::Local(depth, i) => Node::Local(depth, i),
+                Address::Free(depth) => Node::Free(depth, s.clone()),
+            },
+            Sexp::List(ref items) => {
+                match items.first() {
+                    None => return Err(EvalError::from("Unexpected ()")),
+                    Some(cmd) if check_sym(cmd, "quote") => match items.get(1) {
+                        Some(datum) => Node::Const(eval::quote(datum)?),
+                        None => return Err(EvalError::from("`quote` expected 1 arg; was given 0")),
+                    },
+                    Some(cmd) if check_sym(cmd, "define") => self.define(&items[1..])?,
+                    Some(cmd) if check_sym(cmd, "set!") => self.set(&items[1..])?,
+                    Some(cmd) if check_sym(cmd, "lambda") => self.lambda(&items[1..])?,
+                    Some(cmd) if check_sym(cmd, "if") => self.if_(&items[1..])?,
+                    Some(cmd) if check_sym(cmd, "begin") => self.begin(&items[1..])?,
+                    Some(f) => {
+                        let f = self.expr(f)?;
+                        let args: Vec<Node> = items[1..].iter().map(|x| self.expr(x)).collect::<Result<_, _>>()?;
+                        match self.fold(&f, &args) {
+                            Some((value, guards)) => {
+                                let call = Node::Call(Box::new(f), args);
+                                Node::Folded(Box::new(Fold { value, guards, call }))
+                            },
+                            None => Node::Call(Box::new(f), args),
+                        }
+                    },
+                }
+            },
+        })
+    }
+
+    // Makes a call now if f is a free variable naming a primitive that
+    // builtins::is_foldable, which the scope still binds, and the arguments
+    // are constant. The call is left to run if it fails.
+    fn fold(&mut self, f: &Node, args: &[Node]) -> Option<(SValue, Vec<Guard>)> {
+        let (depth, name) = match *f {
+            Node::Free(depth, ref name) if builtins::is_foldable(name) => (depth, name),
+            _ => return None,
+        };
+        let primitive = self.interp.primitives().iter().find(|(n, _)| n == name)?.1.clone();
+        if self.env.borrow().lookup(name).as_ref() != Some(&primitive) {
+            return None;
+        }
+        let mut guards = vec![(depth, name.clone(), primitive.clone())];
+        let mut values = vec![];
+        for arg in args {
+            match *arg {
+                Node::Const(ref v) => values.push(v.clone()),
+                Node::Folded(ref fold) => {
+                    for guard in &fold.guards {
+                        if !guards.iter().any(|(d, n, _)| *d == guard.0 && *n == guard.1) {
+                            guards.push(guard.clone());
+                        }
+                    }
+                    values.push(fold.value.clone());
+                },
+                _ => return None,
+            }
+        }
+        let value = eval::apply(self.interp, primitive, values).ok()?;
+        Some((value, guards))
+    }
+
+    fn define(&mut self, args: &[Sexp]) -> Result<Node, EvalError> {
+        match args.first() {
+            Some(Sexp::Symbol(s)) => {
+                let value = match args.get(1) {
+                    Some(value) => Box::new(self.expr(value)?),
+                    None => return Err(EvalError::from("Expected value in define statement")),
+                };
+                // Defines in a lambda body were given slots in its frame
+                Ok(match self.scopes.last() {
+                    Some(scope) => match scope.iter().position(|n| n == s) {
+                        Some(i) => Node::SetLocal(0, i, value),
+                        None => return Err(EvalError::Message(format!("`define` of {} is not allowed here", s))),
+                    },
+                    None => Node::Define(s.clone(), value),
+                })
+            },
+            _ => Err(EvalError::from("Expected symbol after `define`")),
+        }
+    }
+
+    fn set(&mut self, args: &[Sexp]) -> Result<Node, EvalError> {
+        match (args.first(), args.get(1)) {
+            (Some(Sexp::Symbol(s)), Some(value)) => {
+                let value = Box::new(self.expr(value)?);
+                Ok(match self.resolve(s) {
+                    Address::Local(depth, i) => Node::SetLocal(depth, i, value),
+                    Address::Free(depth) => Node::SetFree(depth, s.clone(), value),
+                })
+            },
+            _ => Err(EvalError::from("Expected symbol and value after `set!`")),
+        }
+    }
+
+    fn lambda(&mut self, args: &[Sexp]) -> Result<Node, EvalError> {
+        let params = match args.first() {
+            Some(params) => eval::get_param_list(params)?,
+            None => return Err(EvalError::from("Expected argument list after `lambda`")),
+        };
+        let body = eval::make_body(&args[1..])?;
+
+        let mut frame: Vec<Rc<str>> = params.names.to_vec();
+        frame.extend(params.rest.iter().cloned());
+        scan_defines(&body, &mut frame);
+        self.scopes.push(frame.clone());
+        let body = self.expr(&body);
+        self.scopes.pop();
+        Ok(Node::Lambda(Rc::new(Lambda { params, frame, body: body? })))
+    }
+
+    fn if_(&mut self, args: &[Sexp]) -> Result<Node, EvalError> {
+        if args.len() < 2 || args.len() > 3 {
+            return Err(EvalError::from("`if` expected 2 or 3 args"));
+        }
+        let test = self.expr(&args[0])?;
+        let consequent = self.expr(&args[1])?;
+        let alternative = match args.get(2) {
+            Some(alternative) => self.expr(alternative)?,
+            None => Node::Const(SValue::nil()),
+        };
+        Ok(match test {
+            Node::Const(ref v) if v.is_true() => consequent,
+            Node::Const(_) => alternative,
+            test => Node::If(Box::new(test), Box::new(consequent), Box::new(alternative)),
+        })
+    }
+
+    // Nested begins are flattened, and values that would be discarded
+    // without effect are left out
+    fn begin(&mut self, args: &[Sexp]) -> Result<Node, EvalError> {
+        let mut nodes = vec![];
+        for sexp in args {
+            match self.expr(sexp)? {
+                Node::Begin(inner) => nodes.extend(inner),
+                node => nodes.push(node),
+            }
+        }
+        let last = nodes.pop().unwrap_or(Node::Const(SValue::nil()));
+        nodes.retain(|node| !is_pure(node));
+        if nodes.is_empty() {
+            return Ok(last);
+        }
+        nodes.push(last);
+        Ok(Node::Begin(nodes))
+    }
+}
@@ -0,0 +1,1231 @@
+use eval;
+use eval::{Env, ErrorKind, ErrorObject, EvalError, Pair, SValue, SymTable};
+use gc;
+use interpreter::Interpreter;
+use library;
+use native;
+use native::{Arity, IntoSValue, Native, Rest};
+use parse;
+use port;
+use parameter;
+use parameter::Parameter;
+use port::Port;
+use promise;
+use promise::Promise;
+use record::{Record, RecordType};
+use sandbox::{Capabilities, FileAccess};
+use vector::Vector;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+// The one character a case mapping gives, if it gives only one
+fn single<I: Iterator<Item = char>>(mut chars: I) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// True if cmp holds for each adjacent pair of xs
+fn chain(x: f64, xs: Vec<f64>, cmp: fn(&f64, &f64) -> bool) -> bool {
+    let mut prev = x;
+    for x in xs {
+        if !cmp(&prev, &x) {
+            return false;
+        }
+        prev = x;
+    }
+    true
+}
+
+fn pair(name: &str, x: SValue) -> Result<Rc<Pair>, String> {
+    match x {
+        SValue::Pair(p) => Ok(p),
+        x => Err(format!("`{}` expected a pair; was given {:?}", name, x)),
+    }
+}
+
+fn vector(name: &str, x: SValue) -> Result<Rc<Vector>, String> {
+    match x {
+        SValue::Vector(v) => Ok(v),
+        x => Err(format!("`{}` expected a vector; was given {:?}", name, x)),
+    }
+}
+
+// The elements from start to end, which default to the whole vector
+fn vector_range(name: &str, v: &Vector, start: Option<usize>, end: Option<usize>)
+                -> Result<(usize, usize), String> {
+    let (start, end) = (start.unwrap_or(0), end.unwrap_or_else(|| v.len()));
+    if start > end || end > v.len() {
+        return Err(format!("`{}` was given the range {} to {} of a vector of length {}", name, start, end, v.len()));
+    }
+    Ok((start, end))
+}
+
+// The vector and range that `vector->list` and `vector-copy` take, having
+// charged for the elements they copy
+fn vector_args(interp: &mut Interpreter, name: &str, args: &[SValue])
+               -> Result<(Rc<Vector>, usize, usize), EvalError> {
+    let v = vector(name, args[0].clone())?;
+    let (start, end) = vector_range(name, &v, native::convert(name, args, 1)?, native::convert(name, args, 2)?)?;
+    interp.allocate(end - start)?;
+    Ok((v, start, end))
+}
+
+fn port(name: &str, x: &SValue) -> Result<Rc<Port>, EvalError> {
+    x.downcast::<Port>().ok_or_else(|| {
+        EvalError::Message(format!("`{}` expected a port; was given {:?}", name, x))
+    })
+}
+
+// The port passed as argument i, or else the current input port
+fn input_port(interp: &mut Interpreter, name: &str, args: &[SValue], i: usize)
+              -> Result<Rc<Port>, EvalError> {
+    port(name, args.get(i).unwrap_or(&interp.ports().input.get()))
+}
+
+fn output_port(interp: &mut Interpreter, name: &str, args: &[SValue], i: usize)
+               -> Result<Rc<Port>, EvalError> {
+    port(name, args.get(i).unwrap_or(&interp.ports().output.get()))
+}
+
+fn error_object(name: &str, x: &SValue) -> Result<Rc<ErrorObject>, String> {
+    x.downcast::<ErrorObject>()
+        .ok_or_else(|| format!("`{}` expected an error object; was given {:?}", name, x))
+}
+
+fn is_error(x: &SValue, kind: ErrorKind) -> bool {
+    x.downcast::<ErrorObject>().is_some_and(|e| e.kind == kind)
+}
+
+fn environment(name: &str, x: &SValue) -> Result<Rc<Env>, String> {
+    x.downcast::<Env>()
+        .ok_or_else(|| format!("`{}` expected an environment; was given {:?}", name, x))
+}
+
+fn environment_value(env: Env) -> SValue {
+    SValue::foreign("environment", env)
+}
+
+/// The libraries made from the primitives. Those of R7RS are all here, even
+/// where none of their procedures are implemented yet, so that programs
+/// importing them run; `(interpreter)` has what is particular to this one.
+pub const LIBRARIES: [&str; 20] = [
+    "(scheme base)", "(scheme case-lambda)", "(scheme char)", "(scheme complex)",
+    "(scheme cxr)", "(scheme eval)", "(scheme file)", "(scheme inexact)",
+    "(scheme lazy)", "(scheme load)", "(scheme process-context)", "(scheme r5rs)",
+    "(scheme read)", "(scheme repl)", "(scheme time)", "(scheme write)",
+    "(srfi 1)", "(srfi 41)", "(srfi 132)", "(interpreter)",
+];
+
+/// The library a primitive belongs to. (scheme r5rs) also has those of
+/// every other (scheme ...) library.
+pub fn library_of(name: &str) -> &'static str {
+    match name {
+        "char-alphabetic?" | "char-numeric?" | "char-whitespace?" | "char-upper-case?"
+            | "char-lower-case?" | "char-upcase" | "char-downcase" | "char-foldcase"
+            | "digit-value" => "(scheme char)",
+        "eval" | "environment" => "(scheme eval)",
+        "open-input-file" | "open-output-file" | "call-with-input-file"
+            | "call-with-output-file" | "with-input-from-file"
+            | "with-output-to-file" => "(scheme file)",
+        "load" => "(scheme load)",
+        "exit" | "command-line" => "(scheme process-context)",
+        "scheme-report-environment" | "null-environment" => "(scheme r5rs)",
+        "read" => "(scheme read)",
+        "interaction-environment" => "(scheme repl)",
+        "display" | "write" => "(scheme write)",
+        "%delay" | "%delay-force" | "make-promise" | "promise?" | "force" => "(scheme lazy)",
+        "stream-null" | "%stream-cons" | "stream?" | "stream-null?" | "stream-pair?" | "stream-car"
+            | "stream-cdr" | "stream-map" | "stream-filter" | "stream-take" | "list->stream"
+            | "stream->list" => "(srfi 41)",
+        "filter" | "remove" | "partition" | "reduce" | "fold" | "fold-left" | "fold-right"
+            | "append-map" | "list-index" | "any" | "every" | "iota" | "delete" | "last" => "(srfi 1)",
+        "sort" | "list-sort" | "sort!" | "merge" | "sorted?" | "vector-sort" | "vector-sort!"
+            | "vector-binary-search" => "(srfi 132)",
+        "gc" | "heap-statistics" | "reciprocal" | "double" | "with-output-to-string"
+            | "call-with-output-string" => "(interpreter)",
+        _ => "(scheme base)",
+    }
+}
+
+/// Whether a primitive only computes its result from its arguments, so
+/// that calls of it on constants can be made when code is compiled.
+pub fn is_foldable(name: &str) -> bool {
+    matches!(name,
+        "+" | "-" | "*" | "/" | "=" | "<" | ">" | "<=" | ">=" | "max" | "min" | "not" | "eq?"
+            | "equal?" | "null?" | "pair?" | "char?" | "char->integer" | "integer->char"
+            | "char-alphabetic?" | "char-numeric?" | "char-whitespace?" | "char-upper-case?"
+            | "char-lower-case?" | "char-upcase" | "char-downcase" | "char-foldcase"
+            | "digit-value" | "string-length")
+}
+
+fn or_eof<T: IntoSValue>(x: Option<T>) -> SValue {
+    x.map_or(SValue::Eof, IntoSValue::into_svalue)
+}
+
+// Defines the primitives the capabilities allow. Without the console, the
+// port procedures are still there, but the current ports refuse to be used.
+pub fn install(interp: &mut Interpreter, caps: &Capabilities) {
+    install_pure(interp);
+    install_exceptions(interp);
+    install_eval(interp);
+    install_ports(interp);
+    install_lists(interp);
+    install_vectors(interp);
+    install_sorting(interp);
+    install_match(interp);
+    install_records(interp);
+    install_lazy(interp);
+    install_dynamic(interp);
+    install_streams(interp);
+    if caps.files != FileAccess::None {
+        install_files(interp);
+    }
+    if caps.process {
+        install_process(interp);
+    }
+}
+
+fn install_pure(interp: &mut Interpreter) {
+    interp.define_fn("+", |Rest(xs): Rest<f64>| xs.iter().sum::<f64>());
+    interp.define_fn("*", |Rest(xs): Rest<f64>| xs.iter().product::<f64>());
+    interp.define_fn("-", |x: f64, Rest(xs): Rest<f64>| {
+        if xs.is_empty() { -x } else { xs.iter().fold(x, |acc, y| acc - y) }
+    });
+    interp.define_fn("/", |x: f64, Rest(xs): Rest<f64>| {
+        if xs.is_empty() { 1.0 / x } else { xs.iter().fold(x, |acc, y| acc / y) }
+    });
+    interp.define_fn("reciprocal", |x: f64| 1.0 / x);
+    interp.define_fn("max", |x: f64, Rest(xs): Rest<f64>| xs.into_iter().fold(x, f64::max));
+    interp.define_fn("min", |x: f64, Rest(xs): Rest<f64>| xs.into_iter().fold(x, f64::min));
+    interp.define_fn("=", |x: f64, Rest(xs): Rest<f64>| chain(x, xs, f64::eq));
+    interp.define_fn("<", |x: f64, Rest(xs): Rest<f64>| chain(x, xs, f64::lt));
+    interp.define_fn(">", |x: f64, Rest(xs): Rest<f64>| chain(x, xs, f64::gt));
+    interp.define_fn("<=", |x: f64, Rest(xs): Rest<f64>| chain(x, xs, f64::le));
+    interp.define_fn(">=", |x: f64, Rest(xs): Rest<f64>| chain(x, xs, f64::ge));
+
+    interp.define_fn("not", |x: SValue| x == SValue::Bool(false));
+    interp.define_fn("eq?", |a: SValue, b: SValue| match (&a, &b) {
+        (SValue::Pair(p), SValue::Pair(q)) => Rc::ptr_eq(p, q),
+        (SValue::String(s), SValue::String(t)) => Rc::ptr_eq(s, t),
+        (SValue::Vector(v), SValue::Vector(w)) => Rc::ptr_eq(v, w),
+        _ => a == b,
+    });
+    interp.define_native("equal?", Arity::exactly(2), |interp, args| {
+        Ok(SValue::Bool(args[0].equal(&args[1], &mut || interp.step())?))
+    });
+
+    interp.define_fn("cons", SValue::cons);
+    interp.define_fn("car", |p: SValue| pair("car", p).map(|p| p.car()));
+    interp.define_fn("cdr", |p: SValue| pair("cdr", p).map(|p| p.cdr()));
+    interp.define_fn("set-car!", |p: SValue, v: SValue| pair("set-car!", p).map(|p| p.set_car(v)));
+    interp.define_fn("set-cdr!", |p: SValue, v: SValue| pair("set-cdr!", p).map(|p| p.set_cdr(v)));
+    interp.define_fn("list", |Rest(xs): Rest<SValue>| SValue::list(xs));
+    interp.define_fn("null?", |x: SValue| x == SValue::Nil);
+    interp.define_fn("pair?", |x: SValue| matches!(x, SValue::Pair(_)));
+    interp.define_native("length", Arity::exactly(1), |interp, args| {
+        match args[0].list_items(&mut || interp.step())? {
+            Some(items) => Ok(SValue::Number(items.len() as f64)),
+            None => Err(EvalError::Message(format!(
+                "`length` expected a list as argument 1; was given {:?}", args[0]))),
+        }
+    });
+
+    interp.define_fn("values", |Rest(xs): Rest<SValue>| SValue::values(xs));
+    interp.define_native("call-with-values", Arity::exactly(2), |interp, args| {
+        let results = eval::apply(interp, args[0].clone(), vec![])?;
+        eval::apply(interp, args[1].clone(), results.into_values())
+    });
+
+    interp.define_fn("gc", gc::collect);
+    interp.define_fn("heap-statistics", || {
+        let stats = gc::stats();
+        let entry = |name: &str, n: usize| {
+            SValue::cons(SValue::symbol(name), SValue::Number(n as f64))
+        };
+        SValue::list(vec![
+            entry("live", stats.live),
+            entry("allocated", stats.allocated),
+            entry("collections", stats.collections),
+            entry("reclaimed", stats.reclaimed),
+        ])
+    });
+
+    interp.define_fn("string-length", |s: Rc<str>| s.chars().count());
+    interp.define_native("string-append", Arity::at_least(0), |interp, args| {
+        let ss = (0..args.len()).map(|i| native::convert("string-append", &args, i))
+            .collect::<Result<Vec<Rc<str>>, _>>()?;
+        interp.allocate(ss.iter().map(|s| s.len()).sum())?;
+        Ok(SValue::String(Rc::from(ss.concat())))
+    });
+
+    interp.define_fn("char?", |x: SValue| matches!(x, SValue::Char(_)));
+    interp.define_fn("char->integer", |c: char| c as u32 as f64);
+    interp.define_fn("integer->char", |n: f64| {
+        char::from_u32(n as u32).filter(|_| n >= 0.0 && n.fract() == 0.0)
+            .ok_or_else(|| format!("`integer->char` expected a code point; was given {}", n))
+    });
+    interp.define_fn("char-alphabetic?", char::is_alphabetic);
+    interp.define_fn("char-numeric?", char::is_numeric);
+    interp.define_fn("char-whitespace?", char::is_whitespace);
+    interp.define_fn("char-upper-case?", char::is_uppercase);
+    interp.define_fn("char-lower-case?", char::is_lowercase);
+    interp.define_fn("char-upcase", |c: char| single(c.to_uppercase()).unwrap_or(c));
+    interp.define_fn("char-downcase", |c: char| single(c.to_lowercase()).unwrap_or(c));
+    interp.define_fn("char-foldcase", |c: char| single(c.to_lowercase()).unwrap_or(c));
+    interp.define_fn("digit-value", |c: char| c.to_digit(10).map(|d| d as f64));
+
+    interp.define_fn("features", || {
+        SValue::list(library::features().into_iter().map(|f| SValue::symbol(&f)).collect())
+    });
+
+    let double = interp.eval_str("(lambda (x) (+ x x))");
+    if let Ok(f) = double {
+        interp.define_global("double", f);
+    }
+}
+
+// Errors unwind the Rust stack, so a handler installed by
+// with-exception-handler is only called once its thunk has been left, and
+// can't resume it; only raise-continuable calls it where the raise was.
+fn install_exceptions(interp: &mut Interpreter) {
+    interp.define_native("raise", Arity::exactly(1), |_, args| {
+        Err(EvalError::Raise(args[0].clone()))
+    });
+    interp.define_native("raise-continuable", Arity::exactly(1), |interp, args| {
+        let handler = match interp.handlers().pop() {
+            Some(Some(handler)) => handler,
+            // Guards catch what is raised within them
+            Some(None) => {
+                interp.handlers().push(None);
+                return Err(EvalError::Raise(args[0].clone()));
+            },
+            None => return Err(EvalError::Raise(args[0].clone())),
+        };
+        // The handler runs with only those outside it in effect
+        let result = eval::apply(interp, handler.clone(), args);
+        interp.handlers().push(Some(handler));
+        result
+    });
+    interp.define_native("with-exception-handler", Arity::exactly(2), |interp, args| {
+        let handler = args[0].clone();
+        interp.handlers().push(Some(handler.clone()));
+        let result = eval::apply(interp, args[1].clone(), vec![]);
+        interp.handlers().pop();
+        match result {
+            Err(e) => match e.clone().condition() {
+                Ok(condition) => {
+                    eval::apply(interp, handler, vec![condition])?;
+                    Err(e)
+                },
+                Err(e) => Err(e),
+            },
+            ok => ok,
+        }
+    });
+    // What `guard` expands into: calls body, and handler with anything it
+    // raises
+    interp.define_native("%guard", Arity::exactly(2), |interp, args| {
+        interp.handlers().push(None);
+        let result = eval::apply(interp, args[0].clone(), vec![]);
+        interp.handlers().pop();
+        match result {
+            Err(e) => eval::apply(interp, args[1].clone(), vec![e.condition()?]),
+            ok => ok,
+        }
+    });
+
+    interp.define_native("error", Arity::at_least(1), |_, mut args| {
+        let message = match args.remove(0) {
+            SValue::String(s) => s.to_string(),
+            other => format!("{:?}", other),
+        };
+        Err(EvalError::Raise(ErrorObject::value(ErrorKind::Other, message, args)))
+    });
+    interp.define_fn("error-object?", |x: SValue| x.downcast::<ErrorObject>().is_some());
+    interp.define_fn("error-object-message", |x: SValue| {
+        error_object("error-object-message", &x).map(|e| e.message.clone())
+    });
+    interp.define_fn("error-object-irritants", |x: SValue| {
+        error_object("error-object-irritants", &x).map(|e| e.irritants.clone())
+    });
+    interp.define_fn("read-error?", |x: SValue| is_error(&x, ErrorKind::Read));
+    interp.define_fn("file-error?", |x: SValue| is_error(&x, ErrorKind::File));
+}
+
+// Environments are scopes to evaluate code at the top level of. Apart from
+// the interaction environment, they are new each time, so that what code
+// defines in one is seen by nothing else.
+fn install_eval(interp: &mut Interpreter) {
+    interp.define_native("eval", Arity::between(1, 2), |interp, args| {
+        let env = match args.get(1) {
+            Some(env) => (*environment("eval", env)?).clone(),
+            None => interp.globals(),
+        };
+        interp.eval_in(&env, &eval::unquote(&args[0])?)
+    });
+    interp.define_native("interaction-environment", Arity::exactly(0), |interp, _| {
+        Ok(environment_value(interp.globals()))
+    });
+    interp.define_native("scheme-report-environment", Arity::exactly(1), |interp, args| {
+        if args[0] != SValue::Number(5.0) {
+            return Err(EvalError::from("`scheme-report-environment` only supports version 5"));
+        }
+        Ok(environment_value(interp.report_environment()))
+    });
+    interp.define_native("null-environment", Arity::exactly(1), |_, args| {
+        if args[0] != SValue::Number(5.0) {
+            return Err(EvalError::from("`null-environment` only supports version 5"));
+        }
+        // Syntax isn't bound in scopes, so this one is empty
+        Ok(environment_value(SymTable::empty().into_env()))
+    });
+    interp.define_native("environment", Arity::at_least(0), |interp, args| {
+        let env = SymTable::empty().into_env();
+        for set in &args {
+            library::import(interp, &env, &eval::unquote(set)?)?;
+        }
+        Ok(environment_value(env))
+    });
+}
+
+fn install_ports(interp: &mut Interpreter) {
+    let current = [
+        ("current-input-port", interp.ports().input.clone()),
+        ("current-output-port", interp.ports().output.clone()),
+        ("current-error-port", interp.ports().error.clone()),
+    ];
+    for (name, parameter) in current {
+        interp.define_global(name, SValue::Parameter(parameter));
+    }
+    interp.define_fn("input-port?", |x: SValue| x.downcast::<Port>().is_some_and(|p| p.is_input()));
+    interp.define_fn("output-port?", |x: SValue| x.downcast::<Port>().is_some_and(|p| !p.is_input()));
+    interp.define_fn("eof-object", || SValue::Eof);
+    interp.define_fn("eof-object?", |x: SValue| x == SValue::Eof);
+
+    interp.define_native("read", Arity::between(0, 1), |interp, args| {
+        let text = match input_port(interp, "read", &args, 0)?.read_datum()? {
+            Some(text) => text,
+            None => return Ok(SValue::Eof),
+        };
+        let read_error = |message: String| {
+            EvalError::Raise(ErrorObject::value(ErrorKind::Read, format!("`read` {}", message), vec![]))
+        };
+        match parse::read_all(&text) {
+            Ok(ref data) if data.len() == 1 => eval::quote(&data[0]).map_err(|e| read_error(e.to_string())),
+            Ok(_) => Err(read_error(format!("found more than one datum in {}", text))),
+            Err(e) => Err(read_error(format!("could not parse {}: {}", text, e))),
+        }
+    });
+    interp.define_native("read-char", Arity::between(0, 1), |interp, args| {
+        Ok(or_eof(input_port(interp, "read-char", &args, 0)?.read_char()?))
+    });
+    interp.define_native("peek-char", Arity::between(0, 1), |interp, args| {
+        Ok(or_eof(input_port(interp, "peek-char", &args, 0)?.peek_char()?))
+    });
+    interp.define_native("read-line", Arity::between(0, 1), |interp, args| {
+        Ok(or_eof(input_port(interp, "read-line", &args, 0)?.read_line()?))
+    });
+    interp.define_native("read-string", Arity::between(1, 2), |interp, args| {
+        let k: usize = native::convert("read-string", &args, 0)?;
+        Ok(or_eof(input_port(interp, "read-string", &args, 1)?.read_string(k)?))
+    });
+
+    interp.define_native("write-char", Arity::between(1, 2), |interp, args| {
+        let c: char = native::convert("write-char", &args, 0)?;
+        output_port(interp, "write-char", &args, 1)?.write_str(c.encode_utf8(&mut [0; 4]))?;
+        Ok(SValue::nil())
+    });
+    interp.define_native("write-string", Arity::between(1, 2), |interp, args| {
+        let s: &str = native::convert("write-string", &args, 0)?;
+        output_port(interp, "write-string", &args, 1)?.write_str(s)?;
+        Ok(SValue::nil())
+    });
+    interp.define_native("newline", Arity::between(0, 1), |interp, args| {
+        output_port(interp, "newline", &args, 0)?.write_str("\n")?;
+        Ok(SValue::nil())
+    });
+    interp.define_native("display", Arity::between(1, 2), |interp, args| {
+        let text = args[0].to_text(false, &mut || interp.step())?;
+        output_port(interp, "display", &args, 1)?.write_str(&text)?;
+        Ok(SValue::nil())
+    });
+    interp.define_native("write", Arity::between(1, 2), |interp, args| {
+        let text = args[0].to_text(true, &mut || interp.step())?;
+        output_port(interp, "write", &args, 1)?.write_str(&text)?;
+        Ok(SValue::nil())
+    });
+    interp.define_native("flush-output-port", Arity::between(0, 1), |interp, args| {
+        output_port(interp, "flush-output-port", &args, 0)?.flush()?;
+        Ok(SValue::nil())
+    });
+
+    interp.define_fn("open-input-string", |s: Rc<str>| Port::open_input_string(&s).into_svalue());
+    interp.define_fn("open-output-string", || Port::open_output_string().into_svalue());
+    interp.define_fn("get-output-string", |x: SValue| {
+        x.downcast::<Port>().and_then(|p| p.output_string()).ok_or_else(|| {
+            format!("`get-output-string` expected a string output port; was given {:?}", x)
+        })
+    });
+    interp.define_native("with-output-to-string", Arity::exactly(1), |interp, args| {
+        let port = Rc::new(Port::open_output_string());
+        with_current_port(interp, port::wrap(port.clone()), args[0].clone(), true)?;
+        Ok(port.output_string().unwrap_or_default().into_svalue())
+    });
+    interp.define_native("call-with-output-string", Arity::exactly(1), |interp, args| {
+        let port = Rc::new(Port::open_output_string());
+        eval::apply(interp, args[0].clone(), vec![port::wrap(port.clone())])?;
+        Ok(port.output_string().unwrap_or_default().into_svalue())
+    });
+
+    for &name in &["close-port", "close-input-port", "close-output-port"] {
+        interp.define_native(name, Arity::exactly(1), move |_, args| {
+            port(name, &args[0])?.close()?;
+            Ok(SValue::nil())
+        });
+    }
+}
+
+// Opens a file as a port, if the capabilities allow it
+fn open_file(interp: &mut Interpreter, name: &str, args: &[SValue], write: bool)
+             -> Result<SValue, EvalError> {
+    let path: &str = native::convert(name, args, 0)?;
+    let file_error = |message| EvalError::Raise(ErrorObject::value(ErrorKind::File, message, vec![]));
+    let resolved = interp.capabilities().file_path(path, write).map_err(file_error)?;
+    let opened = if write { Port::open_output_file(&resolved) } else { Port::open_input_file(&resolved) };
+    match opened {
+        Ok(port) => Ok(interp.ports().open(port)),
+        Err(e) => Err(file_error(format!("`{}` cannot open {}: {}", name, path, e))),
+    }
+}
+
+// Calls f with a file's port, closing it afterwards
+fn call_with_file(interp: &mut Interpreter, name: &str, args: Vec<SValue>, write: bool)
+                  -> Result<SValue, EvalError> {
+    let port_value = open_file(interp, name, &args, write)?;
+    let result = eval::apply(interp, args[1].clone(), vec![port_value.clone()]);
+    port(name, &port_value)?.close()?;
+    result
+}
+
+// Calls thunk with port as the current input or output port
+fn with_current_port(interp: &mut Interpreter, port: SValue, thunk: SValue, write: bool)
+                     -> Result<SValue, EvalError> {
+    let current = if write { interp.ports().output.clone() } else { interp.ports().input.clone() };
+    parameter::parameterize(interp, vec![(current, port)], |interp| eval::apply(interp, thunk, vec![]))
+}
+
+fn with_file(interp: &mut Interpreter, name: &str, args: Vec<SValue>, write: bool)
+             -> Result<SValue, EvalError> {
+    let port_value = open_file(interp, name, &args, write)?;
+    let result = with_current_port(interp, port_value.clone(), args[1].clone(), write);
+    port(name, &port_value)?.close()?;
+    result
+}
+
+// The lists given as arguments from the first onwards, as to map
+fn lists(name: &str, args: &[SValue], first: usize) -> Result<Vec<Vec<SValue>>, EvalError> {
+    (first..args.len()).map(|i| native::convert(name, args, i)).collect()
+}
+
+// The elements at each position of lists, up to the end of the shortest,
+// as the arguments of a call
+fn rows(lists: &[Vec<SValue>]) -> Vec<Vec<SValue>> {
+    let n = lists.iter().map(Vec::len).min().unwrap_or(0);
+    (0..n).map(|i| lists.iter().map(|list| list[i].clone()).collect()).collect()
+}
+
+// Whether a and b are the same by compare if one was given, or else equal
+fn same(interp: &mut Interpreter, compare: Option<&SValue>, a: &SValue, b: &SValue) -> Result<bool, EvalError> {
+    match compare {
+        Some(compare) => Ok(eval::apply(interp, compare.clone(), vec![a.clone(), b.clone()])?.is_true()),
+        None => a.equal(b, &mut || interp.step()),
+    }
+}
+
+// The elements of list for which pred holds, and those for which it doesn't
+fn partition(interp: &mut Interpreter, name: &str, args: &[SValue]) -> Result<(Vec<SValue>, Vec<SValue>), EvalError> {
+    let list: Vec<SValue> = native::convert(name, args, 1)?;
+    let (mut kept, mut removed) = (vec![], vec![]);
+    for x in list {
+        if eval::apply(interp, args[0].clone(), vec![x.clone()])?.is_true() {
+            kept.push(x);
+        } else {
+            removed.push(x);
+        }
+    }
+    Ok((kept, removed))
+}
+
+// The procedures on lists of R7RS and SRFI 1. Each walks its lists in a
+// loop, so none is limited in length by the stack.
+fn install_lists(interp: &mut Interpreter) {
+    interp.define_native("map", Arity::at_least(2), |interp, args| {
+        let results = rows(&lists("map", &args, 1)?).into_iter()
+            .map(|row| eval::apply(interp, args[0].clone(), row))
+            .collect::<Result<_, _>>()?;
+        Ok(SValue::list(results))
+    });
+    interp.define_native("for-each", Arity::at_least(2), |interp, args| {
+        for row in rows(&lists("for-each", &args, 1)?) {
+            eval::apply(interp, args[0].clone(), row)?;
+        }
+        Ok(SValue::nil())
+    });
+    interp.define_native("apply", Arity::at_least(2), |interp, mut args| {
+        let last = args.pop().unwrap();
+        let f = args.remove(0);
+        match last.to_vec() {
+            Some(rest) => {
+                args.extend(rest);
+                eval::apply(interp, f, args)
+            },
+            None => Err(EvalError::Message(format!(
+                "`apply` expected a list as its last argument; was given {:?}", last))),
+        }
+    });
+    interp.define_native("filter", Arity::exactly(2), |interp, args| {
+        Ok(SValue::list(partition(interp, "filter", &args)?.0))
+    });
+    interp.define_native("remove", Arity::exactly(2), |interp, args| {
+        Ok(SValue::list(partition(interp, "remove", &args)?.1))
+    });
+    interp.define_native("partition", Arity::exactly(2), |interp, args| {
+        let (kept, removed) = partition(interp, "partition", &args)?;
+        Ok(SValue::values(vec![SValue::list(kept), SValue::list(removed)]))
+    });
+    // (f element accumulated), starting from the first element
+    interp.define_native("reduce", Arity::exactly(3), |interp, args| {
+        let mut list = native::convert::<Vec<SValue>>("reduce", &args, 2)?.into_iter();
+        let mut acc = match list.next() {
+            Some(first) => first,
+            None => return Ok(args[1].clone()),
+        };
+        for x in list {
+            acc = eval::apply(interp, args[0].clone(), vec![x, acc])?;
+        }
+        Ok(acc)
+    });
+    // (kons element... accumulated), from the left
+    interp.define_native("fold", Arity::at_least(3), |interp, args| {
+        let mut acc = args[1].clone();
+        for mut row in rows(&lists("fold", &args, 2)?) {
+            row.push(acc);
+            acc = eval::apply(interp, args[0].clone(), row)?;
+        }
+        Ok(acc)
+    });
+    // As in R6RS rather than SRFI 1: (combine accumulated element...)
+    interp.define_native("fold-left", Arity::at_least(3), |interp, args| {
+        let mut acc = args[1].clone();
+        for row in rows(&lists("fold-left", &args, 2)?) {
+            let mut call = vec![acc];
+            call.extend(row);
+            acc = eval::apply(interp, args[0].clone(), call)?;
+        }
+        Ok(acc)
+    });
+    // (kons element... accumulated), from the right
+    interp.define_native("fold-right", Arity::at_least(3), |interp, args| {
+        let mut acc = args[1].clone();
+        for mut row in rows(&lists("fold-right", &args, 2)?).into_iter().rev() {
+            row.push(acc);
+            acc = eval::apply(interp, args[0].clone(), row)?;
+        }
+        Ok(acc)
+    });
+    interp.define_native("append-map", Arity::at_least(2), |interp, args| {
+        let mut results = vec![];
+        for row in rows(&lists("append-map", &args, 1)?) {
+            let result = eval::apply(interp, args[0].clone(), row)?;
+            match result.to_vec() {
+                Some(items) => results.extend(items),
+                None => return Err(EvalError::Message(format!(
+                    "`append-map` expected its procedure to give lists; was given {:?}", result))),
+            }
+        }
+        Ok(SValue::list(results))
+    });
+    interp.define_native("list-index", Arity::at_least(2), |interp, args| {
+        for (i, row) in rows(&lists("list-index", &args, 1)?).into_iter().enumerate() {
+            if eval::apply(interp, args[0].clone(), row)?.is_true() {
+                return Ok(SValue::Number(i as f64));
+            }
+        }
+        Ok(SValue::Bool(false))
+    });
+    // The first true value pred gives
+    interp.define_native("any", Arity::at_least(2), |interp, args| {
+        for row in rows(&lists("any", &args, 1)?) {
+            let result = eval::apply(interp, args[0].clone(), row)?;
+            if result.is_true() {
+                return Ok(result);
+            }
+        }
+        Ok(SValue::Bool(false))
+    });
+    // The last value pred gives, if none is false
+    interp.define_native("every", Arity::at_least(2), |interp, args| {
+        let mut result = SValue::Bool(true);
+        for row in rows(&lists("every", &args, 1)?) {
+            result = eval::apply(interp, args[0].clone(), row)?;
+            if !result.is_true() {
+                break;
+            }
+        }
+        Ok(result)
+    });
+    interp.define_native("iota", Arity::between(1, 3), |interp, args| {
+        let count: usize = native::convert("iota", &args, 0)?;
+        let start: Option<f64> = native::convert("iota", &args, 1)?;
+        let step: Option<f64> = native::convert("iota", &args, 2)?;
+        interp.allocate(count)?;
+        let (start, step) = (start.unwrap_or(0.0), step.unwrap_or(1.0));
+        let items = (0..count).map(|i| interp.step().map(|_| SValue::Number(start + i as f64 * step)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SValue::list(items))
+    });
+    interp.define_native("delete", Arity::between(2, 3), |interp, args| {
+        let mut kept = vec![];
+        for x in native::convert::<Vec<SValue>>("delete", &args, 1)? {
+            if !same(interp, args.get(2), &args[0], &x)? {
+                kept.push(x);
+            }
+        }
+        Ok(SValue::list(kept))
+    });
+    interp.define_fn("last", |xs: Vec<SValue>| {
+        xs.last().cloned().ok_or("`last` expected a non-empty list; was given ()")
+    });
+    // Only the pairs are copied, including those of an improper list
+    interp.define_native("list-copy", Arity::exactly(1), |interp, args| {
+        let mut items = vec![];
+        let mut seen = HashSet::new();
+        let mut cur = args[0].clone();
+        while let SValue::Pair(p) = cur {
+            if !seen.insert(gc::address(&p)) {
+                return Err(EvalError::from("`list-copy` expected a list; was given a circular list"));
+            }
+            items.push(p.car());
+            cur = p.cdr();
+        }
+        interp.allocate(items.len())?;
+        Ok(SValue::list_with_tail(items, cur))
+    });
+    interp.define_native("assoc", Arity::between(2, 3), |interp, args| {
+        for entry in native::convert::<Vec<SValue>>("assoc", &args, 1)? {
+            let key = match entry {
+                SValue::Pair(ref p) => p.car(),
+                _ => return Err(EvalError::Message(format!(
+                    "`assoc` expected an association list; was given {:?}", args[1]))),
+            };
+            if same(interp, args.get(2), &args[0], &key)? {
+                return Ok(entry);
+            }
+        }
+        Ok(SValue::Bool(false))
+    });
+}
+
+// Whether less holds of a and b
+fn less(interp: &mut Interpreter, less: &SValue, a: &SValue, b: &SValue) -> Result<bool, EvalError> {
+    interp.step()?;
+    Ok(eval::apply(interp, less.clone(), vec![a.clone(), b.clone()])?.is_true())
+}
+
+// Merges the sorted a and b onto out, taking from a while what b has next
+// is not less, so that equal elements keep their order
+fn merge_into(interp: &mut Interpreter, by: &SValue, a: &[SValue], b: &[SValue], out: &mut Vec<SValue>)
+              -> Result<(), EvalError> {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if less(interp, by, &b[j], &a[i])? {
+            out.push(b[j].clone());
+            j += 1;
+        } else {
+            out.push(a[i].clone());
+            i += 1;
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    Ok(())
+}
+
+fn install_vectors(interp: &mut Interpreter) {
+    interp.define_fn("vector?", |x: SValue| matches!(x, SValue::Vector(_)));
+    interp.define_native("vector", Arity::at_least(0), |interp, args| {
+        interp.allocate(args.len())?;
+        Ok(SValue::Vector(Vector::new(args)))
+    });
+    // Without a fill, which #f also stands for, the elements are #f
+    interp.define_native("make-vector", Arity::between(1, 2), |interp, args| {
+        let k: usize = native::convert("make-vector", &args, 0)?;
+        let fill: Option<SValue> = native::convert("make-vector", &args, 1)?;
+        interp.allocate(k)?;
+        Ok(SValue::Vector(Vector::new(vec![fill.unwrap_or(SValue::Bool(false)); k])))
+    });
+    interp.define_fn("vector-length", |v: SValue| vector("vector-length", v).map(|v| v.len()));
+    interp.define_fn("vector-ref", |v: SValue, k: usize| {
+        let v = vector("vector-ref", v)?;
+        v.get(k).ok_or_else(|| format!("`vector-ref` was given index {} of a vector of length {}", k, v.len()))
+    });
+    interp.define_fn("vector-set!", |v: SValue, k: usize, x: SValue| {
+        let v = vector("vector-set!", v)?;
+        if !v.set(k, x) {
+            return Err(format!("`vector-set!` was given index {} of a vector of length {}", k, v.len()));
+        }
+        Ok(())
+    });
+    interp.define_native("vector->list", Arity::between(1, 3), |interp, args| {
+        let (v, start, end) = vector_args(interp, "vector->list", &args)?;
+        Ok(SValue::list(v.to_vec()[start..end].to_vec()))
+    });
+    interp.define_native("list->vector", Arity::exactly(1), |interp, args| {
+        let xs: Vec<SValue> = native::convert("list->vector", &args, 0)?;
+        interp.allocate(xs.len())?;
+        Ok(SValue::Vector(Vector::new(xs)))
+    });
+    interp.define_native("vector-copy", Arity::between(1, 3), |interp, args| {
+        let (v, start, end) = vector_args(interp, "vector-copy", &args)?;
+        Ok(SValue::Vector(Vector::new(v.to_vec()[start..end].to_vec())))
+    });
+    interp.define_fn("vector-fill!", |v: SValue, x: SValue, start: Option<usize>, end: Option<usize>| {
+        let v = vector("vector-fill!", v)?;
+        let (start, end) = vector_range("vector-fill!", &v, start, end)?;
+        for k in start..end {
+            v.set(k, x.clone());
+        }
+        Ok::<_, String>(())
+    });
+}
+
+// A stable merge sort, merging runs of doubling width. The comparison is
+// Scheme code that may fail or be inconsistent, so the standard library's
+// sort, which can't stop early and may panic on such a comparison, isn't used.
+fn merge_sort(interp: &mut Interpreter, by: &SValue, mut items: Vec<SValue>) -> Result<Vec<SValue>, EvalError> {
+    let n = items.len();
+    let mut width = 1;
+    while width < n {
+        let mut merged = Vec::with_capacity(n);
+        for start in (0..n).step_by(2 * width) {
+            let mid = cmp::min(start + width, n);
+            let end = cmp::min(start + 2 * width, n);
+            merge_into(interp, by, &items[start..mid], &items[mid..end], &mut merged)?;
+        }
+        items = merged;
+        width *= 2;
+    }
+    Ok(items)
+}
+
+// The sorting procedures of SRFI 132. Those making something new take the
+// comparison first, and those changing it in place the data; `sort` and
+// `merge` are kept as the shorter names.
+fn install_sorting(interp: &mut Interpreter) {
+    for &name in &["sort", "list-sort"] {
+        interp.define_native(name, Arity::exactly(2), move |interp, args| {
+            let list = native::convert(name, &args, 1)?;
+            Ok(SValue::list(merge_sort(interp, &args[0], list)?))
+        });
+    }
+    // Puts the elements in order in the pairs of the list given, as far as
+    // they still reach if the comparison changed it
+    interp.define_native("sort!", Arity::exactly(2), |interp, args| {
+        let list = native::convert("sort!", &args, 1)?;
+        let mut cur = args[1].clone();
+        for x in merge_sort(interp, &args[0], list)? {
+            cur = match cur {
+                SValue::Pair(ref p) => {
+                    p.set_car(x);
+                    p.cdr()
+                },
+                _ => break,
+            };
+        }
+        Ok(args[1].clone())
+    });
+    interp.define_native("merge", Arity::exactly(3), |interp, args| {
+        let a: Vec<SValue> = native::convert("merge", &args, 1)?;
+        let b: Vec<SValue> = native::convert("merge", &args, 2)?;
+        let mut merged = Vec::with_capacity(a.len() + b.len());
+        merge_into(interp, &args[0], &a, &b, &mut merged)?;
+        Ok(SValue::list(merged))
+    });
+    interp.define_native("sorted?", Arity::exactly(2), |interp, args| {
+        let list: Vec<SValue> = native::convert("sorted?", &args, 1)?;
+        for pair in list.windows(2) {
+            if less(interp, &args[0], &pair[1], &pair[0])? {
+                return Ok(SValue::Bool(false));
+            }
+        }
+        Ok(SValue::Bool(true))
+    });
+    // A new vector of the part of the one given from start to end
+    interp.define_native("vector-sort", Arity::between(2, 4), |interp, args| {
+        let v = vector("vector-sort", args[1].clone())?;
+        let (start, end) = vector_range("vector-sort", &v, native::convert("vector-sort", &args, 2)?,
+                                        native::convert("vector-sort", &args, 3)?)?;
+        let sorted = merge_sort(interp, &args[0], v.to_vec()[start..end].to_vec())?;
+        Ok(SValue::Vector(Vector::new(sorted)))
+    });
+    interp.define_native("vector-sort!", Arity::between(2, 4), |interp, args| {
+        let v = vector("vector-sort!", args[0].clone())?;
+        let (start, end) = vector_range("vector-sort!", &v, native::convert("vector-sort!", &args, 2)?,
+                                        native::convert("vector-sort!", &args, 3)?)?;
+        let sorted = merge_sort(interp, &args[1], v.to_vec()[start..end].to_vec())?;
+        for (k, x) in (start..end).zip(sorted) {
+            v.set(k, x);
+        }
+        Ok(SValue::nil())
+    });
+    // The index of an element of a sorted vector for which (cmp element
+    // value) is zero, or #f; cmp gives a negative number for an element
+    // before value and a positive one for an element after it
+    interp.define_native("vector-binary-search", Arity::between(3, 5), |interp, args| {
+        let v = vector("vector-binary-search", args[0].clone())?;
+        let (mut start, mut end) = vector_range("vector-binary-search", &v,
+                                                native::convert("vector-binary-search", &args, 3)?,
+                                                native::convert("vector-binary-search", &args, 4)?)?;
+        while start < end {
+            let mid = start + (end - start) / 2;
+            let element = v.get(mid).unwrap_or(SValue::Nil);
+            let order = eval::apply(interp, args[2].clone(), vec![element, args[1].clone()])?;
+            match order {
+                SValue::Number(n) if n < 0.0 => start = mid + 1,
+                SValue::Number(n) if n > 0.0 => end = mid,
+                SValue::Number(_) => return Ok(SValue::Number(mid as f64)),
+                _ => return Err(EvalError::Message(format!(
+                    "`vector-binary-search` expected a number from the comparison; was given {:?}", order))),
+            }
+        }
+        Ok(SValue::Bool(false))
+    });
+}
+
+// What match expands to calls on the value being matched
+fn install_match(interp: &mut Interpreter) {
+    // The elements before the last k of a proper list, reversed, and the
+    // last k, or #f if it is shorter
+    interp.define_fn("%match-split", |x: SValue, k: usize| {
+        match x.to_vec() {
+            Some(mut items) if items.len() >= k => {
+                let tail = items.split_off(items.len() - k);
+                items.reverse();
+                SValue::cons(SValue::list(items), SValue::list(tail))
+            },
+            _ => SValue::Bool(false),
+        }
+    });
+    interp.define_fn("%match-record", |rtype: Rc<RecordType>, x: SValue| match x {
+        SValue::Record(ref r) if r.is(&rtype) => SValue::list(r.entries().into_iter().map(|(_, v)| v).collect()),
+        _ => SValue::Bool(false),
+    });
+    interp.define_fn("%match-failed", |x: SValue| {
+        Err::<SValue, _>(format!("`match` found no clause matching {:?}", x))
+    });
+}
+
+fn procedure<F>(name: &str, arity: Arity, f: F) -> SValue
+    where F: Fn(&mut Interpreter, Vec<SValue>) -> Result<SValue, EvalError> + 'static {
+    SValue::Native(Rc::new(Native::new(name, arity, f)))
+}
+
+fn field_index(name: &str, rtype: &RecordType, field: &str) -> Result<usize, String> {
+    rtype.field_index(field).ok_or_else(|| {
+        format!("`{}` names {}, which is not a field of {}", name, field, rtype.name)
+    })
+}
+
+// What define-record-type expands to: a record type, then procedures for
+// it, each named for the errors it gives
+fn install_records(interp: &mut Interpreter) {
+    interp.define_fn("%make-record-type", |name: Rc<str>, fields: Vec<Rc<str>>| {
+        if let Some((_, field)) = fields.iter().enumerate().find(|&(i, f)| fields[..i].contains(f)) {
+            return Err(format!("Record type {} has two fields named {}", name, field));
+        }
+        let type_name = format!("record-type {}", name);
+        Ok(SValue::foreign(&type_name, RecordType { name, fields }))
+    });
+    interp.define_fn("%record-constructor", |rtype: Rc<RecordType>, name: Rc<str>, fields: Vec<Rc<str>>| {
+        let indices = fields.iter()
+            .map(|field| field_index(&name, &rtype, field))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok::<_, String>(procedure(&name, Arity::exactly(indices.len()), move |_, args| {
+            let mut values = vec![SValue::Nil; rtype.fields.len()];
+            for (&i, arg) in indices.iter().zip(args) {
+                values[i] = arg;
+            }
+            Ok(SValue::Record(Record::new(&rtype, values)))
+        }))
+    });
+    interp.define_fn("%record-predicate", |rtype: Rc<RecordType>, name: Rc<str>| {
+        procedure(&name, Arity::exactly(1), move |_, args| {
+            Ok(SValue::Bool(matches!(args[0], SValue::Record(ref r) if r.is(&rtype))))
+        })
+    });
+    // The record an accessor or modifier was given, if it has the right type
+    fn record<'a>(name: &str, rtype: &Rc<RecordType>, x: &'a SValue) -> Result<&'a Record, EvalError> {
+        match *x {
+            SValue::Record(ref r) if r.is(rtype) => Ok(r),
+            _ => Err(EvalError::Message(format!(
+                "`{}` expected a record of type {}; was given {:?}", name, rtype.name, x))),
+        }
+    }
+    interp.define_fn("%record-accessor", |rtype: Rc<RecordType>, field: Rc<str>, name: Rc<str>| {
+        let i = field_index(&name, &rtype, &field)?;
+        Ok::<_, String>(procedure(&name.clone(), Arity::exactly(1), move |_, args| {
+            Ok(record(&name, &rtype, &args[0])?.get(i))
+        }))
+    });
+    interp.define_fn("%record-modifier", |rtype: Rc<RecordType>, field: Rc<str>, name: Rc<str>| {
+        let i = field_index(&name, &rtype, &field)?;
+        Ok::<_, String>(procedure(&name.clone(), Arity::exactly(2), move |_, args| {
+            record(&name, &rtype, &args[0])?.set(i, args[1].clone());
+            Ok(SValue::Nil)
+        }))
+    });
+}
+
+fn install_dynamic(interp: &mut Interpreter) {
+    interp.define_native("make-parameter", Arity::between(1, 2), |interp, mut args| {
+        let converter = args.get(1).cloned();
+        let value = match converter {
+            Some(ref converter) => eval::apply(interp, converter.clone(), vec![args.remove(0)])?,
+            None => args.remove(0),
+        };
+        Ok(SValue::Parameter(Parameter::new(value, converter)))
+    });
+    // What parameterize expands to: the parameters, their new values, and
+    // the body as a thunk. Every value is converted before any is set.
+    interp.define_native("%parameterize", Arity::exactly(3), |interp, args| {
+        let (parameters, values) = match (args[0].to_vec(), args[1].to_vec()) {
+            (Some(parameters), Some(values)) => (parameters, values),
+            _ => return Err(EvalError::from("`%parameterize` expected two lists")),
+        };
+        let mut bindings = vec![];
+        for (parameter, value) in parameters.into_iter().zip(values) {
+            let parameter = match parameter {
+                SValue::Parameter(p) => p,
+                v => return Err(EvalError::Message(format!(
+                    "`parameterize` expected a parameter; was given {:?}", v))),
+            };
+            let value = parameter.convert(interp, value)?;
+            bindings.push((parameter, value));
+        }
+        let body = args[2].clone();
+        parameter::parameterize(interp, bindings, |interp| eval::apply(interp, body, vec![]))
+    });
+    // Without continuations, the thunk is only ever entered once, and left
+    // by returning or by an error. after runs either way, unless evaluation
+    // was stopped outright, which runs no more Scheme code.
+    interp.define_native("dynamic-wind", Arity::exactly(3), |interp, args| {
+        eval::apply(interp, args[0].clone(), vec![])?;
+        let result = eval::apply(interp, args[1].clone(), vec![]);
+        match result {
+            Err(EvalError::Limit(_)) | Err(EvalError::Interrupted) => result,
+            _ => {
+                eval::apply(interp, args[2].clone(), vec![])?;
+                result
+            },
+        }
+    });
+}
+
+// What delay and delay-force expand to take the expression as a thunk
+fn install_lazy(interp: &mut Interpreter) {
+    interp.define_fn("%delay", Promise::delayed);
+    interp.define_fn("%delay-force", Promise::lazy);
+    interp.define_fn("make-promise", |v: SValue| match v {
+        SValue::Promise(_) => v,
+        v => Promise::done(v),
+    });
+    interp.define_fn("promise?", |v: SValue| matches!(v, SValue::Promise(_)));
+    interp.define_native("force", Arity::exactly(1), |interp, args| promise::force(interp, &args[0]));
+}
+
+// The pair a stream forces to, or None for the empty stream
+fn stream_pair(interp: &mut Interpreter, name: &str, s: &SValue) -> Result<Option<Rc<Pair>>, EvalError> {
+    match promise::force(interp, s)? {
+        SValue::Pair(p) => Ok(Some(p)),
+        SValue::Nil => Ok(None),
+        _ => Err(EvalError::Message(format!("`{}` expected a stream; was given {:?}", name, s))),
+    }
+}
+
+fn stream_cons(car: SValue, cdr: SValue) -> SValue {
+    Promise::stream_done(SValue::cons(car, cdr))
+}
+
+// A stream whose pairs are made by next when first forced
+fn lazy_stream<F>(name: &str, next: F) -> SValue
+    where F: Fn(&mut Interpreter) -> Result<SValue, EvalError> + 'static {
+    Promise::stream_lazy(procedure(name, Arity::exactly(0), move |interp, _| next(interp)))
+}
+
+fn stream_map(f: SValue, streams: Vec<SValue>) -> SValue {
+    lazy_stream("stream-map", move |interp| {
+        let mut cars = vec![];
+        let mut cdrs = vec![];
+        for s in &streams {
+            match stream_pair(interp, "stream-map", s)? {
+                Some(p) => {
+                    cars.push(p.car());
+                    cdrs.push(p.cdr());
+                },
+                None => return Ok(Promise::done(SValue::Nil)),
+            }
+        }
+        let g = f.clone();
+        let car = Promise::delayed(procedure("stream-map", Arity::exactly(0), move |interp, _| {
+            let args = cars.iter().map(|car| promise::force(interp, car)).collect::<Result<_, _>>()?;
+            eval::apply(interp, g.clone(), args)
+        }));
+        Ok(stream_cons(car, stream_map(f.clone(), cdrs)))
+    })
+}
+
+// Elements are tested as the stream is forced, skipping any that fail in
+// a loop. The thunk lets go of the stream when it starts, so that those it
+// skips are freed as it goes, rather than all at once through a chain of
+// drops as deep as the run of them.
+fn stream_filter(pred: SValue, s: SValue) -> SValue {
+    let s = RefCell::new(Some(s));
+    lazy_stream("stream-filter", move |interp| {
+        let mut s = match s.borrow_mut().take() {
+            Some(s) => s,
+            None => return Err(EvalError::from("`stream-filter` was forced again while being forced")),
+        };
+        while let Some(p) = stream_pair(interp, "stream-filter", &s)? {
+            let x = promise::force(interp, &p.car())?;
+            if eval::apply(interp, pred.clone(), vec![x.clone()])?.is_true() {
+                return Ok(stream_cons(Promise::done(x), stream_filter(pred.clone(), p.cdr())));
+            }
+            s = p.cdr();
+        }
+        Ok(Promise::done(SValue::Nil))
+    })
+}
+
+fn stream_take(n: usize, s: SValue) -> SValue {
+    lazy_stream("stream-take", move |interp| {
+        if n == 0 {
+            return Ok(Promise::done(SValue::Nil));
+        }
+        Ok(match stream_pair(interp, "stream-take", &s)? {
+            Some(p) => stream_cons(p.car(), stream_take(n - 1, p.cdr())),
+            None => Promise::done(SValue::Nil),
+        })
+    })
+}
+
+// Streams in the manner of SRFI 41: promises of either the empty list or
+// a pair of a promise of the first element and the stream of the rest
+fn install_streams(interp: &mut Interpreter) {
+    interp.define_global("stream-null", Promise::stream_done(SValue::Nil));
+    // What stream-cons expands to, with its expressions as thunks
+    interp.define_fn("%stream-cons", |car: SValue, cdr: SValue| {
+        stream_cons(Promise::delayed(car), Promise::stream_lazy(cdr))
+    });
+    interp.define_fn("stream?", |v: SValue| matches!(v, SValue::Promise(ref p) if p.is_stream()));
+    interp.define_native("stream-null?", Arity::exactly(1), |interp, args| {
+        Ok(SValue::Bool(stream_pair(interp, "stream-null?", &args[0])?.is_none()))
+    });
+    interp.define_native("stream-pair?", Arity::exactly(1), |interp, args| {
+        Ok(SValue::Bool(stream_pair(interp, "stream-pair?", &args[0])?.is_some()))
+    });
+    interp.define_native("stream-car", Arity::exactly(1), |interp, args| {
+        match stream_pair(interp, "stream-car", &args[0])? {
+            Some(p) => promise::force(interp, &p.car()),
+            None => Err(EvalError::from("`stream-car` expected a stream pair; was given the empty stream")),
+        }
+    });
+    interp.define_native("stream-cdr", Arity::exactly(1), |interp, args| {
+        match stream_pair(interp, "stream-cdr", &args[0])? {
+            Some(p) => Ok(p.cdr()),
+            None => Err(EvalError::from("`stream-cdr` expected a stream pair; was given the empty stream")),
+        }
+    });
+    interp.define_native("stream-map", Arity::at_least(2), |_, mut args| {
+        let f = args.remove(0);
+        Ok(stream_map(f, args))
+    });
+    interp.define_native("stream-filter", Arity::exactly(2), |_, args| {
+        Ok(stream_filter(args[0].clone(), args[1].clone()))
+    });
+    interp.define_native("stream-take", Arity::exactly(2), |_, args| {
+        let n: usize = native::convert("stream-take", &args, 0)?;
+        Ok(stream_take(n, args[1].clone()))
+    });
+    interp.define_fn("list->stream", |xs: Vec<SValue>| {
+        xs.into_iter().rev().fold(Promise::stream_done(SValue::Nil), |s, x| stream_cons(Promise::done(x), s))
+    });
+    // At most n elements of the stream, given first as in SRFI 41, or
+    // without n all of it, which must end
+    interp.define_native("stream->list", Arity::between(1, 2), |interp, args| {
+        let (mut n, mut s) = match args.len() {
+            2 => (Some(native::convert::<usize>("stream->list", &args, 0)?), args[1].clone()),
+            _ => (None, args[0].clone()),
+        };
+        let mut items = vec![];
+        while n != Some(0) {
+            match stream_pair(interp, "stream->list", &s)? {
+                Some(p) => {
+                    items.push(promise::force(interp, &p.car())?);
+                    s = p.cdr();
+                },
+                None => break,
+            }
+            n = n.map(|n| n - 1);
+        }
+        Ok(SValue::list(items))
+    });
+}
+
+fn install_files(interp: &mut Interpreter) {
+    interp.define_native("load", Arity::between(1, 2), |interp, args| {
+        let path: &str = native::convert("load", &args, 0)?;
+        let env = match args.get(1) {
+            Some(env) => (*environment("load", env)?).clone(),
+            None => interp.globals(),
+        };
+        let file = interp.open_source("load", path)?;
+        interp.load(&file, &env)
+    });
+    interp.define_native("open-input-file", Arity::exactly(1), |interp, args| {
+        open_file(interp, "open-input-file", &args, false)
+    });
+    interp.define_native("open-output-file", Arity::exactly(1), |interp, args| {
+        open_file(interp, "open-output-file", &args, true)
+    });
+    interp.define_native("call-with-input-file", Arity::exactly(2), |interp, args| {
+        call_with_file(interp, "call-with-input-file", args, false)
+    });
+    interp.define_native("call-with-output-file", Arity::exactly(2), |interp, args| {
+        call_with_file(interp, "call-with-output-file", args, true)
+    });
+    interp.define_native("with-input-from-file", Arity::exactly(2), |interp, args| {
+        with_file(interp, "with-input-from-file", args, false)
+    });
+    interp.define_native("with-output-to-file", Arity::exactly(2), |interp, args| {
+        with_file(interp, "with-output-to-file", args, true)
+    });
+}
+
+fn install_process(interp: &mut Interpreter) {
+    interp.define_native("exit", Arity::between(0, 1), |interp, args| {
+        let code = match args.first() {
+            None | Some(&SValue::Bool(true)) => 0,
+            Some(&SValue::Bool(false)) => 1,
+            Some(&SValue::Number(f)) => f as i32,
+            Some(_) => return Err(EvalError::from("`exit` expected a number or boolean")),
+        };
+        // The host may end the process without dropping the interpreter
+        interp.ports().flush_all();
+        Err(EvalError::Exit(code))
+    });
+    interp.define_fn("command-line", Vec::<SValue>::new);
+}
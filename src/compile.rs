@@ -0,0 +1,171 @@
+use std::rc::Rc;
+use analyze::{Lambda, Node};
+use eval::{Params, SValue};
+
+/// An instruction for the stack machine in `vm`. Variables are addressed
+/// as `analyze` resolved them.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Const(usize),            // push consts[i]
+    Local(usize, usize),     // push the value in a slot
+    SetLocal(usize, usize),  // pop into a slot, pushing ()
+    Free(usize, usize),      // push the value of names[i], `depth` frames out
+    SetFree(usize, usize),
+    Define(usize),           // pop into a top-level binding of names[i], pushing ()
+    Closure(usize),          // push a procedure made from templates[i]
+    Jump(usize),
+    JumpUnless(usize),       // pop, and jump if false
+    JumpUnlessIs(usize, usize), // pop, and jump unless it is consts[i]
+    Pop,
+    Call(usize),             // apply the procedure below this many arguments
+    TailCall(usize),         // as Call, in place of the current procedure
+    Return,
+}
+
+/// Compiled code: a lambda, or a top-level expression with no parameters.
+pub struct Template {
+    pub params: Params,
+    pub frame: Vec<Rc<str>>, // the names of the slots: params, then internal defines
+    pub code: Vec<Op>,
+    pub consts: Vec<SValue>,
+    pub names: Vec<Rc<str>>,
+    pub templates: Vec<Rc<Template>>, // of the lambdas within
+}
+
+/// Compiles an analyzed top-level expression, to be run in some scope.
+pub fn compile(node: &Node) -> Template {
+    let params = Params { names: vec![], rest: None };
+    Compiler::new(params, vec![]).finish(node)
+}
+
+fn compile_lambda(lambda: &Lambda) -> Template {
+    Compiler::new(lambda.params.clone(), lambda.frame.clone()).finish(&lambda.body)
+}
+
+struct Compiler {
+    template: Template,
+}
+
+impl Compiler {
+    fn new(params: Params, frame: Vec<Rc<str>>) -> Compiler {
+        Compiler {
+            template: Template {
+                params,
+                frame,
+                code: vec![],
+                consts: vec![],
+                names: vec![],
+                templates: vec![],
+            },
+        }
+    }
+
+    fn finish(mut self, body: &Node) -> Template {
+        self.node(body, true);
+        self.emit(Op::Return);
+        self.template
+    }
+
+    fn emit(&mut self, op: Op) {
+        self.template.code.push(op);
+    }
+
+    // The position of the next instruction
+    fn here(&self) -> usize {
+        self.template.code.len()
+    }
+
+    fn constant(&mut self, v: &SValue) -> usize {
+        self.template.consts.push(v.clone());
+        self.template.consts.len() - 1
+    }
+
+    fn name(&mut self, s: &Rc<str>) -> usize {
+        match self.template.names.iter().position(|n| n == s) {
+            Some(i) => i,
+            None => {
+                self.template.names.push(s.clone());
+                self.template.names.len() - 1
+            },
+        }
+    }
+
+    fn node(&mut self, node: &Node, tail: bool) {
+        match *node {
+            Node::Const(ref v) => {
+                let i = self.constant(v);
+                self.emit(Op::Const(i));
+            },
+            Node::Local(depth, i) => self.emit(Op::Local(depth, i)),
+            Node::Free(depth, ref s) => {
+                let i = self.name(s);
+                self.emit(Op::Free(depth, i));
+            },
+            Node::SetLocal(depth, i, ref value) => {
+                self.node(value, false);
+                self.emit(Op::SetLocal(depth, i));
+            },
+            Node::SetFree(depth, ref s, ref value) => {
+                self.node(value, false);
+                let i = self.name(s);
+                self.emit(Op::SetFree(depth, i));
+            },
+            Node::Define(ref s, ref value) => {
+                self.node(value, false);
+                let i = self.name(s);
+                self.emit(Op::Define(i));
+            },
+            Node::Lambda(ref lambda) => {
+                self.template.templates.push(Rc::new(compile_lambda(lambda)));
+                let i = self.template.templates.len() - 1;
+                self.emit(Op::Closure(i));
+            },
+            Node::If(ref test, ref consequent, ref alternative) => {
+                self.node(test, false);
+                let jump_unless = self.here();
+                self.emit(Op::JumpUnless(0));
+                self.node(consequent, tail);
+                let jump = self.here();
+                self.emit(Op::Jump(0));
+                self.template.code[jump_unless] = Op::JumpUnless(self.here());
+                self.node(alternative, tail);
+                self.template.code[jump] = Op::Jump(self.here());
+            },
+            Node::Begin(ref nodes) => {
+                for (i, node) in nodes.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(Op::Pop);
+                    }
+                    self.node(node, tail && i == nodes.len() - 1);
+                }
+            },
+            Node::Call(ref f, ref args) => {
+                self.node(f, false);
+                for arg in args {
+                    self.node(arg, false);
+                }
+                self.emit(if tail { Op::TailCall(args.len()) } else { Op::Call(args.len()) });
+            },
+            // The value, unless a primitive it used has been redefined
+            Node::Folded(ref fold) => {
+                let mut guards = vec![];
+                for (depth, name, primitive) in &fold.guards {
+                    let name = self.name(name);
+                    self.emit(Op::Free(*depth, name));
+                    let primitive = self.constant(primitive);
+                    guards.push((self.here(), primitive));
+                    self.emit(Op::JumpUnlessIs(primitive, 0));
+                }
+                let i = self.constant(&fold.value);
+                self.emit(Op::Const(i));
+                let jump = self.here();
+                self.emit(Op::Jump(0));
+                for (guard, primitive) in guards {
+                    self.template.code[guard] = Op::JumpUnlessIs(primitive, self.here());
+                }
+                self.node(&fold.call, tail);
+                self.template.code[jump] = Op::Jump(self.here());
+            },
+        }
+    }
+}
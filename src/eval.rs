@@ -1,357 +1,1090 @@
 use std::fmt;
-use std::collections::linked_list::LinkedList;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::iter::FromIterator;
+use gc;
+use gc::Trace;
 use parse::Sexp;
-use parse;
 use util;
+use interpreter::Interpreter;
+use native::Native;
+use foreign::Foreign;
+use parameter::Parameter;
+use promise::Promise;
+use record::Record;
+use vector::Vector;
+use compile::Template;
+use vm;
 
-#[derive(PartialEq, Clone)]
+#[derive(Clone)]
 pub enum SValue {
-    List(LinkedList<SValue>),
-    Symbol(String),
-    String(String),
+    Nil, // the empty list
+    Pair(Rc<Pair>),
+    Symbol(Rc<str>),
+    String(Rc<str>),
     Number(f64),
     Bool(bool),
-    Lambda(SymTable, LinkedList<String>, Sexp),
-    //Closure(&'a mut SymTable<'a>, Vec<String>, Sexp), // an environment a list of params and a return expression
+    Char(char),
+    Eof, // what reading returns at the end of input
+    Lambda(Rc<Closure>),
+    Native(Rc<Native>),
+    Foreign(Rc<Foreign>), // a value belonging to the host program
+    Record(Rc<Record>),
+    Vector(Rc<Vector>),
+    Promise(Rc<Promise>),
+    Parameter(Rc<Parameter>),
+    // The results of `values` when there are other than one; a single
+    // result is the value itself, so that returning it allocates nothing
+    Values(Rc<[SValue]>),
+    // What compiled code finds in the slot of an internal define that hasn't
+    // run yet; reading it is an error, so Scheme code never holds one
+    Unbound,
+}
+
+/// A mutable cons cell, shared by every value that refers to it.
+pub struct Pair {
+    car: RefCell<SValue>,
+    cdr: RefCell<SValue>,
+}
+
+impl Pair {
+    pub fn car(&self) -> SValue { self.car.borrow().clone() }
+    pub fn cdr(&self) -> SValue { self.cdr.borrow().clone() }
+    pub fn set_car(&self, v: SValue) { *self.car.borrow_mut() = v; }
+    pub fn set_cdr(&self, v: SValue) { *self.cdr.borrow_mut() = v; }
+}
+
+// The pair in cell if nothing else holds it, taken out so that dropping it
+// frees nothing further
+fn take_unshared(cell: &mut RefCell<SValue>) -> Option<Rc<Pair>> {
+    match *cell.get_mut() {
+        SValue::Pair(ref p) if Rc::strong_count(p) == 1 => {},
+        _ => return None,
+    }
+    match cell.replace(SValue::Nil) {
+        SValue::Pair(p) => Some(p),
+        _ => None,
+    }
+}
+
+// Pairs held only by the one being dropped are freed here from a work
+// stack, rather than each by the last, so that a long list can't overflow
+// the native stack
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut pending: Vec<Rc<Pair>> = take_unshared(&mut self.car).into_iter()
+            .chain(take_unshared(&mut self.cdr))
+            .collect();
+        while let Some(p) = pending.pop() {
+            if let Ok(mut pair) = Rc::try_unwrap(p) {
+                pending.extend(take_unshared(&mut pair.car));
+                pending.extend(take_unshared(&mut pair.cdr));
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Params {
+    pub names: Vec<Rc<str>>,
+    pub rest: Option<Rc<str>>, // bound to a list of any further arguments
+}
+
+/// A procedure written in Scheme, with the scope it was made in.
+pub struct Closure {
+    pub env: Env,
+    pub body: Body,
+}
+
+pub enum Body {
+    Source(Params, Sexp), // run by the tree-walker
+    Compiled(Rc<Template>),
+}
+
+impl Closure {
+    pub fn new(env: &Env, body: Body) -> Rc<Closure> {
+        let closure = Rc::new(Closure { env: env.clone(), body });
+        gc::track(&closure);
+        closure
+    }
+}
+
+/// Called by natives as they go over a large value, so that the limits and
+/// interrupts checked between steps can stop them; `Interpreter::step` in
+/// a closure, as a rule.
+pub(crate) type Check<'a> = &'a mut dyn FnMut() -> Result<(), EvalError>;
+
+impl PartialEq for SValue {
+    fn eq(&self, other: &SValue) -> bool {
+        self.equal(other, &mut || Ok(())).unwrap_or(false)
+    }
 }
 
 impl SValue {
-    pub fn nil() -> SValue { SValue::List(LinkedList::new()) }
-    fn r5rs_write(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &SValue::String(ref s) => write!(f, "{:?}", s),
-            x => x.r5rs_display(f)
+    /// Whether the values are `equal?`, calling check for each pair of
+    /// parts compared.
+    pub(crate) fn equal(&self, other: &SValue, check: Check) -> Result<bool, EvalError> {
+        // Pairs and vectors are compared from a work stack rather than by
+        // recursion, so that deep nesting can't overflow the native stack.
+        // Two met again are already being compared, and are taken as equal,
+        // so that circular structures are compared in finite time.
+        let mut pending = vec![(self.clone(), other.clone())];
+        let mut seen = HashSet::new();
+        while let Some((a, b)) = pending.pop() {
+            check()?;
+            match (a, b) {
+                (SValue::Pair(a), SValue::Pair(b)) => {
+                    if Rc::ptr_eq(&a, &b) || !seen.insert((gc::address(&a), gc::address(&b))) {
+                        continue;
+                    }
+                    pending.push((a.cdr(), b.cdr()));
+                    pending.push((a.car(), b.car()));
+                },
+                (SValue::Vector(a), SValue::Vector(b)) => {
+                    if Rc::ptr_eq(&a, &b) || !seen.insert((gc::address(&a), gc::address(&b))) {
+                        continue;
+                    }
+                    if a.len() != b.len() {
+                        return Ok(false);
+                    }
+                    pending.extend(a.to_vec().into_iter().zip(b.to_vec()).rev());
+                },
+                (a, b) => if !a.eq_atom(&b) {
+                    return Ok(false);
+                },
+            }
         }
+        Ok(true)
     }
-    fn r5rs_display(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &SValue::List(ref ll) => {
-                let mut s = String::new();
-                s.push('(');
-                for x in ll {
-                    s.push_str(format!("{} ", x).as_ref());
+
+    // Equality of everything but pairs and vectors, which `equal` follows itself
+    fn eq_atom(&self, other: &SValue) -> bool {
+        match (self, other) {
+            (SValue::Nil, SValue::Nil) => true,
+            (SValue::Symbol(a), SValue::Symbol(b)) => a == b,
+            (SValue::String(a), SValue::String(b)) => a == b,
+            (SValue::Number(a), SValue::Number(b)) => a == b,
+            (SValue::Bool(a), SValue::Bool(b)) => a == b,
+            (SValue::Char(a), SValue::Char(b)) => a == b,
+            (SValue::Eof, SValue::Eof) => true,
+            // Scopes may contain the lambda itself, so compare by identity
+            (SValue::Lambda(a), SValue::Lambda(b)) => Rc::ptr_eq(a, b),
+            (SValue::Native(a), SValue::Native(b)) => Rc::ptr_eq(a, b),
+            (SValue::Foreign(a), SValue::Foreign(b)) => Rc::ptr_eq(a, b),
+            (SValue::Record(a), SValue::Record(b)) => Rc::ptr_eq(a, b),
+            (SValue::Promise(a), SValue::Promise(b)) => Rc::ptr_eq(a, b),
+            (SValue::Parameter(a), SValue::Parameter(b)) => Rc::ptr_eq(a, b),
+            (SValue::Values(a), SValue::Values(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl SValue {
+    pub fn nil() -> SValue { SValue::Nil }
+
+    pub fn symbol(s: &str) -> SValue { SValue::Symbol(Rc::from(s)) }
+
+    pub fn string(s: &str) -> SValue { SValue::String(Rc::from(s)) }
+
+    pub fn cons(car: SValue, cdr: SValue) -> SValue {
+        let pair = Rc::new(Pair { car: RefCell::new(car), cdr: RefCell::new(cdr) });
+        gc::track(&pair);
+        SValue::Pair(pair)
+    }
+
+    pub fn list(items: Vec<SValue>) -> SValue {
+        SValue::list_with_tail(items, SValue::Nil)
+    }
+
+    pub fn list_with_tail(items: Vec<SValue>, tail: SValue) -> SValue {
+        items.into_iter().rev().fold(tail, |cdr, car| SValue::cons(car, cdr))
+    }
+
+    /// The elements of a proper list, or None for anything else, including
+    /// circular lists.
+    pub fn to_vec(&self) -> Option<Vec<SValue>> {
+        self.list_items(&mut || Ok(())).unwrap_or(None)
+    }
+
+    /// As `to_vec`, calling check for each element.
+    pub(crate) fn list_items(&self, check: Check) -> Result<Option<Vec<SValue>>, EvalError> {
+        let mut items = vec![];
+        let mut cur = self.clone();
+        let mut slow = self.clone(); // advances at half speed to detect cycles
+        loop {
+            check()?;
+            match cur {
+                SValue::Nil => return Ok(Some(items)),
+                SValue::Pair(p) => {
+                    items.push(p.car());
+                    cur = p.cdr();
+                },
+                _ => return Ok(None),
+            }
+            if items.len() % 2 == 0 {
+                if let SValue::Pair(p) = slow {
+                    slow = p.cdr();
+                }
+                if let (SValue::Pair(a), SValue::Pair(b)) = (&cur, &slow) {
+                    if Rc::ptr_eq(a, b) {
+                        return Ok(None);
+                    }
                 }
-                s.pop();
-                s.push(')');
-                write!(f, "{}", s)
+            }
+        }
+    }
+
+    /// The value of returning `results`: the result itself when there is
+    /// exactly one.
+    pub fn values(mut results: Vec<SValue>) -> SValue {
+        if results.len() == 1 {
+            results.pop().unwrap()
+        } else {
+            SValue::Values(results.into())
+        }
+    }
+
+    /// The results a value stands for, the reverse of `values`.
+    pub fn into_values(self) -> Vec<SValue> {
+        match self {
+            SValue::Values(results) => results.to_vec(),
+            v => vec![v],
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        *self != SValue::Bool(false)
+    }
+
+    /// What `write` prints for the value, or `display` if write is false,
+    /// calling check for each part printed.
+    pub(crate) fn to_text(&self, write: bool, check: Check) -> Result<String, EvalError> {
+        let mut text = String::new();
+        let mut printer = Printer::new(write, check);
+        match printer.print(self, &mut text) {
+            Ok(()) => Ok(text),
+            Err(_) => Err(printer.stopped.take().expect("only a check stops printing to a string")),
+        }
+    }
+}
+
+impl FromIterator<SValue> for SValue {
+    fn from_iter<I: IntoIterator<Item = SValue>>(iter: I) -> SValue {
+        SValue::list(iter.into_iter().collect())
+    }
+}
+
+// What is left to do in walking or printing a value; both work from a
+// stack of these rather than by recursion, so that deep nesting can't
+// overflow the native stack
+enum Visit {
+    Enter(SValue),
+    Leave(*const ()),
+}
+
+enum Print {
+    Value(SValue),
+    Tail(SValue), // the rest of a list whose first items are printed
+    Text(&'static str),
+    Field(Rc<str>),
+}
+
+struct Printer<'a> {
+    write: bool, // as by `write` rather than `display`
+    labels: HashMap<*const (), Option<usize>>, // numbered when first printed
+    next_label: usize,
+    check: Check<'a>,
+    stopped: Option<EvalError>, // what check failed with, if it ended the printing
+}
+
+impl<'a> Printer<'a> {
+    fn new(write: bool, check: Check<'a>) -> Printer<'a> {
+        Printer { write, labels: HashMap::new(), next_label: 0, check, stopped: None }
+    }
+
+    // Calls check, keeping what it fails with
+    fn check(&mut self) -> fmt::Result {
+        (self.check)().map_err(|e| {
+            self.stopped = Some(e);
+            fmt::Error
+        })
+    }
+
+    // Records in labels the pairs, records and vectors that a chain of
+    // references leads back to, so that printing can mark them with datum
+    // labels instead of looping
+    fn find_cycles(&mut self, v: &SValue) -> fmt::Result {
+        let mut path = HashSet::new();
+        let mut done = HashSet::new();
+        let mut pending = vec![Visit::Enter(v.clone())];
+        while let Some(visit) = pending.pop() {
+            self.check()?;
+            let v = match visit {
+                Visit::Enter(v) => v,
+                Visit::Leave(addr) => {
+                    path.remove(&addr);
+                    done.insert(addr);
+                    continue;
+                },
+            };
+            let (addr, parts) = match v {
+                SValue::Pair(ref p) => (gc::address(p), vec![p.car(), p.cdr()]),
+                SValue::Record(ref r) => (gc::address(r), r.entries().into_iter().map(|(_, field)| field).collect()),
+                SValue::Vector(ref vector) => (gc::address(vector), vector.to_vec()),
+                SValue::Values(ref results) => {
+                    pending.extend(results.iter().rev().cloned().map(Visit::Enter));
+                    continue;
+                },
+                _ => continue,
+            };
+            if path.contains(&addr) {
+                self.labels.insert(addr, None);
+            } else if !done.contains(&addr) {
+                path.insert(addr);
+                pending.push(Visit::Leave(addr));
+                pending.extend(parts.into_iter().rev().map(Visit::Enter));
+            }
+        }
+        Ok(())
+    }
+
+    // Prints the #n= or #n# label for the object at addr if it has one;
+    // true if it was already printed and nothing more should be
+    fn label(&mut self, addr: *const (), f: &mut dyn fmt::Write) -> Result<bool, fmt::Error> {
+        match self.labels.get_mut(&addr) {
+            None => Ok(false),
+            Some(&mut Some(n)) => write!(f, "#{}#", n).map(|_| true),
+            Some(label) => {
+                *label = Some(self.next_label);
+                self.next_label += 1;
+                write!(f, "#{}=", self.next_label - 1).map(|_| false)
             },
-            &SValue::Symbol(ref s) => write!(f, "{}", s),
-            &SValue::String(ref s) => write!(f, "{}", s),
-            &SValue::Number(x) => write!(f, "{}", x),
-            &SValue::Bool(b) => write!(f, "{}", b),
-            &SValue::Lambda(_, _, _) => {
-                write!(f, "#<procedure>")
+        }
+    }
+
+    fn print(&mut self, v: &SValue, f: &mut dyn fmt::Write) -> fmt::Result {
+        self.find_cycles(v)?;
+        let mut pending = vec![Print::Value(v.clone())];
+        while let Some(next) = pending.pop() {
+            self.check()?;
+            match next {
+                Print::Value(ref v) => self.print_one(v, &mut pending, f)?,
+                Print::Tail(SValue::Nil) => write!(f, ")")?,
+                Print::Tail(SValue::Pair(ref q)) if !self.labels.contains_key(&gc::address(q)) => {
+                    write!(f, " ")?;
+                    pending.push(Print::Tail(q.cdr()));
+                    pending.push(Print::Value(q.car()));
+                },
+                Print::Tail(tail) => {
+                    write!(f, " . ")?;
+                    pending.push(Print::Text(")"));
+                    pending.push(Print::Value(tail));
+                },
+                Print::Text(s) => write!(f, "{}", s)?,
+                Print::Field(ref name) => write!(f, " {}: ", name)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Prints an atom, or the start of a compound value, leaving its parts
+    // in pending
+    fn print_one(&mut self, v: &SValue, pending: &mut Vec<Print>, f: &mut dyn fmt::Write) -> fmt::Result {
+        // Items separated by spaces, pushed so that the first is printed first
+        let items = |pending: &mut Vec<Print>, items: Vec<SValue>| {
+            for (i, item) in items.into_iter().enumerate().rev() {
+                pending.push(Print::Value(item));
+                if i > 0 {
+                    pending.push(Print::Text(" "));
+                }
             }
+        };
+        match *v {
+            SValue::Nil => write!(f, "()"),
+            SValue::Pair(ref p) => {
+                if self.label(gc::address(p), f)? {
+                    return Ok(());
+                }
+                pending.push(Print::Tail(p.cdr()));
+                pending.push(Print::Value(p.car()));
+                write!(f, "(")
+            },
+            SValue::Symbol(ref s) => write!(f, "{}", s),
+            SValue::String(ref s) if self.write => write!(f, "{:?}", s),
+            SValue::String(ref s) => write!(f, "{}", s),
+            SValue::Number(x) => write!(f, "{}", x),
+            SValue::Bool(b) => write!(f, "{}", if b { "#t" } else { "#f" }),
+            SValue::Char(c) if self.write => match util::char_name(c) {
+                Some(name) => write!(f, "#\\{}", name),
+                None => write!(f, "#\\{}", c),
+            },
+            SValue::Char(c) => write!(f, "{}", c),
+            SValue::Eof => write!(f, "#<eof>"),
+            SValue::Unbound => write!(f, "#<unbound>"),
+            SValue::Lambda(_) => write!(f, "#<procedure>"),
+            SValue::Native(ref n) => write!(f, "#<procedure {}>", n.name),
+            SValue::Foreign(ref obj) => write!(f, "{:?}", obj),
+            SValue::Record(ref r) => {
+                if self.label(gc::address(r), f)? {
+                    return Ok(());
+                }
+                pending.push(Print::Text(">"));
+                for (name, value) in r.entries().into_iter().rev() {
+                    pending.push(Print::Value(value));
+                    pending.push(Print::Field(name));
+                }
+                write!(f, "#<{}", r.record_type().name)
+            },
+            SValue::Vector(ref vector) => {
+                if self.label(gc::address(vector), f)? {
+                    return Ok(());
+                }
+                pending.push(Print::Text(")"));
+                items(pending, vector.to_vec());
+                write!(f, "#(")
+            },
+            SValue::Promise(_) => write!(f, "#<promise>"),
+            SValue::Parameter(_) => write!(f, "#<parameter>"),
+            SValue::Values(ref results) => {
+                items(pending, results.to_vec());
+                Ok(())
+            },
         }
     }
 }
 
 impl fmt::Display for SValue {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.r5rs_display(f) }
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { Printer::new(false, &mut || Ok(())).print(self, f) }
 }
 
 impl fmt::Debug for SValue {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.r5rs_write(f) }
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { Printer::new(true, &mut || Ok(())).print(self, f) }
+}
+
+// Only pairs, records, vectors, promises, parameters and scopes are tracked; what
+// natives, foreign objects and multiple values hold can't be seen, so it
+// is kept alive as if held by the host
+pub fn trace_value(v: &SValue, out: &mut Vec<*const ()>) {
+    match *v {
+        SValue::Pair(ref p) => out.push(gc::address(p)),
+        SValue::Lambda(ref closure) => out.push(gc::address(closure)),
+        SValue::Record(ref r) => out.push(gc::address(r)),
+        SValue::Vector(ref vector) => out.push(gc::address(vector)),
+        SValue::Promise(ref p) => out.push(gc::address(p)),
+        SValue::Parameter(ref p) => out.push(gc::address(p)),
+        _ => {},
+    }
+}
+
+impl Trace for Pair {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        match (self.car.try_borrow(), self.cdr.try_borrow()) {
+            (Ok(car), Ok(cdr)) => {
+                trace_value(&car, out);
+                trace_value(&cdr, out);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn clear(&self) {
+        self.set_car(SValue::Nil);
+        self.set_cdr(SValue::Nil);
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        out.push(gc::address(&self.env));
+        true
+    }
+
+    // Any cycle through a closure passes through its scope, which is
+    // cleared instead
+    fn clear(&self) {}
+}
+
+impl Trace for RefCell<SymTable> {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        match self.try_borrow() {
+            Ok(table) => {
+                for (_, v) in table.items.iter() {
+                    trace_value(v, out);
+                }
+                if let Some(ref parent) = table.parent {
+                    out.push(gc::address(parent));
+                }
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        let mut table = self.borrow_mut();
+        table.items.clear();
+        table.parent = None;
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    Message(String),
+    // Raised by (exit); unwinds evaluation so the host can shut down
+    Exit(i32),
+    // Evaluation was stopped for going over one of the interpreter's `Limits`
+    Limit(Limit),
+    // Evaluation was stopped through an `InterruptHandle`
+    Interrupted,
+    // A value passed to `raise`, or an error object, that nothing caught
+    Raise(SValue),
+    // An error from a loaded or included file, boxed so as not to enlarge
+    // every result returned through the evaluator
+    InFile(Box<Located>),
 }
 
+/// An error with the file and line of the top-level form it came from.
 #[derive(Debug, PartialEq, Clone)]
+pub struct Located {
+    pub file: String,
+    pub line: usize,
+    pub error: EvalError,
+}
+
+/// The resource an evaluation ran out of.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Limit {
+    Fuel,
+    Depth,
+    Heap,
+    Time,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::Message(ref s) => write!(f, "{}", s),
+            EvalError::Exit(code) => write!(f, "exit with status {}", code),
+            EvalError::Limit(limit) => write!(f, "Exceeded the limit on {}", limit),
+            EvalError::Interrupted => write!(f, "Interrupted"),
+            EvalError::Raise(ref v) => match v.downcast::<ErrorObject>() {
+                Some(e) => write!(f, "{}", e),
+                None => write!(f, "Uncaught exception: {:?}", v),
+            },
+            // The innermost file first, then those that loaded it
+            EvalError::InFile(ref at) => match at.error {
+                EvalError::InFile(_) => write!(f, "{}\n  from {}, line {}", at.error, at.file, at.line),
+                _ => write!(f, "{}, line {}: {}", at.file, at.line, at.error),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Limit::Fuel => "evaluation steps",
+            Limit::Depth => "recursion depth",
+            Limit::Heap => "heap size",
+            Limit::Time => "running time",
+        })
+    }
+}
+
+impl EvalError {
+    /// The value a Scheme handler sees for this error, or the error itself
+    /// if Scheme code may not catch it, as with exits and limits.
+    pub fn condition(self) -> Result<SValue, EvalError> {
+        match self {
+            EvalError::Message(message) => Ok(ErrorObject::value(ErrorKind::Other, message, vec![])),
+            EvalError::Raise(v) => Ok(v),
+            EvalError::InFile(at) => at.error.condition(),
+            e => Err(e),
+        }
+    }
+
+    /// Notes that this error came from the form at `line` of `file`,
+    /// unless it is one that stops evaluation rather than reporting a
+    /// failure, which callers match on as it is.
+    pub fn in_file(self, file: &str, line: usize) -> EvalError {
+        match self {
+            EvalError::Message(_) | EvalError::Raise(_) | EvalError::InFile(_) => {
+                EvalError::InFile(Box::new(Located { file: String::from(file), line, error: self }))
+            },
+            e => e,
+        }
+    }
+}
+
+/// What sort of failure an error object describes, for the predicates
+/// `read-error?` and `file-error?`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorKind {
+    Read,
+    File,
+    Other,
+}
+
+/// What `error` raises, and what errors from primitives become when a
+/// handler catches them.
+#[derive(Debug)]
+pub struct ErrorObject {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub irritants: Vec<SValue>,
+}
+
+impl ErrorObject {
+    /// A new error object, as a Scheme value.
+    pub fn value(kind: ErrorKind, message: String, irritants: Vec<SValue>) -> SValue {
+        SValue::foreign("error-object", ErrorObject { kind, message, irritants })
+    }
+}
+
+impl fmt::Display for ErrorObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for irritant in &self.irritants {
+            write!(f, " {:?}", irritant)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<String> for EvalError {
+    fn from(s: String) -> EvalError { EvalError::Message(s) }
+}
+
+impl<'a> From<&'a str> for EvalError {
+    fn from(s: &'a str) -> EvalError { EvalError::Message(String::from(s)) }
+}
+
+// A scope shared by every lambda created in it
+pub type Env = Rc<RefCell<SymTable>>;
+
+#[derive(Debug)]
 pub struct SymTable {
-    items: Vec<(String, SValue)>,
+    items: Vec<(Rc<str>, SValue)>,
+    parent: Option<Env>, // the enclosing scope, searched after this one
 }
 
 impl SymTable {
     pub fn empty() -> SymTable {
-        SymTable { items: vec![] }
+        SymTable { items: vec![], parent: None }
+    }
+
+    pub fn from(items: Vec<(Rc<str>, SValue)>) -> SymTable {
+        SymTable { items, parent: None }
     }
 
-    pub fn from(items: Vec<(String, SValue)>) -> SymTable {
-        SymTable { items: items }
+    pub fn child(parent: &Env, items: Vec<(Rc<str>, SValue)>) -> SymTable {
+        SymTable { items, parent: Some(parent.clone()) }
     }
 
-    pub fn lookup<'a, 'b>(&'a self, sym: &'b String) -> Option<SValue> {
-        for &(ref s, ref v) in self.items.iter() {
-            if s == sym {
+    pub fn into_env(self) -> Env {
+        let env = Rc::new(RefCell::new(self));
+        gc::track(&env);
+        env
+    }
+
+    pub fn lookup(&self, sym: &str) -> Option<SValue> {
+        for (s, v) in self.items.iter() {
+            if &**s == sym {
                 return Some(v.clone());
             }
         }
-        None
+        match self.parent {
+            Some(ref parent) => parent.borrow().lookup(sym),
+            None => None,
+        }
+    }
+
+    pub fn bindings(&self) -> Vec<(Rc<str>, SValue)> {
+        self.items.clone()
+    }
+
+    pub fn parent(&self) -> Option<Env> {
+        self.parent.clone()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
     }
 
-    pub fn assign<'a, 'b>(&'a mut self, sym: &'b String, val: SValue) {
+    // Compiled code addresses variables by frame depth and slot instead of name
+    pub fn get(&self, depth: usize, index: usize) -> SValue {
+        match self.parent {
+            Some(ref parent) if depth > 0 => parent.borrow().get(depth - 1, index),
+            _ => self.items[index].1.clone(),
+        }
+    }
+
+    pub fn name_at(&self, depth: usize, index: usize) -> Rc<str> {
+        match self.parent {
+            Some(ref parent) if depth > 0 => parent.borrow().name_at(depth - 1, index),
+            _ => self.items[index].0.clone(),
+        }
+    }
+
+    pub fn set_at(&mut self, depth: usize, index: usize, val: SValue) {
+        match self.parent {
+            Some(ref parent) if depth > 0 => parent.borrow_mut().set_at(depth - 1, index, val),
+            _ => self.items[index].1 = val,
+        }
+    }
+
+    // Changes the innermost binding of sym; false if it is unbound
+    pub fn set(&mut self, sym: &str, val: SValue) -> bool {
+        for item in self.items.iter_mut() {
+            if &*item.0 == sym {
+                item.1 = val;
+                return true;
+            }
+        }
+        match self.parent {
+            Some(ref parent) => parent.borrow_mut().set(sym, val),
+            None => false,
+        }
+    }
+
+    // Binds sym in this scope, shadowing any binding in enclosing ones
+    pub fn assign(&mut self, sym: &str, val: SValue) {
         let mut loc = 0;
-        for &(ref s, _) in self.items.iter() {
-            if s == sym {
+        for (s, _) in self.items.iter() {
+            if &**s == sym {
                 break;
             }
             loc += 1;
         }
-        self.items.push((sym.clone(), val));
+        self.items.push((Rc::from(sym), val));
         if loc < self.items.len() - 1 {
             self.items.swap_remove(loc);
         }
     }
 }
 
-fn check_sym<'a>(sexp: &'a Sexp, s: &'static str) -> bool {
-    sexp == &Sexp::Symbol(String::from(s))
-}
-
-pub fn quote(sexp: &Sexp) -> SValue {
-    match sexp {
-        &Sexp::List(ref ll) =>
-            SValue::List(
-                ll.iter().map(|x| quote(&x.clone())).collect()),
-        &Sexp::Symbol(ref s) => SValue::Symbol(s.clone()),
-        &Sexp::String(ref s) => SValue::String(s.clone()),
-        &Sexp::Number(ref f) => SValue::Number(f.clone()),
-        &Sexp::Bool(ref b) => SValue::Bool(b.clone()),
+fn check_sym(sexp: &Sexp, s: &'static str) -> bool {
+    match *sexp {
+        Sexp::Symbol(ref sym) => &**sym == s,
+        _ => false,
     }
 }
 
-type BinOp = Fn(SValue, SValue) -> Result<SValue, String>;
-fn binop(op: Box<BinOp>, ident: SValue, ll: LinkedList<SValue>)
-             -> Result<SValue, String> {
-    let mut total = ident;
-    for x in ll {
-        match op(total, x) {
-            Ok(v) => total = v,
-            Err(e) => return Err(e),
-        }
+// Splits the items of a list written with a dot, as in (a b . c), into
+// those before the dot and the one after it
+fn split_dotted(items: &[Sexp]) -> Result<(&[Sexp], Option<&Sexp>), EvalError> {
+    match items.iter().position(|x| check_sym(x, ".")) {
+        None => Ok((items, None)),
+        Some(i) if i > 0 && i + 2 == items.len() => Ok((&items[..i], Some(&items[i + 1]))),
+        Some(_) => Err(EvalError::from("Misplaced `.` in list")),
     }
-    Ok(total)
 }
 
-fn get_binop(s: String) -> Option<(Box<BinOp>, SValue)> {
-    match &*s {
-        "+" => {
-            let f = Box::new(|x, y| match (x, y) {
-                (SValue::Number(fx), SValue::Number(fy)) =>
-                    Ok(SValue::Number(fx + fy)),
-                _ => Err(String::from("Expected numbers for +")),
-            });
-            Some((f, SValue::Number(0f64)))
+pub fn quote(sexp: &Sexp) -> Result<SValue, EvalError> {
+    Ok(match *sexp {
+        Sexp::List(ref items) => {
+            let (items, tail) = split_dotted(items)?;
+            let tail = match tail {
+                Some(tail) => quote(tail)?,
+                None => SValue::Nil,
+            };
+            let items = items.iter().map(quote).collect::<Result<Vec<_>, _>>()?;
+            SValue::list_with_tail(items, tail)
+        },
+        Sexp::Vector(ref items) => {
+            SValue::Vector(Vector::new(items.iter().map(quote).collect::<Result<_, _>>()?))
         },
+        Sexp::Symbol(ref s) => SValue::Symbol(s.clone()),
+        Sexp::String(ref s) => SValue::String(s.clone()),
+        Sexp::Number(f) => SValue::Number(f),
+        Sexp::Bool(b) => SValue::Bool(b),
+        Sexp::Char(c) => SValue::Char(c),
+    })
+}
 
-        "*" => Some((Box::new(|x: SValue, y: SValue| match (x, y) {
-            (SValue::Number(fx), SValue::Number(fy)) => Ok(SValue::Number(fx * fy)),
-            _ => Err(String::from("Expected numbers for *")),
-        }), SValue::Number(1f64))),
-
-        // Todo: do as macro?
-        // "and" => {
-        //     let f = Box::new(|x, y| match (x, y) {
-        //         (SValue::Bool(p), SValue::Bool(q)) =>
-        //             Ok(SValue::Bool(p && q)),
-        //         _ => Err("Expected bool in `and` call"),
-        //     });
-        //     Some((f, SValue::Bool(true)))
-        // },
+/// The expression a datum stands for, the reverse of `quote`. Procedures
+/// and other values with no written form can't be made into expressions.
+pub fn unquote(v: &SValue) -> Result<Sexp, EvalError> {
+    unquote_within(v, &mut HashSet::new())
+}
 
-        _ => None,
-    }
+// path holds the pairs of the lists v is within, to refuse circular data
+fn unquote_within(v: &SValue, path: &mut HashSet<*const ()>) -> Result<Sexp, EvalError> {
+    Ok(match *v {
+        SValue::Nil => Sexp::List(vec![].into()),
+        SValue::Pair(_) => {
+            let mut items = vec![];
+            let mut chain = vec![];
+            let mut cur = v.clone();
+            while let SValue::Pair(p) = cur {
+                if !path.insert(gc::address(&p)) {
+                    return Err(EvalError::from("Cannot evaluate circular data"));
+                }
+                chain.push(gc::address(&p));
+                items.push(unquote_within(&p.car(), path)?);
+                cur = p.cdr();
+            }
+            if cur != SValue::Nil {
+                items.push(Sexp::Symbol(Rc::from(".")));
+                items.push(unquote_within(&cur, path)?);
+            }
+            for addr in chain {
+                path.remove(&addr);
+            }
+            Sexp::List(items.into())
+        },
+        SValue::Vector(ref vector) => {
+            if !path.insert(gc::address(vector)) {
+                return Err(EvalError::from("Cannot evaluate circular data"));
+            }
+            let items = vector.to_vec().iter().map(|x| unquote_within(x, path)).collect::<Result<Vec<_>, _>>();
+            path.remove(&gc::address(vector));
+            Sexp::Vector(items?.into())
+        },
+        SValue::Symbol(ref s) => Sexp::Symbol(s.clone()),
+        SValue::String(ref s) => Sexp::String(s.clone()),
+        SValue::Number(f) => Sexp::Number(f),
+        SValue::Bool(b) => Sexp::Bool(b),
+        SValue::Char(c) => Sexp::Char(c),
+        _ => return Err(EvalError::Message(format!("Cannot evaluate {:?}, which has no written form", v))),
+    })
 }
 
-fn check_binop<'a>(sexp: &'a Sexp) -> Option<(Box<BinOp>, SValue)> {
-    if let &Sexp::Symbol(ref s) = sexp {
-        let x: Option<(Box<BinOp>, SValue)> = get_binop(s.clone());
-        return x;
+fn param_name(sexp: &Sexp) -> Result<Rc<str>, EvalError> {
+    if let Sexp::Symbol(ref s) = *sexp {
+        Ok(s.clone())
     } else {
-        return None;
+        Err(EvalError::from("Expected symbol in argument list"))
     }
 }
 
-fn get_param_list(ss: LinkedList<Sexp>) -> Result<LinkedList<String>, String> {
-    let mut vals = LinkedList::new();
-    for x in ss {
-        if let Sexp::Symbol(s) = x {
-            vals.push_back(s);
-        } else {
-            return Err(String::from("Expected symbol in argument list"));
-        }
+// Accepts (a b), (a b . rest) or a lone symbol taking all arguments
+pub fn get_param_list(params: &Sexp) -> Result<Params, EvalError> {
+    match *params {
+        Sexp::List(ref ss) => {
+            let (names, rest) = split_dotted(ss)?;
+            Ok(Params {
+                names: names.iter().map(param_name).collect::<Result<_, _>>()?,
+                rest: match rest {
+                    Some(rest) => Some(param_name(rest)?),
+                    None => None,
+                },
+            })
+        },
+        Sexp::Symbol(ref s) => Ok(Params { names: vec![], rest: Some(s.clone()) }),
+        _ => Err(EvalError::from("Expected argument list after `lambda`")),
     }
-    return Ok(vals);
 }
 
-pub fn eval_from_src(src: String) -> Result<SValue, String> {
-    let tokens = util::tokenize(&src);
-    if let Ok(toks) = tokens {
-        if let Ok(ast) = parse::read_sexp(&mut util::ClingyIter::new(toks.iter())) {
-            eval(&mut SymTable::empty(), ast)
-        } else {
-            Err(String::from("sdfjklsdfjkl"))
-        }
-    } else {
-        Err(String::from("sdfjklsdfjkl"))
+// A body of several expressions is evaluated as a `begin`
+pub fn make_body(body: &[Sexp]) -> Result<Sexp, EvalError> {
+    match body.len() {
+        0 => Err(EvalError::from("Expected body after argument list in lambda")),
+        1 => Ok(body[0].clone()),
+        _ => {
+            let mut items = vec![Sexp::Symbol(Rc::from("begin"))];
+            items.extend(body.iter().cloned());
+            Ok(Sexp::List(items.into()))
+        },
     }
 }
 
-pub fn arith_table() -> SymTable {
-    let x = eval_from_src(String::from("(lambda (x) (+ x x))"));
-    if let Ok(f) = x {
-        SymTable::from(vec![(String::from("double"), f)])
-    } else {
-        SymTable::empty()
+fn make_lambda(env: &Env, params: &Sexp, body: &[Sexp]) -> Result<SValue, EvalError> {
+    let body = Body::Source(get_param_list(params)?, make_body(body)?);
+    Ok(SValue::Lambda(Closure::new(env, body)))
+}
+
+pub fn check_arity(params: &Params, argc: usize) -> Result<(), EvalError> {
+    let n = params.names.len();
+    if argc < n || (argc > n && params.rest.is_none()) {
+        let expected = if params.rest.is_some() { "at least " } else { "" };
+        return Err(EvalError::Message(format!(
+            "Expected {}{} args; was given {}", expected, n, argc)));
     }
+    Ok(())
 }
 
-// todo: make lambdas capture environ; put values in Boxes; add  mutability
-// tables like prototypes, having links to superscopes
+fn invoc_sub_scope(env: &Env, params: &Params, args: Vec<SValue>) -> Result<Env, EvalError> {
+    check_arity(params, args.len())?;
+    let mut args = args.into_iter();
+    let mut items: Vec<(Rc<str>, SValue)> = params.names.iter().cloned()
+        .zip(args.by_ref())
+        .collect();
+    if let Some(ref rest) = params.rest {
+        items.push((rest.clone(), args.collect()));
+    }
+    Ok(SymTable::child(env, items).into_env())
+}
 
-fn mk_sub_scope(table: &SymTable) -> SymTable {
-    table.clone()
+pub fn apply(interp: &mut Interpreter, f: SValue, args: Vec<SValue>) -> Result<SValue, EvalError> {
+    match f {
+        SValue::Lambda(closure) => match closure.body {
+            Body::Source(ref params, ref body) => {
+                let new_env = invoc_sub_scope(&closure.env, params, args)?;
+                interp.push_call()?;
+                let result = eval_tail(interp, new_env, body.clone(), &mut true);
+                interp.pop_call();
+                result
+            },
+            Body::Compiled(ref template) => vm::call(interp, &closure.env, template, args),
+        },
+        SValue::Native(native) => native.call(interp, args),
+        SValue::Parameter(parameter) => parameter.call(&args),
+        _ => Err(EvalError::from("Expected callable value")),
+    }
 }
 
-fn invoc_sub_scope<'a>(table: &'a SymTable, params: LinkedList<String>, args: LinkedList<SValue>) -> SymTable {
-    let mut new_scope = table.clone();
-    for (name, val) in params.iter().zip(args.iter()) {
-        new_scope.assign(&name, val.clone());
+// TODO: parameterize by stx_forms to allow macro extensibility
+pub fn eval(interp: &mut Interpreter, env: &Env, sexp: &Sexp) -> Result<SValue, EvalError> {
+    let mut in_call = false;
+    let result = eval_tail(interp, env.clone(), sexp.clone(), &mut in_call);
+    if in_call {
+        interp.pop_call();
     }
-    new_scope
+    result
 }
 
-fn eval_all(table: &mut SymTable, ll: LinkedList<Sexp>) -> Result<LinkedList<SValue>, String> {
-    let mut vals = LinkedList::new();
-    for x in ll {
-        match eval(table, x) {
-            Ok(v) => vals.push_back(v),
-            Err(e) => return Err(e)
+// Evaluates sexp, going on in a loop to whatever is in tail position, so
+// that tail calls don't grow the native stack. in_call is set once a call
+// has been counted against the depth limit for this loop; the caller takes
+// it off again.
+fn eval_tail(interp: &mut Interpreter, mut env: Env, mut sexp: Sexp, in_call: &mut bool)
+             -> Result<SValue, EvalError> {
+    loop {
+        interp.step()?;
+        let items = match sexp {
+            Sexp::List(ref items) => items.clone(),
+            _ => return eval_atom(&env, &sexp),
+        };
+        let (cmd, args) = match items.split_first() {
+            Some(split) => split,
+            None => return Err(EvalError::from("Unexpected ()")),
+        };
+        if check_sym(cmd, "if") {
+            if args.len() < 2 || args.len() > 3 {
+                return Err(EvalError::from("`if` expected 2 or 3 args"));
+            }
+            let test = eval(interp, &env, &args[0])?;
+            sexp = if test.is_true() {
+                args[1].clone()
+            } else if let Some(alternative) = args.get(2) {
+                alternative.clone()
+            } else {
+                return Ok(SValue::nil());
+            };
+        } else if check_sym(cmd, "begin") {
+            let (last, init) = match args.split_last() {
+                Some(split) => split,
+                None => return Ok(SValue::nil()),
+            };
+            for sexp in init {
+                eval(interp, &env, sexp)?;
+            }
+            sexp = last.clone();
+        } else if let Some(form) = special_form(cmd) {
+            return eval_special(interp, &env, form, args);
+        } else {
+            let f = eval(interp, &env, cmd)?;
+            let args = args.iter()
+                .map(|x| eval(interp, &env, x))
+                .collect::<Result<Vec<_>, _>>()?;
+            let closure = match f {
+                SValue::Lambda(ref closure) if matches!(closure.body, Body::Source(..)) => closure.clone(),
+                _ => return apply(interp, f, args),
+            };
+            if let Body::Source(ref params, ref body) = closure.body {
+                env = invoc_sub_scope(&closure.env, params, args)?;
+                sexp = body.clone();
+            }
+            if !*in_call {
+                interp.push_call()?;
+                *in_call = true;
+            }
         }
     }
-    Ok(vals)
 }
 
-// TODO: parameterize by stx_forms to allow macro extensibility
-pub fn eval<'a>(table: &'a mut SymTable, sexp: Sexp) -> Result<SValue, String> {
-    match sexp {
+fn special_form(cmd: &Sexp) -> Option<&'static str> {
+    ["quote", "define", "set!", "lambda"].iter().cloned().find(|s| check_sym(cmd, s))
+}
+
+fn eval_atom(env: &Env, sexp: &Sexp) -> Result<SValue, EvalError> {
+    match *sexp {
         Sexp::Number(f) => Ok(SValue::Number(f)),
 
         Sexp::Bool(b) => Ok(SValue::Bool(b)),
 
-        Sexp::Symbol(s) => {
-            if let Some(sval) = table.lookup(&s) {
+        Sexp::Char(c) => Ok(SValue::Char(c)),
+
+        Sexp::Symbol(ref s) => {
+            let found = env.borrow().lookup(s);
+            if let Some(sval) = found {
                 Ok(sval)
             } else {
-                Err(format!("Symbol is not bound: {}.\nScope: {:?}", &s, table))
+                Err(EvalError::Message(format!("Symbol is not bound: {}", s)))
             }
         },
 
-        Sexp::String(s) => Ok(SValue::String(s)),
-
-        Sexp::List(items) => {
-            let mut item_ll = items.clone();
-
-            if let Some(cmd) = item_ll.pop_front() {
-                if check_sym(&cmd, "quote") {
-                    if let Some(sexp) = item_ll.pop_front() {
-                        Ok(quote(&sexp))
-                    } else {
-                        Err(String::from("`quote` expected 1 arg; was given 0"))
-                    }
-
-                } else if check_sym(&cmd, "define") {
-                    if let Some(Sexp::Symbol(s)) = item_ll.pop_front() {
-                        if let Some(sexp) = item_ll.pop_front() {
-                            match eval(table, sexp) {
-                                Ok(v) => {
-                                    table.assign(&s, v);
-                                    Ok(SValue::nil())
-                                },
-                                Err(e) => Err(e)
-                            }
-                        } else {
-                            Err(String::from("Expected value in define statement"))
-                        }
-                    } else {
-                        Err(String::from("Expected symbol after `define`"))
-                    }
-
-                } else if check_sym(&cmd, "lambda") {
-                    if let Some(Sexp::List(arg_sexps)) = item_ll.pop_front() {
-                        match get_param_list(arg_sexps) {
-                            Ok(params) => {
-                                if let Some(body) = item_ll.pop_front() {
-                                    Ok(SValue::Lambda(mk_sub_scope(table), params, body))
-                                } else {
-                                    Err(String::from("Expected body after argument list in lambda"))
-                                }
-                            },
-                            Err(e) => Err(e),
-                        }
-                    } else {
-                        Err(String::from("Expected argument list after `lambda`"))
-                    }
-
-                } else if check_sym(&cmd, "display") {
-                    if let Some(sexp) = item_ll.pop_front() {
-                        match eval(table, sexp) {
-                            Ok(v) => {
-                                println!("{}", v);
-                                Ok(SValue::nil())
-                            },
-                            Err(e) => Err(e),
-                        }
-                    } else {
-                        Err(String::from("`display` expected 1 arg; was given 0"))
-                    }
+        Sexp::String(ref s) => Ok(SValue::String(s.clone())),
 
-                } else if check_sym(&cmd, "write") {
-                    if let Some(sexp) = item_ll.pop_front() {
-                        match eval(table, sexp) {
-                            Ok(v) => {
-                                println!("{:?}", v);
-                                Ok(SValue::nil())
-                            },
-                            Err(e) => Err(e),
-                        }
-                    } else {
-                        Err(String::from("`write` expected 1 arg; was given 0"))
-                    }
+        Sexp::Vector(_) => quote(sexp),
 
-                } else if check_sym(&cmd, "reciprocal") {
-                    if let Some(sexp) = item_ll.pop_front() {
-                        match eval(table, sexp) {
-                            Ok(v) => {
-                                match v {
-                                    SValue::Number(f) => Ok(SValue::Number(1f64 / f)),
-                                    _ => Err(String::from("`reciprocal` expected number")),
-                                }
-                            },
-                            Err(e) => Err(e),
-                        }
-                    } else {
-                        Err(String::from("`reciprocal` expected 1 arg; was given 0"))
-                    }
-                } else if let Some((op, ident)) = check_binop(&cmd) {
-                    let mut vals = LinkedList::new();
-                    for e in item_ll {
-                        let rval = eval(table, e);
-                        if let Ok(v) = rval {
-                            vals.push_back(v);
-                        } else {
-                            return rval;
-                        }
-                    }
-                    binop(op, ident, vals)
+        Sexp::List(_) => unreachable!(),
+    }
+}
 
+// The special forms with nothing in tail position
+fn eval_special(interp: &mut Interpreter, env: &Env, form: &str, args: &[Sexp]) -> Result<SValue, EvalError> {
+    match form {
+        "quote" => match args.first() {
+            Some(sexp) => quote(sexp),
+            None => Err(EvalError::from("`quote` expected 1 arg; was given 0")),
+        },
+        "define" => match args.first() {
+            Some(Sexp::Symbol(s)) => {
+                if let Some(sexp) = args.get(1) {
+                    let v = eval(interp, env, sexp)?;
+                    env.borrow_mut().assign(s, v);
+                    Ok(SValue::nil())
                 } else {
-                    match eval(table, cmd) {
-                        Ok(SValue::Lambda(mut sub_table, params, body)) => {
-                            // item_ll is the list of args
-                            match eval_all(table, item_ll) {
-                                Ok(args) => {
-                                    let mut new_table = invoc_sub_scope(&mut sub_table, params, args);
-                                    eval(&mut new_table, body)
-                                },
-                                Err(e) => Err(e),
-                            }
-                        },
-                        Ok(_) => Err(String::from("Expected callable value")),
-                        Err(e) => Err(e),
-                    }
+                    Err(EvalError::from("Expected value in define statement"))
                 }
-            } else {
-                Err(String::from("Unexpected ()"))
-            }
+            },
+            // (define (name . params) body...)
+            Some(Sexp::List(signature)) => {
+                match signature.split_first() {
+                    Some((Sexp::Symbol(s), params)) => {
+                        // (f . args) leaves (. args), which means just args
+                        let params = match params {
+                            [dot, rest] if check_sym(dot, ".") => rest.clone(),
+                            _ => Sexp::List(params.into()),
+                        };
+                        let f = make_lambda(env, &params, &args[1..])?;
+                        env.borrow_mut().assign(s, f);
+                        Ok(SValue::nil())
+                    },
+                    _ => Err(EvalError::from("Expected symbol after `define`")),
+                }
+            },
+            _ => Err(EvalError::from("Expected symbol after `define`")),
+        },
+        "set!" => match (args.first(), args.get(1)) {
+            (Some(Sexp::Symbol(s)), Some(sexp)) => {
+                let v = eval(interp, env, sexp)?;
+                if env.borrow_mut().set(s, v) {
+                    Ok(SValue::nil())
+                } else {
+                    Err(EvalError::Message(format!("Symbol is not bound: {}", s)))
+                }
+            },
+            _ => Err(EvalError::from("Expected symbol and value after `set!`")),
+        },
+        _ => match args.first() {
+            Some(params) => make_lambda(env, params, &args[1..]),
+            None => Err(EvalError::from("Expected argument list after `lambda`")),
         },
     }
 }
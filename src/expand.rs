@@ -0,0 +1,691 @@
+use std::rc::Rc;
+use eval;
+use eval::EvalError;
+use interpreter::Interpreter;
+use library;
+use parse::Sexp;
+
+fn is_sym(sexp: Option<&Sexp>, s: &str) -> bool {
+    match sexp {
+        Some(Sexp::Symbol(sym)) => &**sym == s,
+        _ => false,
+    }
+}
+
+fn sym(s: &str) -> Sexp {
+    Sexp::Symbol(Rc::from(s))
+}
+
+fn list(items: Vec<Sexp>) -> Sexp {
+    Sexp::List(items.into())
+}
+
+// A variable for expansions to bind that source code can't refer to, as
+// the reader takes # to start other syntax
+fn temp(name: &str) -> Sexp {
+    sym(&format!("#{}", name))
+}
+
+// Clauses in the manner of cond, as nested ifs, with fallback as the value
+// when no test holds
+fn clauses(items: &[Sexp], fallback: Sexp) -> Result<Sexp, EvalError> {
+    let (clause, rest) = match items.split_first() {
+        Some(split) => split,
+        None => return Ok(fallback),
+    };
+    let parts = match *clause {
+        Sexp::List(ref parts) if !parts.is_empty() => parts,
+        _ => return Err(EvalError::from("Expected a clause (test expression...)")),
+    };
+    let mut body = vec![sym("begin")];
+    body.extend(parts[1..].iter().cloned());
+    if is_sym(parts.first(), "else") {
+        if !rest.is_empty() {
+            return Err(EvalError::from("`else` must be the last clause"));
+        }
+        return Ok(list(body));
+    }
+    let rest = clauses(rest, fallback)?;
+    // The test's value is kept when it is needed, with the remaining
+    // clauses put off in a thunk
+    let keep = |then: Sexp| {
+        let lambda = list(vec![sym("lambda"), list(vec![temp("test"), temp("rest")]),
+                               list(vec![sym("if"), temp("test"), then, list(vec![temp("rest")])])]);
+        let rest = list(vec![sym("lambda"), list(vec![]), rest.clone()]);
+        list(vec![lambda, parts[0].clone(), rest])
+    };
+    Ok(match parts[1..] {
+        [] => keep(temp("test")),
+        [ref arrow, ref receiver] if is_sym(Some(arrow), "=>") => {
+            keep(list(vec![receiver.clone(), temp("test")]))
+        },
+        _ => list(vec![sym("if"), parts[0].clone(), list(body), rest]),
+    })
+}
+
+fn string(s: &str) -> Sexp {
+    Sexp::String(Rc::from(s))
+}
+
+// (define-record-type <point> (make-point x y) point? (x point-x set-point-x!)...) =>
+//   (begin (define <point> (%make-record-type "point" (list "x" ...)))
+//          (define make-point (%record-constructor <point> "make-point" (list "x" "y")))
+//          (define point? (%record-predicate <point> "point?"))
+//          (define point-x (%record-accessor <point> "x" "point-x"))
+//          (define set-point-x! (%record-modifier <point> "x" "set-point-x!")) ...)
+fn record_type(items: &[Sexp]) -> Result<Sexp, EvalError> {
+    let invalid = || EvalError::from(
+        "Expected (define-record-type name (constructor field...) predicate (field accessor [modifier])...)");
+    let names = |items: &[Sexp]| -> Result<Vec<Rc<str>>, EvalError> {
+        items.iter().map(|item| match *item {
+            Sexp::Symbol(ref s) => Ok(s.clone()),
+            _ => Err(invalid()),
+        }).collect()
+    };
+    let (type_name, constructor, predicate, specs) = match items {
+        [Sexp::Symbol(type_name), constructor, Sexp::Symbol(predicate), specs @ ..] => {
+            (type_name, constructor, predicate, specs)
+        },
+        _ => return Err(invalid()),
+    };
+    let type_var = &items[0];
+    let mut fields = vec![];
+    let mut procedures = vec![];
+    for spec in specs {
+        let parts = match *spec {
+            Sexp::List(ref parts) if (2..=3).contains(&parts.len()) => names(parts)?,
+            _ => return Err(invalid()),
+        };
+        fields.push(parts[0].clone());
+        procedures.push(("%record-accessor", parts[0].clone(), parts[1].clone()));
+        if let Some(modifier) = parts.get(2) {
+            procedures.push(("%record-modifier", parts[0].clone(), modifier.clone()));
+        }
+    }
+    let strings = |names: &[Rc<str>]| {
+        let mut list_call = vec![sym("list")];
+        list_call.extend(names.iter().map(|name| string(name)));
+        list(list_call)
+    };
+    let define = |name: &str, value: Vec<Sexp>| list(vec![sym("define"), sym(name), list(value)]);
+
+    // Printed without the brackets conventional for the variable
+    let name = type_name.trim_start_matches('<').trim_end_matches('>');
+    let mut body = vec![sym("begin"), list(vec![
+        sym("define"), type_var.clone(),
+        list(vec![sym("%make-record-type"), string(name), strings(&fields)]),
+    ])];
+    // The constructor may be a bare name, taking every field
+    let (constructor, params) = match *constructor {
+        Sexp::Symbol(ref s) => (s.clone(), fields.clone()),
+        Sexp::List(ref parts) if !parts.is_empty() => {
+            let parts = names(parts)?;
+            (parts[0].clone(), parts[1..].to_vec())
+        },
+        _ => return Err(invalid()),
+    };
+    body.push(define(&constructor, vec![
+        sym("%record-constructor"), type_var.clone(), string(&constructor), strings(&params),
+    ]));
+    body.push(define(predicate, vec![sym("%record-predicate"), type_var.clone(), string(predicate)]));
+    for (builder, field, name) in procedures {
+        body.push(define(&name, vec![sym(builder), type_var.clone(), string(&field), string(&name)]));
+    }
+    Ok(list(body))
+}
+
+// The formals of a lambda with each variable renamed as a temporary,
+// along with the variables and the temporaries, in order
+fn rename_formals(name: &str, formals: &Sexp) -> Result<(Sexp, Vec<Sexp>, Vec<Sexp>), EvalError> {
+    let invalid = || EvalError::Message(format!("Expected formals in `{}`", name));
+    let (mut vars, mut temps) = (vec![], vec![]);
+    let mut rename = |item: &Sexp| match *item {
+        Sexp::Symbol(ref s) if &**s == "." => Ok(item.clone()),
+        Sexp::Symbol(ref s) => {
+            vars.push(item.clone());
+            temps.push(temp(s));
+            Ok(temp(s))
+        },
+        _ => Err(invalid()),
+    };
+    let renamed = match *formals {
+        Sexp::Symbol(_) => rename(formals)?,
+        Sexp::List(ref items) => list(items.iter().map(rename).collect::<Result<_, _>>()?),
+        _ => return Err(invalid()),
+    };
+    Ok((renamed, vars, temps))
+}
+
+fn lambda(formals: Sexp, body: &[Sexp]) -> Sexp {
+    let mut lambda = vec![sym("lambda"), formals];
+    lambda.extend(body.iter().cloned());
+    list(lambda)
+}
+
+// (call-with-values (lambda () expr) consumer)
+fn call_with_values(expr: &Sexp, consumer: Sexp) -> Sexp {
+    list(vec![sym("call-with-values"), list(vec![sym("lambda"), list(vec![]), expr.clone()]), consumer])
+}
+
+// (let-values (((formals) expr)...) body...) =>
+//   (call-with-values (lambda () expr)
+//     (lambda temps... ((lambda (formals...) body...) temps...)))
+// with the expressions evaluated outside the scope of any formals, or
+// for let*-values, each in the scope of those before it
+fn let_values(name: &str, items: &[Sexp]) -> Result<Sexp, EvalError> {
+    let invalid = || EvalError::Message(format!("Expected ((formals expression)...) body... after `{}`", name));
+    let (bindings, body) = match items {
+        [Sexp::List(bindings), body @ ..] if !body.is_empty() => (bindings, body),
+        _ => return Err(invalid()),
+    };
+    let mut pairs = vec![];
+    for binding in bindings.iter() {
+        match *binding {
+            Sexp::List(ref parts) if parts.len() == 2 => pairs.push((&parts[0], &parts[1])),
+            _ => return Err(invalid()),
+        }
+    }
+    if pairs.is_empty() {
+        return Ok(list(vec![lambda(list(vec![]), body)]));
+    }
+    let mut inner = body.to_vec();
+    if name == "let*-values" {
+        for &(formals, expr) in pairs.iter().rev() {
+            inner = vec![call_with_values(expr, lambda(formals.clone(), &inner))];
+        }
+        return Ok(inner.remove(0));
+    }
+    let mut renamed = vec![];
+    let (mut vars, mut temps) = (vec![], vec![]);
+    for &(formals, _) in &pairs {
+        let (formals, v, t) = rename_formals(name, formals)?;
+        renamed.push(formals);
+        vars.extend(v);
+        temps.extend(t);
+    }
+    let mut call = vec![lambda(list(vars), body)];
+    call.extend(temps);
+    inner = vec![list(call)];
+    for (&(_, expr), formals) in pairs.iter().zip(renamed).rev() {
+        inner = vec![call_with_values(expr, lambda(formals, &inner))];
+    }
+    Ok(inner.remove(0))
+}
+
+// (do ((var init step)...) (test expr...) command...) =>
+//   ((lambda ()
+//      (define (#loop var...) (if test (begin expr...) (begin command... (#loop step...))))
+//      (#loop init...)))
+// where a variable without a step keeps its value
+fn do_loop(items: &[Sexp]) -> Result<Sexp, EvalError> {
+    let invalid = || EvalError::from("Expected ((variable init [step])...) (test expression...) command... after `do`");
+    let (specs, exit, commands) = match items {
+        [Sexp::List(specs), Sexp::List(exit), commands @ ..] if !exit.is_empty() => (specs, exit, commands),
+        _ => return Err(invalid()),
+    };
+    let (mut vars, mut inits, mut steps) = (vec![temp("loop")], vec![temp("loop")], vec![temp("loop")]);
+    for spec in specs.iter() {
+        match *spec {
+            Sexp::List(ref parts) if (2..=3).contains(&parts.len()) && matches!(parts[0], Sexp::Symbol(_)) => {
+                vars.push(parts[0].clone());
+                inits.push(parts[1].clone());
+                steps.push(parts.get(2).unwrap_or(&parts[0]).clone());
+            },
+            _ => return Err(invalid()),
+        }
+    }
+    let mut result = vec![sym("begin")];
+    result.extend(exit[1..].iter().cloned());
+    let mut body = vec![sym("begin")];
+    body.extend(commands.iter().cloned());
+    body.push(list(steps));
+    let loop_body = list(vec![sym("if"), exit[0].clone(), list(result), list(body)]);
+    let define = list(vec![sym("define"), list(vars), loop_body]);
+    Ok(list(vec![lambda(list(vec![]), &[define, list(inits)])]))
+}
+
+// (parameterize ((param value)...) body...) =>
+//   (%parameterize (list param...) (list value...) (lambda () body...))
+fn parameterize(items: &[Sexp]) -> Result<Sexp, EvalError> {
+    let invalid = || EvalError::from("Expected ((parameter value)...) body... after `parameterize`");
+    let (bindings, body) = match items {
+        [Sexp::List(bindings), body @ ..] if !body.is_empty() => (bindings, body),
+        _ => return Err(invalid()),
+    };
+    let (mut parameters, mut values) = (vec![sym("list")], vec![sym("list")]);
+    for binding in bindings.iter() {
+        match *binding {
+            Sexp::List(ref parts) if parts.len() == 2 => {
+                parameters.push(parts[0].clone());
+                values.push(parts[1].clone());
+            },
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(list(vec![sym("%parameterize"), list(parameters), list(values), lambda(list(vec![]), body)]))
+}
+
+// (define-values (a b . c) expr) =>
+//   (begin (define #values (call-with-values (lambda () expr) (lambda (#a #b . #c) (list #a #b #c))))
+//          (define a (car #values)) (define b (car (cdr #values))) (define c (car (cdr (cdr #values)))))
+// so that expr sees any earlier bindings of the variables
+fn define_values(items: &[Sexp]) -> Result<Sexp, EvalError> {
+    let (formals, expr) = match items {
+        [formals, expr] => (formals, expr),
+        _ => return Err(EvalError::from("Expected formals and an expression after `define-values`")),
+    };
+    let (renamed, vars, temps) = rename_formals("define-values", formals)?;
+    let mut collect = vec![sym("list")];
+    collect.extend(temps);
+    let mut body = vec![sym("begin"), list(vec![
+        sym("define"), temp("values"), call_with_values(expr, lambda(renamed, &[list(collect)])),
+    ])];
+    let mut rest = temp("values");
+    for var in vars {
+        body.push(list(vec![sym("define"), var, list(vec![sym("car"), rest.clone()])]));
+        rest = list(vec![sym("cdr"), rest]);
+    }
+    Ok(list(body))
+}
+
+fn call(f: Sexp, args: Vec<Sexp>) -> Sexp {
+    let mut call = vec![f];
+    call.extend(args);
+    list(call)
+}
+
+// ((lambda (var) body) value)
+fn bind(var: Sexp, value: Sexp, body: Sexp) -> Sexp {
+    call(lambda(list(vec![var.clone()]), &[body]), vec![value])
+}
+
+fn test(condition: Sexp, then: Sexp, otherwise: Sexp) -> Sexp {
+    list(vec![sym("if"), condition, then, otherwise])
+}
+
+fn invalid_pattern(pattern: &Sexp) -> EvalError {
+    match eval::quote(pattern) {
+        Ok(v) => EvalError::Message(format!("Invalid pattern in `match`: {:?}", v)),
+        Err(e) => e,
+    }
+}
+
+// A quasi-pattern as the pattern it stands for: symbols are quoted, and
+// what is unquoted is a pattern again
+fn quasi(qp: &Sexp) -> Sexp {
+    match *qp {
+        Sexp::Symbol(ref s) if &**s == "..." || &**s == "." => qp.clone(),
+        Sexp::Symbol(_) => list(vec![sym("quote"), qp.clone()]),
+        Sexp::List(ref items) => match **items {
+            [ref head, ref p] if is_sym(Some(head), "unquote") => p.clone(),
+            _ => list(items.iter().map(quasi).collect()),
+        },
+        _ => qp.clone(),
+    }
+}
+
+// The variables a pattern binds, added to vars in the order they appear
+fn pattern_variables(pattern: &Sexp, vars: &mut Vec<Rc<str>>) {
+    match *pattern {
+        Sexp::Symbol(ref s) if !["_", "...", "."].contains(&&**s) && !vars.contains(s) => {
+            vars.push(s.clone());
+        },
+        Sexp::List(ref items) => {
+            let head = items.first();
+            if is_sym(head, "quasiquote") && items.len() == 2 {
+                pattern_variables(&quasi(&items[1]), vars);
+            } else if is_sym(head, "?") || is_sym(head, "$") {
+                for item in items.iter().skip(2) {
+                    pattern_variables(item, vars);
+                }
+            } else if is_sym(head, "and") || is_sym(head, "or") {
+                for item in &items[1..] {
+                    pattern_variables(item, vars);
+                }
+            } else if !is_sym(head, "quote") && !is_sym(head, "not") {
+                for item in items.iter() {
+                    pattern_variables(item, vars);
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+// Compiles the patterns of a match form to tests on the value, each given
+// the code to run if it matches and if it doesn't. Pattern variables are
+// bound to temporaries while matching, and to their names only around the
+// body, so that they can't hide the procedures the tests call. Failing
+// calls a thunk, so that the code of the next clause appears only once.
+struct Matcher {
+    count: usize,
+    temps: Vec<(Rc<str>, Sexp)>, // for the variables of the clause
+}
+
+impl Matcher {
+    // The count follows a #, which symbols that are read can't contain, so
+    // that no two temporaries are alike, whatever the variables are named
+    fn fresh(&mut self, name: &str) -> Sexp {
+        self.count += 1;
+        temp(&format!("{}#{}", name, self.count))
+    }
+
+    fn var(&mut self, name: &Rc<str>) -> Sexp {
+        if let Some((_, t)) = self.temps.iter().find(|(var, _)| var == name) {
+            return t.clone();
+        }
+        let t = self.fresh(name);
+        self.temps.push((name.clone(), t.clone()));
+        t
+    }
+
+    // The temporaries of the variables pattern binds
+    fn vars(&mut self, pattern: &Sexp) -> Vec<Sexp> {
+        let mut vars = vec![];
+        pattern_variables(pattern, &mut vars);
+        vars.iter().map(|var| self.var(var)).collect()
+    }
+
+    // Code matching the value of the temporary x against pattern
+    fn pattern(&mut self, x: &Sexp, pattern: &Sexp, ok: Sexp, fail: &Sexp) -> Result<Sexp, EvalError> {
+        let items = match *pattern {
+            Sexp::Symbol(ref s) if &**s == "_" => return Ok(ok),
+            Sexp::Symbol(ref s) if &**s == "..." || &**s == "." => return Err(invalid_pattern(pattern)),
+            Sexp::Symbol(ref s) => return Ok(bind(self.var(s), x.clone(), ok)),
+            Sexp::List(ref items) => items,
+            _ => {
+                let equal = call(sym("equal?"), vec![x.clone(), pattern.clone()]);
+                return Ok(test(equal, ok, fail.clone()));
+            },
+        };
+        let head = items.first();
+        if is_sym(head, "quote") {
+            return Ok(test(call(sym("equal?"), vec![x.clone(), pattern.clone()]), ok, fail.clone()));
+        }
+        if is_sym(head, "quasiquote") {
+            return match items[1..] {
+                [ref qp] => self.pattern(x, &quasi(qp), ok, fail),
+                _ => Err(invalid_pattern(pattern)),
+            };
+        }
+        // (? predicate pattern...)
+        if is_sym(head, "?") && items.len() >= 2 {
+            let ok = self.all(x, &items[2..], ok, fail)?;
+            return Ok(test(call(items[1].clone(), vec![x.clone()]), ok, fail.clone()));
+        }
+        // ($ record-type pattern...), matching the fields in order
+        if is_sym(head, "$") && items.len() >= 2 {
+            let fields = self.fresh("fields");
+            let ok = self.list(&fields, &items[2..], ok, fail)?;
+            let record = call(sym("%match-record"), vec![items[1].clone(), x.clone()]);
+            return Ok(bind(fields.clone(), record, test(fields, ok, fail.clone())));
+        }
+        if is_sym(head, "and") {
+            return self.all(x, &items[1..], ok, fail);
+        }
+        // Each alternative goes on to a procedure taking what it bound
+        if is_sym(head, "or") {
+            let vars = self.vars(pattern);
+            let k = self.fresh("k");
+            let matched = call(k.clone(), vars.clone());
+            let mut code = fail.clone();
+            for alternative in items[1..].iter().rev() {
+                let next = self.fresh("next");
+                let body = self.pattern(x, alternative, matched.clone(), &list(vec![next.clone()]))?;
+                code = bind(next, lambda(list(vec![]), &[code]), body);
+            }
+            return Ok(bind(k, lambda(list(vars), &[ok]), code));
+        }
+        if is_sym(head, "not") {
+            return match items[1..] {
+                [ref p] => {
+                    let k = self.fresh("k");
+                    let body = self.pattern(x, p, fail.clone(), &list(vec![k.clone()]))?;
+                    Ok(bind(k, lambda(list(vec![]), &[ok]), body))
+                },
+                _ => Err(invalid_pattern(pattern)),
+            };
+        }
+        self.list(x, items, ok, fail)
+    }
+
+    // Code matching x against every one of patterns
+    fn all(&mut self, x: &Sexp, patterns: &[Sexp], mut ok: Sexp, fail: &Sexp) -> Result<Sexp, EvalError> {
+        for p in patterns.iter().rev() {
+            ok = self.pattern(x, p, ok, fail)?;
+        }
+        Ok(ok)
+    }
+
+    // Code matching x against the items of a list pattern, which may be
+    // dotted and may have one pattern followed by ...
+    fn list(&mut self, x: &Sexp, items: &[Sexp], ok: Sexp, fail: &Sexp) -> Result<Sexp, EvalError> {
+        match *items {
+            [] => Ok(test(call(sym("null?"), vec![x.clone()]), ok, fail.clone())),
+            [ref dot, ref tail] if is_sym(Some(dot), ".") => self.pattern(x, tail, ok, fail),
+            [ref p, ref ellipsis, ref after @ ..] if is_sym(Some(ellipsis), "...") => {
+                self.repeated(x, p, after, ok, fail)
+            },
+            [ref p, ref rest @ ..] => {
+                if is_sym(Some(p), ".") || is_sym(Some(p), "...") {
+                    return Err(invalid_pattern(&list(items.to_vec())));
+                }
+                let (car, cdr) = (self.fresh("car"), self.fresh("cdr"));
+                let ok = self.list(&cdr, rest, ok, fail)?;
+                let ok = self.pattern(&car, p, ok, fail)?;
+                let ok = bind(cdr, call(sym("cdr"), vec![x.clone()]), ok);
+                let ok = bind(car, call(sym("car"), vec![x.clone()]), ok);
+                Ok(test(call(sym("pair?"), vec![x.clone()]), ok, fail.clone()))
+            },
+        }
+    }
+
+    // (p ... after...): the list is split before its last elements, those
+    // before are matched against p in a loop that collects the values of
+    // its variables into lists, and the rest against after
+    fn repeated(&mut self, x: &Sexp, p: &Sexp, after: &[Sexp], ok: Sexp, fail: &Sexp)
+                -> Result<Sexp, EvalError> {
+        if after.iter().any(|item| is_sym(Some(item), ".")) {
+            return Err(EvalError::from("Expected a proper list after `...` in `match`"));
+        }
+        let (split, tail, each) = (self.fresh("split"), self.fresh("tail"), self.fresh("each"));
+        let (lp, rest, element) = (self.fresh("loop"), self.fresh("rest"), self.fresh("element"));
+        let vars = self.vars(p);
+        let accs: Vec<Sexp> = vars.iter().map(|_| self.fresh("acc")).collect();
+
+        let mut end = self.list(&tail, after, ok, fail)?;
+        for (var, acc) in vars.iter().zip(&accs).rev() {
+            end = bind(var.clone(), acc.clone(), end);
+        }
+        let mut next = vec![call(sym("cdr"), vec![rest.clone()])];
+        next.extend(vars.iter().zip(&accs).map(|(var, acc)| call(sym("cons"), vec![var.clone(), acc.clone()])));
+        let step = self.pattern(&element, p, call(lp.clone(), next), fail)?;
+        let step = bind(element, call(sym("car"), vec![rest.clone()]), step);
+        let mut formals = vec![lp.clone(), rest.clone()];
+        formals.extend(accs.iter().cloned());
+        let define = list(vec![sym("define"), list(formals),
+                               test(call(sym("null?"), vec![rest]), end, step)]);
+        // The elements to repeat come reversed, so that consing puts them back in order
+        let mut start = vec![each.clone()];
+        start.extend(accs.iter().map(|_| list(vec![sym("quote"), list(vec![])])));
+        let body = call(lambda(list(vec![]), &[define, call(lp, start)]), vec![]);
+        let body = bind(each, call(sym("car"), vec![split.clone()]), body);
+        let body = bind(tail, call(sym("cdr"), vec![split.clone()]), body);
+        let count = Sexp::Number(after.len() as f64);
+        let code = test(split.clone(), body, fail.clone());
+        Ok(bind(split, call(sym("%match-split"), vec![x.clone(), count]), code))
+    }
+}
+
+// (match expr (pattern body...)...) =>
+//   ((lambda (#x)
+//      ((lambda (#fail) test of pattern, (#fail) if it fails, else body...)
+//       (lambda () next clause, and finally (%match-failed #x))))
+//    expr)
+fn match_form(items: &[Sexp]) -> Result<Sexp, EvalError> {
+    let (expr, clauses) = match items.split_first() {
+        Some(split) => split,
+        None => return Err(EvalError::from("Expected an expression and (pattern body...) clauses after `match`")),
+    };
+    let mut matcher = Matcher { count: 0, temps: vec![] };
+    let x = matcher.fresh("x");
+    let mut code = call(sym("%match-failed"), vec![x.clone()]);
+    for clause in clauses.iter().rev() {
+        let (pattern, body) = match *clause {
+            Sexp::List(ref parts) if parts.len() >= 2 => (&parts[0], &parts[1..]),
+            _ => return Err(EvalError::from("Expected (pattern body...) clauses after `match`")),
+        };
+        matcher.temps.clear();
+        let temps = matcher.vars(pattern);
+        let vars = matcher.temps.iter().map(|(var, _)| Sexp::Symbol(var.clone())).collect();
+        let fail = matcher.fresh("fail");
+        let ok = call(lambda(list(vars), body), temps);
+        let test = matcher.pattern(&x, pattern, ok, &list(vec![fail.clone()]))?;
+        code = bind(fail, lambda(list(vec![]), &[code]), test);
+    }
+    Ok(bind(x, expr.clone(), code))
+}
+
+// The symbols of a form in lower case, as include-ci reads them
+fn fold_case(sexp: &Sexp) -> Sexp {
+    match *sexp {
+        Sexp::Symbol(ref s) => Sexp::Symbol(Rc::from(s.to_lowercase())),
+        Sexp::List(ref items) => list(items.iter().map(fold_case).collect()),
+        _ => sexp.clone(),
+    }
+}
+
+// The expanded forms of the files an include names, in order
+fn include(interp: &mut Interpreter, name: &str, files: &[Sexp]) -> Result<Vec<Sexp>, EvalError> {
+    if files.is_empty() {
+        return Err(EvalError::Message(format!("Expected file names after `{}`", name)));
+    }
+    let mut forms = vec![];
+    for item in files {
+        let path = match *item {
+            Sexp::String(ref path) => path,
+            _ => return Err(EvalError::Message(format!("Expected file names after `{}`", name))),
+        };
+        let file = interp.open_source(name, path)?;
+        let included = interp.enter_file(&file)?;
+        // Expanded before leaving the file, so its own includes are found
+        // relative to it
+        let expanded = included.iter().map(|&(line, ref sexp)| {
+            let sexp = if name == "include-ci" { fold_case(sexp) } else { sexp.clone() };
+            expand(interp, &sexp).map_err(|e| e.in_file(&file.display_name(), line))
+        }).collect::<Result<Vec<_>, _>>();
+        interp.leave_file();
+        forms.extend(expanded?);
+    }
+    Ok(forms)
+}
+
+/// Rewrites derived syntax into the core forms that both evaluators
+/// implement: quote, define, set!, lambda, if, begin and application.
+/// Files named by include are read here, through the interpreter.
+pub fn expand(interp: &mut Interpreter, sexp: &Sexp) -> Result<Sexp, EvalError> {
+    match *sexp {
+        Sexp::List(ref items) => {
+            if is_sym(items.first(), "quote") {
+                return Ok(sexp.clone());
+            }
+            // (include "file"...) => (begin forms of the files...)
+            // (cond-expand (requirement body...)...) => (begin body...), for
+            // the first requirement the implementation meets
+            if is_sym(items.first(), "cond-expand") {
+                let mut body = vec![sym("begin")];
+                body.extend(library::cond_expand(interp, &items[1..])?.iter().cloned());
+                return expand(interp, &list(body));
+            }
+            if is_sym(items.first(), "define-record-type") {
+                return expand(interp, &record_type(&items[1..])?);
+            }
+            // (receive formals expr body...) =>
+            //   (call-with-values (lambda () expr) (lambda formals body...))
+            if is_sym(items.first(), "receive") {
+                return match items[1..] {
+                    [ref formals, ref expr, ref body @ ..] if !body.is_empty() => {
+                        expand(interp, &call_with_values(expr, lambda(formals.clone(), body)))
+                    },
+                    _ => Err(EvalError::from("Expected formals, an expression and a body after `receive`")),
+                };
+            }
+            // (delay expr) => (%delay (lambda () expr)), and likewise
+            // delay-force; (stream-cons a b) => (%stream-cons (lambda () a) (lambda () b))
+            for &(name, primitive, arity) in &[("delay", "%delay", 1), ("delay-force", "%delay-force", 1),
+                                                ("stream-cons", "%stream-cons", 2)] {
+                if is_sym(items.first(), name) {
+                    if items.len() != arity + 1 {
+                        return Err(EvalError::Message(format!(
+                            "`{}` expected {} {}; was given {}", name, arity,
+                            if arity == 1 { "arg" } else { "args" }, items.len() - 1)));
+                    }
+                    let mut call = vec![sym(primitive)];
+                    call.extend(items[1..].iter().map(|expr| lambda(list(vec![]), std::slice::from_ref(expr))));
+                    return expand(interp, &list(call));
+                }
+            }
+            for name in &["let-values", "let*-values"] {
+                if is_sym(items.first(), name) {
+                    return expand(interp, &let_values(name, &items[1..])?);
+                }
+            }
+            if is_sym(items.first(), "do") {
+                return expand(interp, &do_loop(&items[1..])?);
+            }
+            if is_sym(items.first(), "parameterize") {
+                return expand(interp, &parameterize(&items[1..])?);
+            }
+            if is_sym(items.first(), "define-values") {
+                return expand(interp, &define_values(&items[1..])?);
+            }
+            if is_sym(items.first(), "match") {
+                return expand(interp, &match_form(&items[1..])?);
+            }
+            for name in &["include", "include-ci"] {
+                if is_sym(items.first(), name) {
+                    let mut body = vec![sym("begin")];
+                    body.extend(include(interp, name, &items[1..])?);
+                    return Ok(list(body));
+                }
+            }
+            // (define (name . params) body...) => (define name (lambda params body...))
+            if is_sym(items.first(), "define") {
+                if let Some(Sexp::List(signature)) = items.get(1) {
+                    let (name, params) = match signature.split_first() {
+                        Some((name @ Sexp::Symbol(_), params)) => (name, params),
+                        _ => return Err(EvalError::from("Expected symbol after `define`")),
+                    };
+                    // (f . args) leaves (. args), which means just args
+                    let params = match params {
+                        [dot, rest] if is_sym(Some(dot), ".") => rest.clone(),
+                        _ => Sexp::List(params.into()),
+                    };
+                    let mut lambda = vec![sym("lambda"), params];
+                    lambda.extend(items[2..].iter().cloned());
+                    let define = vec![sym("define"), name.clone(), Sexp::List(lambda.into())];
+                    return expand(interp, &Sexp::List(define.into()));
+                }
+            }
+            // (guard (var clause...) body...) =>
+            //   (%guard (lambda () body...) (lambda (var) clauses, else (raise var)))
+            if is_sym(items.first(), "guard") {
+                let (var, handlers) = match items.get(1) {
+                    Some(Sexp::List(spec)) => match spec.split_first() {
+                        Some((var @ Sexp::Symbol(_), handlers)) => (var, handlers),
+                        _ => return Err(EvalError::from("Expected (variable clause...) after `guard`")),
+                    },
+                    _ => return Err(EvalError::from("Expected (variable clause...) after `guard`")),
+                };
+                let reraise = list(vec![sym("raise"), var.clone()]);
+                let handler = list(vec![sym("lambda"), list(vec![var.clone()]), clauses(handlers, reraise)?]);
+                let mut body = vec![sym("lambda"), list(vec![])];
+                body.extend(items[2..].iter().cloned());
+                return expand(interp, &list(vec![sym("%guard"), list(body), handler]));
+            }
+            let items = items.iter().map(|item| expand(interp, item)).collect::<Result<Vec<_>, _>>()?;
+            Ok(Sexp::List(items.into()))
+        },
+        _ => Ok(sexp.clone()),
+    }
+}
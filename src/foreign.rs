@@ -0,0 +1,99 @@
+use std::any::{self, Any};
+use std::fmt;
+use std::rc::Rc;
+use eval::SValue;
+use native::{FromSValue, IntoSValue};
+
+type Finalizer = dyn FnOnce(&dyn Any);
+
+/// A Rust value handed to Scheme code, which can only pass it around and
+/// give it back. The type name is used when printing it, as `#<name>`.
+pub struct Foreign {
+    type_name: String,
+    value: Rc<dyn Any>,
+    finalizer: Option<Box<Finalizer>>,
+}
+
+impl Foreign {
+    pub fn new<T: Any>(type_name: &str, value: T) -> Foreign {
+        Foreign::from_rc(type_name, Rc::new(value))
+    }
+
+    /// Wraps a value that the host keeps sharing with Scheme code.
+    pub fn from_rc<T: Any>(type_name: &str, value: Rc<T>) -> Foreign {
+        Foreign { type_name: String::from(type_name), value, finalizer: None }
+    }
+
+    /// Sets a procedure to run on the value once Scheme code can no longer
+    /// reach it, whether or not the host still holds it.
+    pub fn with_finalizer<T: Any, F: FnOnce(&T) + 'static>(mut self, f: F) -> Foreign {
+        self.finalizer = Some(Box::new(move |value: &dyn Any| {
+            if let Some(value) = value.downcast_ref::<T>() {
+                f(value);
+            }
+        }));
+        self
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.value.is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref::<T>()
+    }
+
+    pub fn downcast<T: Any>(&self) -> Option<Rc<T>> {
+        self.value.clone().downcast::<T>().ok()
+    }
+}
+
+impl Drop for Foreign {
+    fn drop(&mut self) {
+        if let Some(finalize) = self.finalizer.take() {
+            finalize(&*self.value);
+        }
+    }
+}
+
+impl fmt::Debug for Foreign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<{}>", self.type_name)
+    }
+}
+
+impl SValue {
+    pub fn foreign<T: Any>(type_name: &str, value: T) -> SValue {
+        SValue::Foreign(Rc::new(Foreign::new(type_name, value)))
+    }
+
+    /// The host value inside a foreign object, if it has type `T`.
+    pub fn downcast<T: Any>(&self) -> Option<Rc<T>> {
+        match *self {
+            SValue::Foreign(ref obj) => obj.downcast::<T>(),
+            _ => None,
+        }
+    }
+
+    pub fn is_foreign<T: Any>(&self) -> bool {
+        match *self {
+            SValue::Foreign(ref obj) => obj.is::<T>(),
+            _ => false,
+        }
+    }
+}
+
+impl IntoSValue for Foreign {
+    fn into_svalue(self) -> SValue { SValue::Foreign(Rc::new(self)) }
+}
+
+// Native closures can take host values directly as parameters of type Rc<T>
+impl<'a, T: Any> FromSValue<'a> for Rc<T> {
+    fn from_svalue(v: &'a SValue) -> Result<Rc<T>, String> {
+        v.downcast::<T>().ok_or_else(|| format!("a foreign {}", any::type_name::<T>()))
+    }
+}
@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+/// An object on the interpreter heap that may take part in a reference
+/// cycle, such as a pair or a scope.
+pub trait Trace {
+    /// Pushes the address of every tracked object this one refers to,
+    /// once per reference. Returns false if the object is in use and
+    /// could not be inspected.
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool;
+
+    /// Drops every reference this object holds, breaking the cycles it is in.
+    fn clear(&self);
+
+    /// How much the object counts toward a heap limit: one, plus any
+    /// elements it holds.
+    fn size(&self) -> usize { 1 }
+}
+
+/// Counts of tracked heap objects, as returned by `Interpreter::heap_stats`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub live: usize,        // objects alive as of the last collection or allocation
+    pub allocated: usize,   // objects ever allocated
+    pub collections: usize,
+    pub reclaimed: usize,   // objects freed by collections rather than refcounting
+}
+
+// Objects are tagged with the owner allocating them: the interpreter whose
+// evaluation is in progress, or 0 outside any
+struct Heap {
+    objects: Vec<(usize, Weak<dyn Trace>)>,
+    owned: HashMap<usize, usize>, // the total size of the objects tagged with each owner
+    owner: usize,
+    owners: usize, // the number handed out
+    threshold: usize, // collect when this many objects are tracked
+    stats: HeapStats,
+}
+
+impl Heap {
+    // Forgets the objects that have been freed
+    fn prune(&mut self) {
+        self.objects.retain(|(_, w)| w.strong_count() > 0);
+        self.owned.clear();
+        for (owner, w) in &self.objects {
+            let size = w.upgrade().map_or(0, |obj| obj.size());
+            *self.owned.entry(*owner).or_insert(0) += size;
+        }
+    }
+}
+
+const MIN_THRESHOLD: usize = 4096;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        objects: vec![],
+        owned: HashMap::new(),
+        owner: 0,
+        owners: 0,
+        threshold: MIN_THRESHOLD,
+        stats: HeapStats::default(),
+    });
+}
+
+/// A new owner to tag objects with, for an interpreter to count its own.
+pub fn new_owner() -> usize {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.owners += 1;
+        heap.owners
+    })
+}
+
+/// Tags objects allocated from now on with owner, returning the one
+/// they were tagged with before.
+pub fn set_owner(owner: usize) -> usize {
+    HEAP.with(|heap| mem::replace(&mut heap.borrow_mut().owner, owner))
+}
+
+pub fn address<T: ?Sized>(rc: &Rc<T>) -> *const () {
+    Rc::as_ptr(rc) as *const ()
+}
+
+/// Registers a newly allocated object with the collector, which runs once
+/// enough objects have accumulated since the last collection.
+pub fn track<T: Trace + 'static>(rc: &Rc<T>) {
+    let weak: Weak<dyn Trace> = Rc::downgrade(rc) as Weak<dyn Trace>;
+    let full = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        let owner = heap.owner;
+        heap.objects.push((owner, weak));
+        *heap.owned.entry(owner).or_insert(0) += rc.size();
+        heap.stats.allocated += 1;
+        heap.objects.len() >= heap.threshold
+    });
+    if full {
+        collect();
+    }
+}
+
+/// The total size of the objects registered with owner's tag, counting any
+/// freed since the last collection; a quick upper bound on the size live.
+pub fn tracked_by(owner: usize) -> usize {
+    HEAP.with(|heap| heap.borrow().owned.get(&owner).cloned().unwrap_or(0))
+}
+
+/// The total size of the live objects tagged with owner.
+pub fn live_by(owner: usize) -> usize {
+    HEAP.with(|heap| {
+        heap.borrow().objects.iter()
+            .filter(|(o, _)| *o == owner)
+            .filter_map(|(_, w)| w.upgrade())
+            .map(|obj| obj.size())
+            .sum()
+    })
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        let mut stats = heap.stats;
+        stats.live = heap.objects.iter().filter(|(_, w)| w.strong_count() > 0).count();
+        stats
+    })
+}
+
+/// Frees every tracked object that is only reachable from other tracked
+/// objects, returning how many there were.
+///
+/// Nothing needs to be registered as a root: an object whose reference
+/// count exceeds the references found by tracing the heap is held from
+/// outside it, whether by the global scope, a frame being evaluated, or a
+/// value the host program kept, and so is live along with everything it
+/// refers to.
+pub fn collect() -> usize {
+    let objects: Vec<Rc<dyn Trace>> = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.prune();
+        heap.objects.iter().filter_map(|(_, w)| w.upgrade()).collect()
+    });
+    let index: HashMap<*const (), usize> = objects.iter().enumerate()
+        .map(|(i, obj)| (address(obj), i))
+        .collect();
+
+    // External references: the count less our own, less those from the heap
+    let mut external: Vec<isize> = objects.iter()
+        .map(|obj| Rc::strong_count(obj) as isize - 1)
+        .collect();
+    let mut children: Vec<Vec<usize>> = Vec::with_capacity(objects.len());
+    let mut out = vec![];
+    for (i, obj) in objects.iter().enumerate() {
+        out.clear();
+        if !obj.trace(&mut out) {
+            external[i] += 1; // can't see inside, so keep it and what it holds
+        }
+        let kids: Vec<usize> = out.iter().filter_map(|a| index.get(a).cloned()).collect();
+        for &k in &kids {
+            external[k] -= 1;
+        }
+        children.push(kids);
+    }
+
+    let mut live = vec![false; objects.len()];
+    let mut stack: Vec<usize> = (0..objects.len()).filter(|&i| external[i] > 0).collect();
+    while let Some(i) = stack.pop() {
+        if !live[i] {
+            live[i] = true;
+            stack.extend(children[i].iter().filter(|&&k| !live[k]));
+        }
+    }
+
+    let mut reclaimed = 0;
+    for (obj, &is_live) in objects.iter().zip(live.iter()) {
+        if !is_live {
+            obj.clear();
+            reclaimed += 1;
+        }
+    }
+    drop(objects); // frees the garbage, now that its cycles are broken
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.prune();
+        heap.threshold = MIN_THRESHOLD.max(2 * heap.objects.len());
+        heap.stats.collections += 1;
+        heap.stats.reclaimed += reclaimed;
+    });
+    reclaimed
+}
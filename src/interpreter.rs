@@ -0,0 +1,565 @@
+use std::any::Any;
+use std::fs;
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use analyze;
+use builtins;
+use compile;
+use eval;
+use eval::{Env, ErrorKind, ErrorObject, EvalError, Limit, SValue, SymTable};
+use expand;
+use gc;
+use gc::HeapStats;
+use library;
+use library::Libraries;
+use native::{Arity, IntoNative, Native};
+use parse;
+use parse::Sexp;
+use port::Ports;
+use sandbox::Capabilities;
+use vm;
+
+/// The text of a file to load or include, with the path scripts know it
+/// by and the one it was read from, which differ in a sandbox.
+pub(crate) struct SourceFile {
+    name: PathBuf,
+    path: PathBuf,
+    text: String,
+}
+
+impl SourceFile {
+    pub(crate) fn display_name(&self) -> String {
+        self.name.display().to_string()
+    }
+}
+
+/// How an `Interpreter` runs the code it is given. Both give the same
+/// results, and procedures made by one may be called from the other.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Engine {
+    /// Compile to bytecode for a stack machine. The default.
+    Bytecode,
+    /// Evaluate the expression tree directly; slower, but simpler, and
+    /// kept as the reference for what the compiler should do.
+    TreeWalker,
+}
+
+/// Bounds on the resources a single evaluation may use, for running
+/// untrusted code. Each but `stack` is unlimited when `None`. Going over a
+/// limit stops the evaluation with `EvalError::Limit`, leaving the
+/// interpreter usable.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Limits {
+    /// Evaluation steps: expressions for the tree-walker, instructions
+    /// for the bytecode engine. Primitives that go over a whole list or
+    /// structure, such as `length`, `iota`, `equal?`, the sorts and `write`,
+    /// take a step for each element, so that limits and interrupts reach
+    /// them too; others, such as `list->vector`, run to the end.
+    pub fuel: Option<u64>,
+    /// Procedure calls in progress at once. Tail calls made by the
+    /// bytecode engine don't add to the depth.
+    pub depth: Option<usize>,
+    /// Live pairs, scopes, procedures and vector elements allocated by this
+    /// interpreter's evaluations, counted after collecting garbage.
+    /// Procedures that make vectors, lists or strings are charged for their
+    /// size before making them, so that none can go far past the limit; a
+    /// string counts its length then, but isn't counted once made.
+    pub heap: Option<usize>,
+    pub time: Option<Duration>,
+    /// Bytes of native stack an evaluation may use. The tree-walker
+    /// recurses on the native stack for each call, as do natives such as
+    /// `map` that call back into Scheme, so a call that would use more
+    /// fails with `Limit::Depth` instead of overflowing the stack. When
+    /// `None`, 1 MiB, which leaves room to spare on a thread with Rust's
+    /// default 2 MiB stack; a host evaluating on a thread with a larger
+    /// stack can allow more. Each MiB allows a depth of about 1200 on the
+    /// tree-walker in a release build, and 250 in a debug one; recursion
+    /// through `map` goes about 500 deep on either engine in a release
+    /// build, and 100 in a debug one.
+    pub stack: Option<usize>,
+}
+
+const DEFAULT_STACK: usize = 1 << 20;
+
+// The address of a local variable, which is further from where evaluation
+// started the more of the native stack is in use
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    &marker as *const u8 as usize
+}
+
+/// Stops the evaluation an interpreter is running, from another thread or
+/// a signal handler. Obtained from `Interpreter::interrupt_handle`.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes the evaluation in progress fail with `EvalError::Interrupted`
+    /// at its next safe point, or if there is none, the next to start.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Withdraws an interrupt that hasn't yet stopped an evaluation.
+    pub fn clear(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// How often, in steps, the limits other than depth, and interrupts, are checked
+const CHECK_INTERVAL: u64 = 1024;
+
+// What the evaluation in progress has used
+#[derive(Default)]
+struct Usage {
+    entries: usize, // nested calls into the interpreter from the host
+    steps: u64,
+    check_at: u64, // the step at which to next check the limits
+    depth: usize,
+    deadline: Option<Instant>,
+    stack_base: usize, // the stack address where the evaluation started
+}
+
+/// A Scheme session: a global scope that persists across evaluations.
+pub struct Interpreter {
+    globals: Env,
+    engine: Engine,
+    capabilities: Capabilities,
+    limits: Limits,
+    usage: Usage,
+    interrupt: InterruptHandle,
+    ports: Ports,
+    handlers: Vec<Option<SValue>>, // innermost last; None where a guard is
+    primitives: Vec<(Rc<str>, SValue)>, // the bindings builtins made, for report environments
+    files: Vec<(PathBuf, PathBuf)>, // being loaded or included, innermost last, by name and real path
+    libraries: Libraries,
+    heap_owner: usize, // what gc tags the objects this interpreter allocates with
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::with_engine(Engine::Bytecode)
+    }
+
+    pub fn with_engine(engine: Engine) -> Interpreter {
+        let mut interp = Interpreter::with_capabilities(Capabilities::all());
+        interp.engine = engine;
+        interp
+    }
+
+    /// Creates an interpreter whose scripts have only the primitives that
+    /// `capabilities` allow, for running code that isn't trusted:
+    ///
+    /// ```
+    /// # use scheme::{Capabilities, FileAccess, Interpreter};
+    /// let caps = Capabilities { files: FileAccess::ReadOnly("data".into()), ..Capabilities::pure() };
+    /// let mut interp = Interpreter::with_capabilities(caps);
+    /// assert!(interp.eval_str("(exit)").is_err());
+    /// ```
+    ///
+    /// Procedures the host defines later are available regardless; those
+    /// that touch files should check paths with `Capabilities::file_path`.
+    pub fn with_capabilities(capabilities: Capabilities) -> Interpreter {
+        let mut interp = Interpreter {
+            globals: SymTable::empty().into_env(),
+            engine: Engine::Bytecode,
+            capabilities: capabilities.clone(),
+            limits: Limits::default(),
+            usage: Usage::default(),
+            interrupt: InterruptHandle(Arc::new(AtomicBool::new(false))),
+            ports: Ports::new(capabilities.console),
+            handlers: vec![],
+            primitives: vec![],
+            files: vec![],
+            libraries: Libraries::new(),
+            heap_owner: gc::new_owner(),
+        };
+        builtins::install(&mut interp, &capabilities);
+        interp.primitives = interp.globals.borrow().bindings();
+        interp
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Sets the limits on each later evaluation: each call to `eval`,
+    /// `eval_str`, `eval_file` or `call` made by the host.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// A handle that can stop this interpreter's evaluations from elsewhere,
+    /// such as another thread or a handler for Ctrl-C.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// The current input, output and error ports.
+    pub(crate) fn ports(&mut self) -> &mut Ports {
+        &mut self.ports
+    }
+
+    /// The exception handlers in effect, innermost last.
+    pub(crate) fn handlers(&mut self) -> &mut Vec<Option<SValue>> {
+        &mut self.handlers
+    }
+
+    pub(crate) fn libraries(&mut self) -> &mut Libraries {
+        &mut self.libraries
+    }
+
+    pub(crate) fn primitives(&self) -> &[(Rc<str>, SValue)] {
+        &self.primitives
+    }
+
+    /// The directories `import` looks in for a library it doesn't know,
+    /// with `(foo bar)` in `foo/bar.sld`. Relative ones are taken from the
+    /// directory of the program being run; by default that is the only one.
+    pub fn library_path(&self) -> &[PathBuf] {
+        &self.libraries.path
+    }
+
+    pub fn set_library_path(&mut self, path: Vec<PathBuf>) {
+        self.libraries.path = path;
+    }
+
+    // Runs f as one evaluation, starting the count of what it uses unless
+    // this is a call back into the interpreter from within an evaluation
+    fn enter<F>(&mut self, f: F) -> Result<SValue, EvalError>
+        where F: FnOnce(&mut Interpreter) -> Result<SValue, EvalError> {
+        if self.usage.entries == 0 {
+            self.usage = Usage {
+                deadline: self.limits.time.map(|time| Instant::now() + time),
+                stack_base: stack_address(),
+                ..Usage::default()
+            };
+        }
+        self.usage.entries += 1;
+        let owner = gc::set_owner(self.heap_owner);
+        let result = f(self);
+        gc::set_owner(owner);
+        self.usage.entries -= 1;
+        if self.usage.entries == 0 {
+            // One made too late to stop this evaluation isn't meant for the next
+            self.interrupt.0.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// Counts a step of evaluation, failing if a limit has been reached.
+    pub(crate) fn step(&mut self) -> Result<(), EvalError> {
+        self.usage.steps += 1;
+        if self.usage.steps < self.usage.check_at {
+            return Ok(());
+        }
+        if self.interrupt.0.swap(false, Ordering::SeqCst) {
+            return Err(EvalError::Interrupted);
+        }
+        if self.limits.fuel.is_some_and(|fuel| self.usage.steps > fuel) {
+            return Err(EvalError::Limit(Limit::Fuel));
+        }
+        if self.usage.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(EvalError::Limit(Limit::Time));
+        }
+        self.allocate(0)?;
+        let usage = &mut self.usage;
+        usage.check_at = usage.steps + CHECK_INTERVAL;
+        if let Some(fuel) = self.limits.fuel {
+            usage.check_at = usage.check_at.min(fuel + 1);
+        }
+        Ok(())
+    }
+
+    /// Checks that n more objects or elements can be allocated without
+    /// going past the heap limit, for natives to call before making them.
+    pub(crate) fn allocate(&mut self, n: usize) -> Result<(), EvalError> {
+        if let Some(heap) = self.limits.heap {
+            let owner = self.heap_owner;
+            let over = |used: usize| used.saturating_add(n) > heap;
+            if over(gc::tracked_by(owner)) && { gc::collect(); over(gc::live_by(owner)) } {
+                return Err(EvalError::Limit(Limit::Heap));
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the start of a procedure call, failing if it would go
+    /// deeper than the limit allows, or use too much of the native stack.
+    pub(crate) fn push_call(&mut self) -> Result<(), EvalError> {
+        if self.limits.depth.is_some_and(|depth| self.usage.depth >= depth) {
+            return Err(EvalError::Limit(Limit::Depth));
+        }
+        // The stack grows down on the platforms Rust supports
+        let stack = self.limits.stack.unwrap_or(DEFAULT_STACK);
+        if self.usage.stack_base.saturating_sub(stack_address()) > stack {
+            return Err(EvalError::Limit(Limit::Depth));
+        }
+        self.usage.depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn pop_call(&mut self) {
+        self.usage.depth -= 1;
+    }
+
+    pub(crate) fn call_depth(&self) -> usize {
+        self.usage.depth
+    }
+
+    pub(crate) fn set_call_depth(&mut self, depth: usize) {
+        self.usage.depth = depth;
+    }
+
+    /// Evaluates a single parsed expression in the global scope.
+    pub fn eval(&mut self, sexp: Sexp) -> Result<SValue, EvalError> {
+        self.enter(|interp| {
+            let globals = interp.globals.clone();
+            interp.eval_in(&globals, &sexp)
+        })
+    }
+
+    /// Evaluates an expression at the top level of env, as part of the
+    /// evaluation in progress.
+    pub(crate) fn eval_in(&mut self, env: &Env, sexp: &Sexp) -> Result<SValue, EvalError> {
+        if let Some(result) = library::eval_toplevel(self, env, sexp) {
+            return result;
+        }
+        let sexp = expand::expand(self, sexp)?;
+        match self.engine {
+            Engine::Bytecode => {
+                let template = compile::compile(&analyze::analyze(self, env, &sexp)?);
+                vm::run(self, Rc::new(template), env.clone())
+            },
+            Engine::TreeWalker => eval::eval(self, env, &sexp),
+        }
+    }
+
+    pub(crate) fn globals(&self) -> Env {
+        self.globals.clone()
+    }
+
+    /// A new scope holding only the procedures the interpreter was made
+    /// with, as the capabilities allowed.
+    pub(crate) fn report_environment(&self) -> Env {
+        SymTable::from(self.primitives.clone()).into_env()
+    }
+
+    /// Evaluates every expression in `src`, returning the value of the last.
+    pub fn eval_str(&mut self, src: &str) -> Result<SValue, EvalError> {
+        let forms = match parse::read_all(src) {
+            Ok(forms) => forms,
+            Err(e) => return Err(EvalError::Message(format!("Parse error: {}", e))),
+        };
+        self.enter(|interp| {
+            let mut result = SValue::nil();
+            for sexp in forms {
+                result = interp.eval(sexp)?;
+            }
+            Ok(result)
+        })
+    }
+
+    /// Reads and evaluates a program from a file, as with `eval_source`.
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<SValue, EvalError> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(src) => self.eval_source(&src, path),
+            Err(e) => Err(EvalError::Message(
+                format!("Cannot read {}: {}", path.display(), e))),
+        }
+    }
+
+    /// Evaluates every expression in `src`, the text of the file at `path`,
+    /// returning the value of the last. Errors give the file and line they
+    /// came from, and files the program loads or includes are found
+    /// relative to the file's directory.
+    pub fn eval_source<P: AsRef<Path>>(&mut self, src: &str, path: P) -> Result<SValue, EvalError> {
+        let file = SourceFile {
+            name: path.as_ref().to_path_buf(),
+            path: path.as_ref().to_path_buf(),
+            text: String::from(src),
+        };
+        self.enter(|interp| {
+            let globals = interp.globals.clone();
+            interp.load(&file, &globals)
+        })
+    }
+
+    /// Reads the file a script names for `what`, such as `load`, finding
+    /// a relative path from the directory of the file being loaded.
+    pub(crate) fn open_source(&self, what: &str, path: &str) -> Result<SourceFile, EvalError> {
+        let name = match self.files.last().and_then(|(name, _)| name.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        self.read_source(what, name).map_err(|message| {
+            EvalError::Raise(ErrorObject::value(ErrorKind::File, message, vec![]))
+        })
+    }
+
+    /// Reads the file that would define a library, from the first
+    /// directory of the library path that has it.
+    pub(crate) fn find_library_file(&self, path: &str) -> Option<SourceFile> {
+        let program = self.program_dir();
+        self.libraries.path.iter().find_map(|dir| {
+            self.read_source("import", program.join(dir).join(path)).ok()
+        })
+    }
+
+    /// The directory of the program being run, which is the current one
+    /// outside of any file.
+    pub(crate) fn program_dir(&self) -> PathBuf {
+        match self.libraries.programs.last() {
+            Some(dir) => dir.clone(),
+            None => match self.files.first().and_then(|(name, _)| name.parent()) {
+                Some(dir) => dir.to_path_buf(),
+                None => PathBuf::new(),
+            },
+        }
+    }
+
+    fn read_source(&self, what: &str, name: PathBuf) -> Result<SourceFile, String> {
+        let path = self.capabilities.file_path(&name.to_string_lossy(), false)?;
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(SourceFile { name, path, text }),
+            Err(e) => Err(format!("`{}` cannot read {}: {}", what, name.display(), e)),
+        }
+    }
+
+    /// Parses a file, making it the one that relative paths are found from
+    /// until `leave_file`. Fails if the file is already being loaded or
+    /// included, as it would then never finish.
+    pub(crate) fn enter_file(&mut self, file: &SourceFile) -> Result<Vec<(usize, Sexp)>, EvalError> {
+        let real = fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone());
+        if let Some(start) = self.files.iter().position(|(_, path)| *path == real) {
+            let mut cycle: Vec<String> = self.files[start..].iter()
+                .map(|(name, _)| name.display().to_string())
+                .collect();
+            cycle.push(file.name.display().to_string());
+            return Err(EvalError::Message(
+                format!("Files load or include themselves: {}", cycle.join(" -> "))));
+        }
+        let forms = parse::read_all_lines(&file.text).map_err(|(line, e)| {
+            EvalError::Message(format!("Parse error: {}", e)).in_file(&file.display_name(), line)
+        })?;
+        self.files.push((file.name.clone(), real));
+        Ok(forms)
+    }
+
+    pub(crate) fn leave_file(&mut self) {
+        self.files.pop();
+    }
+
+    /// Evaluates the forms of a file at the top level of env, stopping at
+    /// the first that fails.
+    pub(crate) fn load(&mut self, file: &SourceFile, env: &Env) -> Result<SValue, EvalError> {
+        let forms = self.enter_file(file)?;
+        let mut result = Ok(SValue::nil());
+        for (line, sexp) in forms {
+            result = self.eval_in(env, &sexp).map_err(|e| e.in_file(&file.display_name(), line));
+            if result.is_err() {
+                break;
+            }
+        }
+        self.leave_file();
+        result
+    }
+
+    pub fn define_global(&mut self, name: &str, val: SValue) {
+        self.globals.borrow_mut().assign(name, val);
+    }
+
+    pub fn lookup_global(&self, name: &str) -> Option<SValue> {
+        self.globals.borrow().lookup(name)
+    }
+
+    /// Defines a global procedure implemented by a Rust closure. Arguments
+    /// are converted with `FromSValue` and checked against the closure's
+    /// parameter types, and the result is converted with `IntoSValue`:
+    ///
+    /// ```
+    /// # use scheme::{Interpreter, Rest};
+    /// let mut interp = Interpreter::new();
+    /// interp.define_fn("repeat", |s: String, n: usize| s.repeat(n));
+    /// interp.define_fn("sum", |Rest(xs): Rest<f64>| xs.iter().sum::<f64>());
+    /// ```
+    ///
+    /// Parameters must be owned types (`String` rather than `&str`); a
+    /// `define_native` procedure can borrow from its arguments instead, with
+    /// `FromSValue::from_svalue`.
+    /// Trailing `Option` parameters may be left out of calls, and a final
+    /// `Rest` parameter collects any further arguments. A closure returning
+    /// `Result` reports its `Err` as an evaluation error.
+    pub fn define_fn<F, Args>(&mut self, name: &str, f: F) where F: IntoNative<Args> {
+        let native = f.into_native(name);
+        self.define_global(name, SValue::Native(Rc::new(native)));
+    }
+
+    /// Defines a global procedure that takes its arguments unconverted and
+    /// may use the interpreter, for instance to `call` procedures it is given.
+    pub fn define_native<F>(&mut self, name: &str, arity: Arity, f: F)
+        where F: Fn(&mut Interpreter, Vec<SValue>) -> Result<SValue, EvalError> + 'static {
+        let native = Native::new(name, arity, f);
+        self.define_global(name, SValue::Native(Rc::new(native)));
+    }
+
+    /// Defines a predicate recognizing foreign objects holding a `T`.
+    pub fn define_foreign_predicate<T: Any>(&mut self, name: &str) {
+        self.define_fn(name, |x: SValue| x.is_foreign::<T>());
+    }
+
+    /// Frees unreachable cyclic structures, such as closures stored in the
+    /// scope they were defined in, returning the number of objects freed.
+    /// Values the host holds are kept, along with what they refer to.
+    /// Collection also happens automatically as the heap grows.
+    pub fn gc(&mut self) -> usize {
+        gc::collect()
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        gc::stats()
+    }
+
+    /// Applies a procedure value, such as one found with `lookup_global`.
+    pub fn call(&mut self, procedure: &SValue, args: Vec<SValue>) -> Result<SValue, EvalError> {
+        self.enter(|interp| eval::apply(interp, procedure.clone(), args))
+    }
+
+    /// Sets the list returned by `(command-line)`: the program name
+    /// followed by its arguments.
+    pub fn set_command_line(&mut self, args: Vec<String>) {
+        self.define_fn("command-line", move || args.clone());
+    }
+}
+
+// Procedures defined at top level refer back to the global scope, so the
+// cycle is broken here to let the values in it, and their finalizers, go.
+// Files still open are flushed, in case cycles keep them from being dropped.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.ports.flush_all();
+        self.globals.borrow_mut().clear();
+        self.libraries.clear();
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter { Interpreter::new() }
+}
@@ -0,0 +1,44 @@
+//! An interpreter for a subset of Scheme, usable as a scripting or
+//! configuration language inside other Rust programs.
+//!
+//! ```
+//! use scheme::{Interpreter, SValue};
+//!
+//! let mut interp = Interpreter::new();
+//! interp.define_global("width", SValue::Number(6.0));
+//! interp.eval_str("(define area (lambda (h) (* width h)))").unwrap();
+//! let area = interp.lookup_global("area").unwrap();
+//! let result = interp.call(&area, vec![SValue::Number(7.0)]).unwrap();
+//! assert_eq!(result, SValue::Number(42.0));
+//! ```
+
+pub mod parse;
+pub mod util;
+mod analyze;
+mod builtins;
+mod compile;
+mod eval;
+mod expand;
+mod foreign;
+mod gc;
+mod interpreter;
+mod library;
+mod native;
+mod parameter;
+mod port;
+mod promise;
+mod record;
+mod sandbox;
+mod vector;
+mod vm;
+
+pub use eval::{ErrorKind, ErrorObject, EvalError, Limit, Located, Pair, SValue};
+pub use foreign::Foreign;
+pub use gc::HeapStats;
+pub use interpreter::{Engine, Interpreter, InterruptHandle, Limits};
+pub use native::{Arity, FromSValue, IntoNative, IntoSValue, Native, NativeReturn, Rest};
+pub use parameter::Parameter;
+pub use promise::Promise;
+pub use record::{Record, RecordType};
+pub use sandbox::{Capabilities, FileAccess};
+pub use vector::Vector;
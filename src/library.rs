@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::env::consts;
+use std::path::PathBuf;
+use std::rc::Rc;
+use builtins;
+use eval::{Env, EvalError, SValue, SymTable};
+use interpreter::Interpreter;
+use parse::Sexp;
+
+type Bindings = Vec<(Rc<str>, SValue)>;
+
+/// The libraries an interpreter knows, by name, and the directories it looks
+/// in for those it doesn't.
+pub struct Libraries {
+    defined: HashMap<String, Rc<Bindings>>, // what each library exports
+    scopes: Vec<Env>, // those the libraries were defined in, cleared with the interpreter
+    pub path: Vec<PathBuf>,
+    // For each library file being loaded, the program directory it was
+    // found from, so that its own imports are found from there too
+    pub programs: Vec<PathBuf>,
+}
+
+impl Libraries {
+    pub fn new() -> Libraries {
+        // The empty path is the directory of the program
+        Libraries { defined: HashMap::new(), scopes: vec![], path: vec![PathBuf::new()], programs: vec![] }
+    }
+
+    pub fn clear(&mut self) {
+        self.defined.clear();
+        for scope in self.scopes.drain(..) {
+            scope.borrow_mut().clear();
+        }
+    }
+}
+
+/// The feature identifiers `cond-expand` tests for.
+pub fn features() -> Vec<String> {
+    let mut features = vec![String::from("r7rs"), String::from("full-unicode")];
+    features.extend([consts::FAMILY, consts::OS, consts::ARCH].iter().map(|&f| String::from(f)));
+    features
+}
+
+fn symbol(sexp: &Sexp) -> Option<&str> {
+    match *sexp {
+        Sexp::Symbol(ref s) => Some(s),
+        _ => None,
+    }
+}
+
+// The parts of a library name, such as (foo bar 1), which must be symbols
+// and whole numbers
+fn name_parts(name: &Sexp) -> Result<Vec<String>, EvalError> {
+    let parts = match *name {
+        Sexp::List(ref parts) if !parts.is_empty() => parts,
+        _ => return Err(EvalError::Message(format!("Expected a library name; found {:?}", name))),
+    };
+    parts.iter().map(|part| match *part {
+        Sexp::Symbol(ref s) => Ok(s.to_string()),
+        Sexp::Number(n) if n >= 0.0 && n.fract() == 0.0 => Ok(n.to_string()),
+        _ => Err(EvalError::Message(format!("Expected a library name; found {:?}", name))),
+    }).collect()
+}
+
+fn key(parts: &[String]) -> String {
+    format!("({})", parts.join(" "))
+}
+
+// The primitives of one of builtins::LIBRARIES
+fn standard(interp: &Interpreter, key: &str) -> Option<Bindings> {
+    if !builtins::LIBRARIES.contains(&key) {
+        return None;
+    }
+    let r5rs = key == "(scheme r5rs)";
+    Some(interp.primitives().iter().filter(|&(name, _)| {
+        let library = builtins::library_of(name);
+        library == key || r5rs && library.starts_with("(scheme ")
+    }).cloned().collect())
+}
+
+// What a library exports, loading the file it is defined in if it isn't
+// known yet
+fn find(interp: &mut Interpreter, name: &Sexp) -> Result<Rc<Bindings>, EvalError> {
+    let parts = name_parts(name)?;
+    let key = key(&parts);
+    if let Some(library) = interp.libraries().defined.get(&key) {
+        return Ok(library.clone());
+    }
+    if let Some(library) = standard(interp, &key) {
+        return Ok(Rc::new(library));
+    }
+    let file = match interp.find_library_file(&format!("{}.sld", parts.join("/"))) {
+        Some(file) => file,
+        None => return Err(EvalError::Message(format!("Found no library {}", key))),
+    };
+    // Whatever else the file defines stays out of the program
+    let program = interp.program_dir();
+    interp.libraries().programs.push(program);
+    let loaded = interp.load(&file, &SymTable::empty().into_env());
+    interp.libraries().programs.pop();
+    loaded?;
+    match interp.libraries().defined.get(&key) {
+        Some(library) => Ok(library.clone()),
+        None => Err(EvalError::Message(format!(
+            "{} does not define the library {}", file.display_name(), key))),
+    }
+}
+
+fn identifiers<'a>(what: &str, items: &'a [Sexp]) -> Result<Vec<&'a str>, EvalError> {
+    items.iter().map(|item| symbol(item).ok_or_else(|| {
+        EvalError::Message(format!("`{}` expected identifiers; found {:?}", what, item))
+    })).collect()
+}
+
+fn check_imported(bindings: &Bindings, name: &str) -> Result<(), EvalError> {
+    if bindings.iter().any(|(bound, _)| &**bound == name) {
+        Ok(())
+    } else {
+        Err(EvalError::Message(format!("{} is not among the names imported", name)))
+    }
+}
+
+// The bindings an import set gives: a library name, or one of only, except,
+// prefix and rename applied to another import set
+fn import_set(interp: &mut Interpreter, set: &Sexp) -> Result<Bindings, EvalError> {
+    let items = match *set {
+        Sexp::List(ref items) if !items.is_empty() => items,
+        _ => return Err(EvalError::Message(format!("Expected an import set; found {:?}", set))),
+    };
+    let modifier = match items[0] {
+        Sexp::Symbol(ref s) if items.len() > 1 && matches!(items[1], Sexp::List(_)) => &**s,
+        _ => "",
+    };
+    match modifier {
+        "only" => {
+            let bindings = import_set(interp, &items[1])?;
+            let names = identifiers(modifier, &items[2..])?;
+            for name in &names {
+                check_imported(&bindings, name)?;
+            }
+            Ok(bindings.into_iter().filter(|(bound, _)| names.contains(&&**bound)).collect())
+        },
+        "except" => {
+            let bindings = import_set(interp, &items[1])?;
+            let names = identifiers(modifier, &items[2..])?;
+            for name in &names {
+                check_imported(&bindings, name)?;
+            }
+            Ok(bindings.into_iter().filter(|(bound, _)| !names.contains(&&**bound)).collect())
+        },
+        "prefix" => {
+            let bindings = import_set(interp, &items[1])?;
+            let prefix = match identifiers(modifier, &items[2..])?[..] {
+                [prefix] => prefix,
+                _ => return Err(EvalError::from("`prefix` expected an import set and an identifier")),
+            };
+            Ok(bindings.into_iter().map(|(name, v)| (Rc::from(format!("{}{}", prefix, name)), v)).collect())
+        },
+        "rename" => {
+            let mut bindings = import_set(interp, &items[1])?;
+            for renaming in &items[2..] {
+                let (from, to) = match *renaming {
+                    Sexp::List(ref pair) => match identifiers("rename", pair)?[..] {
+                        [from, to] => (from, to),
+                        _ => return Err(EvalError::from("`rename` expected (name new-name) pairs")),
+                    },
+                    _ => return Err(EvalError::from("`rename` expected (name new-name) pairs")),
+                };
+                check_imported(&bindings, from)?;
+                for binding in bindings.iter_mut().filter(|(bound, _)| &**bound == from) {
+                    binding.0 = Rc::from(to);
+                }
+            }
+            Ok(bindings)
+        },
+        _ => Ok((*find(interp, set)?).clone()),
+    }
+}
+
+/// Binds in env what the import set exports. Later changes to the
+/// variables a library exports are not seen by those that imported them.
+pub fn import(interp: &mut Interpreter, env: &Env, set: &Sexp) -> Result<(), EvalError> {
+    let bindings = import_set(interp, set)?;
+    let mut table = env.borrow_mut();
+    for (name, value) in bindings {
+        table.assign(&name, value);
+    }
+    Ok(())
+}
+
+/// True if a feature requirement of `cond-expand` holds: a feature
+/// identifier, (library name), or and, or and not of requirements.
+pub fn holds(interp: &mut Interpreter, requirement: &Sexp) -> Result<bool, EvalError> {
+    let invalid = || EvalError::Message(format!("Expected a feature requirement; found {:?}", requirement));
+    if let Some(feature) = symbol(requirement) {
+        return Ok(features().iter().any(|f| f == feature));
+    }
+    let items = match *requirement {
+        Sexp::List(ref items) if !items.is_empty() => items,
+        _ => return Err(invalid()),
+    };
+    match (symbol(&items[0]), &items[1..]) {
+        (Some("and"), reqs) => {
+            for req in reqs {
+                if !holds(interp, req)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        },
+        (Some("or"), reqs) => {
+            for req in reqs {
+                if holds(interp, req)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        },
+        (Some("not"), [req]) => Ok(!holds(interp, req)?),
+        (Some("library"), [name]) => {
+            let parts = name_parts(name)?;
+            let key = key(&parts);
+            Ok(interp.libraries().defined.contains_key(&key)
+               || builtins::LIBRARIES.contains(&&*key)
+               || interp.find_library_file(&format!("{}.sld", parts.join("/"))).is_some())
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// The body of the first clause of a `cond-expand` whose requirement holds,
+/// or of its else clause; nothing if there is neither.
+pub fn cond_expand<'a>(interp: &mut Interpreter, clauses: &'a [Sexp]) -> Result<&'a [Sexp], EvalError> {
+    for clause in clauses {
+        let parts = match *clause {
+            Sexp::List(ref parts) if !parts.is_empty() => parts,
+            _ => return Err(EvalError::from("Expected a clause (requirement form...) in `cond-expand`")),
+        };
+        if symbol(&parts[0]) == Some("else") || holds(interp, &parts[0])? {
+            return Ok(&parts[1..]);
+        }
+    }
+    Ok(&[])
+}
+
+// Carries out a library's declarations in its scope, collecting its exports
+// as (internal, external) names
+fn declare(interp: &mut Interpreter, scope: &Env, declarations: &[Sexp],
+           exports: &mut Vec<(Rc<str>, Rc<str>)>) -> Result<(), EvalError> {
+    for declaration in declarations {
+        let items = match *declaration {
+            Sexp::List(ref items) if !items.is_empty() => items,
+            _ => return Err(EvalError::Message(format!(
+                "Expected a library declaration; found {:?}", declaration))),
+        };
+        match symbol(&items[0]).unwrap_or("") {
+            "export" => for spec in &items[1..] {
+                let names = match *spec {
+                    Sexp::Symbol(ref name) => (name.clone(), name.clone()),
+                    Sexp::List(ref parts) => match identifiers("export", parts)?[..] {
+                        ["rename", from, to] => (Rc::from(from), Rc::from(to)),
+                        _ => return Err(EvalError::from("`export` expected names and (rename name new-name)")),
+                    },
+                    _ => return Err(EvalError::from("`export` expected names and (rename name new-name)")),
+                };
+                exports.push(names);
+            },
+            "import" => for set in &items[1..] {
+                import(interp, scope, set)?;
+            },
+            "begin" | "include" | "include-ci" => {
+                interp.eval_in(scope, declaration)?;
+            },
+            "include-library-declarations" => for path in &items[1..] {
+                let path = match *path {
+                    Sexp::String(ref path) => path,
+                    _ => return Err(EvalError::from("`include-library-declarations` expected file names")),
+                };
+                let file = interp.open_source("include-library-declarations", path)?;
+                let forms = interp.enter_file(&file)?;
+                let mut result = Ok(());
+                for (line, form) in forms {
+                    result = declare(interp, scope, &[form], exports)
+                        .map_err(|e| e.in_file(&file.display_name(), line));
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                interp.leave_file();
+                result?;
+            },
+            "cond-expand" => {
+                let chosen = cond_expand(interp, &items[1..])?;
+                declare(interp, scope, chosen, exports)?;
+            },
+            _ => return Err(EvalError::Message(format!(
+                "Expected a library declaration; found {:?}", declaration))),
+        }
+    }
+    Ok(())
+}
+
+fn define_library(interp: &mut Interpreter, items: &[Sexp]) -> Result<(), EvalError> {
+    let name = match items.get(1) {
+        Some(name) => name,
+        None => return Err(EvalError::from("Expected a library name after `define-library`")),
+    };
+    let key = key(&name_parts(name)?);
+    // A library sees only what it imports
+    let scope = SymTable::empty().into_env();
+    interp.libraries().scopes.push(scope.clone());
+    let mut exports = vec![];
+    declare(interp, &scope, &items[2..], &mut exports)?;
+    let mut bindings = vec![];
+    for (internal, external) in exports {
+        match scope.borrow().lookup(&internal) {
+            Some(value) => bindings.push((external, value)),
+            None => return Err(EvalError::Message(format!(
+                "{} exports {}, which it does not define", key, internal))),
+        }
+    }
+    interp.libraries().defined.insert(key, Rc::new(bindings));
+    Ok(())
+}
+
+/// Evaluates `import` and `define-library`, which may only appear at the
+/// top level, and `cond-expand` there, which may choose either of them;
+/// returns None for any other form.
+pub fn eval_toplevel(interp: &mut Interpreter, env: &Env, sexp: &Sexp) -> Option<Result<SValue, EvalError>> {
+    let items = match *sexp {
+        Sexp::List(ref items) => items,
+        _ => return None,
+    };
+    match items.first().and_then(symbol) {
+        Some("import") => Some(items[1..].iter()
+            .try_for_each(|set| import(interp, env, set))
+            .map(|()| SValue::nil())),
+        Some("define-library") => Some(define_library(interp, items).map(|()| SValue::nil())),
+        Some("cond-expand") => Some(cond_expand(interp, &items[1..]).and_then(|forms| {
+            let mut result = SValue::nil();
+            for form in forms {
+                result = interp.eval_in(env, form)?;
+            }
+            Ok(result)
+        })),
+        _ => None,
+    }
+}
@@ -1,44 +1,199 @@
+use std::env;
+use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Read, Write};
+use std::process;
+use std::sync::OnceLock;
+use std::thread;
 
-mod parse;
-mod util;
-mod eval;
+extern crate scheme;
 
-fn main() {
+use scheme::{EvalError, Interpreter, InterruptHandle, Limits};
+use scheme::parse;
+use scheme::util;
+
+const USAGE: &str = "\
+usage: scheme [options] [file | -] [args...]
+
+Runs the program in `file` (or standard input, given `-`), or starts an
+interactive session when there is nothing to run. Arguments following the
+program are available to it through (command-line).
+
+options:
+  -e EXPR    evaluate EXPR; may be repeated, and all arguments are then
+             passed to the program rather than naming a file
+  -i         start an interactive session after running the program
+  --tokens   print the tokens of everything read
+  --ast      print each expression as parsed
+  -h, --help show this message
+  --         treat everything after as file and arguments";
+
+struct Options {
+    exprs: Vec<String>,
+    file: Option<String>,
+    args: Vec<String>,
+    interactive: bool,
+    show_tokens: bool,
+    show_ast: bool,
+}
+
+fn parse_args(mut args: env::Args) -> Result<Options, String> {
+    let mut opts = Options {
+        exprs: vec![],
+        file: None,
+        args: vec![],
+        interactive: false,
+        show_tokens: false,
+        show_ast: false,
+    };
+    let mut positional = vec![];
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "-e" => match args.next() {
+                Some(expr) => opts.exprs.push(expr),
+                None => return Err(String::from("-e requires an expression")),
+            },
+            "-i" => opts.interactive = true,
+            "--tokens" => opts.show_tokens = true,
+            "--ast" => opts.show_ast = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            "--" => {
+                positional.extend(args.by_ref());
+            },
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option {}", arg));
+            },
+            _ => {
+                positional.push(arg);
+                positional.extend(args.by_ref());
+            },
+        }
+    }
+    if opts.exprs.is_empty() && !positional.is_empty() {
+        opts.file = Some(positional.remove(0));
+    }
+    opts.args = positional;
+    Ok(opts)
+}
+
+// Evaluates every form in src, stopping at the first error. Source from
+// a file is evaluated as that file's, so that errors name it.
+fn run(interp: &mut Interpreter, opts: &Options, file: Option<&str>, src: &str) -> Result<(), EvalError> {
+    if opts.show_tokens {
+        if let Ok(toks) = util::tokenize(src) {
+            println!("        Tokens: {:?}", toks);
+        }
+    }
+    if let Some(path) = file {
+        if opts.show_ast {
+            for sexp in parse::read_all(src).unwrap_or_default() {
+                println!(": {:?}", sexp);
+            }
+        }
+        return interp.eval_source(src, path).map(|_| ());
+    }
+    let forms = match parse::read_all(src) {
+        Ok(forms) => forms,
+        Err(e) => return Err(EvalError::Message(format!("Parse error: {}", e))),
+    };
+    for sexp in forms {
+        if opts.show_ast {
+            println!(": {:?}", sexp);
+        }
+        interp.eval(sexp)?;
+    }
+    Ok(())
+}
+
+// Where the SIGINT handler finds the interpreter to stop
+static INTERRUPT: OnceLock<InterruptHandle> = OnceLock::new();
+
+#[cfg(unix)]
+fn handle_ctrl_c(interp: &Interpreter) {
+    use std::os::raw::c_int;
+    const SIGINT: c_int = 2;
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+    extern "C" fn on_sigint(_: c_int) {
+        // Only a flag is set here; evaluation stops at its next step
+        if let Some(handle) = INTERRUPT.get() {
+            handle.interrupt();
+        }
+    }
+    let _ = INTERRUPT.set(interp.interrupt_handle());
+    unsafe {
+        signal(SIGINT, on_sigint as extern "C" fn(c_int) as usize);
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_ctrl_c(_: &Interpreter) {}
+
+fn repl(interp: &mut Interpreter, opts: &Options) -> i32 {
     println!("Welcome to Scheme!");
-    let mut scope = eval::arith_table();
+    // Ctrl-C stops what is being evaluated rather than the session
+    handle_ctrl_c(interp);
+    let mut reader = parse::Reader::new();
     loop {
-        print!("> ");
+        // Continue the prompt while a form spans several lines
+        print!("{}", if reader.is_empty() { "> " } else { "... " });
         if let Err(e) = io::stdout().flush() {
             println!("Error flushing stdout! {}", e);
-            return;
+            return 1;
         }
         let mut input = String::new();
         match io::stdin().read_line(&mut input) {
+            Ok(0) => {
+                println!();
+                if !reader.is_empty() {
+                    println!("Parse error: unexpected end of input");
+                }
+                return 0;
+            },
             Ok(_) => {
-                if let "\\exit" = input.trim() {
-                    return;
+                // Ctrl-C at the prompt isn't meant for what was typed after it
+                interp.interrupt_handle().clear();
+                if reader.is_empty() && input.trim() == "\\exit" {
+                    return 0;
                 }
-                let rtokens = util::tokenize(&input);
-                match rtokens {
-                    Ok(toks) => {
-                        println!("\n        Tokens: {:?}", toks);
-                        let mut citer = util::ClingyIter::new(toks.iter());
-                        while let Ok(sexp) = parse::read_sexp(&mut citer) {
-                            println!(": {:?}", sexp);
-                            match eval::eval(&mut scope, sexp) {
+                reader.feed(&input);
+                let src = String::from(reader.source());
+                match reader.read_forms() {
+                    Ok(forms) => {
+                        if opts.show_tokens {
+                            if let Ok(toks) = util::tokenize(&src) {
+                                println!("\n        Tokens: {:?}", toks);
+                            }
+                        }
+                        for sexp in forms {
+                            if opts.show_ast {
+                                println!(": {:?}", sexp);
+                            }
+                            match interp.eval(sexp) {
+                                // One line for each result, so none for (values)
                                 Ok(sval) => {
-                                    println!("= {:?}", sval);
+                                    for result in sval.into_values() {
+                                        println!("= {:?}", result);
+                                    }
+                                },
+                                Err(EvalError::Exit(code)) => return code,
+                                Err(EvalError::Interrupted) => {
+                                    println!("Interrupted");
+                                    break;
                                 },
                                 Err(e) => {
-                                    println!("Eval error! {:?}", e);
+                                    println!("Eval error! {}", e);
                                 },
                             }
                         }
                     },
+                    Err(parse::ReadError::Incomplete) => {},
                     Err(e) => {
-                        println!("Error while lexing: {:?}", e);
+                        println!("Parse error: {}", e);
                     },
                 }
             },
@@ -46,3 +201,72 @@ fn main() {
         }
     }
 }
+
+// Scheme code recursing through natives like `map`, or on the tree-walker,
+// recurses on the native stack, so programs run on a thread with plenty of it
+const STACK: usize = 256 << 20;
+
+fn main() {
+    let session = thread::Builder::new().stack_size(STACK).spawn(start).unwrap();
+    // A panic in the session has been reported already
+    process::exit(if session.join().is_ok() { 0 } else { 101 });
+}
+
+fn start() {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| String::from("scheme"));
+    let opts = match parse_args(args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("scheme: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        },
+    };
+
+    let mut interp = Interpreter::new();
+    // Leaving room for what runs outside any evaluation
+    interp.set_limits(Limits { stack: Some(STACK - (16 << 20)), ..Limits::default() });
+    let mut command_line = vec![opts.file.clone().unwrap_or(program)];
+    command_line.extend(opts.args.iter().cloned());
+    interp.set_command_line(command_line);
+
+    // Run the program from -e, a file or piped input, if there is one
+    let stdin_is_program = opts.file.as_ref().map_or(
+        opts.exprs.is_empty() && !io::stdin().is_terminal(),
+        |f| f == "-");
+    let mut sources: Vec<(Option<&str>, String)> = opts.exprs.iter().map(|e| (None, e.clone())).collect();
+    if stdin_is_program {
+        let mut src = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut src) {
+            eprintln!("scheme: cannot read standard input: {}", e);
+            process::exit(1);
+        }
+        sources.push((None, src));
+    } else if let Some(ref path) = opts.file {
+        match fs::read_to_string(path) {
+            Ok(src) => sources.push((Some(path), src)),
+            Err(e) => {
+                eprintln!("scheme: cannot read {}: {}", path, e);
+                process::exit(1);
+            },
+        }
+    }
+    for &(file, ref src) in &sources {
+        match run(&mut interp, &opts, file, src) {
+            Ok(()) => {},
+            Err(EvalError::Exit(code)) => process::exit(code),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                // Dropping the interpreter flushes the files it has open
+                drop(interp);
+                process::exit(1);
+            },
+        }
+    }
+
+    if sources.is_empty() || opts.interactive {
+        let code = repl(&mut interp, &opts);
+        drop(interp);
+        process::exit(code);
+    }
+}
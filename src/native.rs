@@ -0,0 +1,440 @@
+use std::fmt;
+use std::hash::Hash;
+use std::collections::HashMap;
+use std::rc::Rc;
+use eval::{EvalError, SValue};
+use interpreter::Interpreter;
+
+/// How many arguments a procedure accepts.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>, // None when variadic
+}
+
+impl Arity {
+    pub fn exactly(n: usize) -> Arity { Arity { min: n, max: Some(n) } }
+    pub fn at_least(n: usize) -> Arity { Arity { min: n, max: None } }
+    pub fn between(min: usize, max: usize) -> Arity { Arity { min, max: Some(max) } }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min && self.max.is_none_or(|max| n <= max)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n| if n == 1 { "arg" } else { "args" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{} {}", max, plural(max)),
+            Some(max) => write!(f, "{} to {} args", self.min, max),
+            None => write!(f, "at least {} {}", self.min, plural(self.min)),
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&mut Interpreter, Vec<SValue>) -> Result<SValue, EvalError>;
+
+/// A procedure implemented in Rust.
+pub struct Native {
+    pub name: String,
+    pub arity: Arity,
+    func: Box<NativeFn>,
+}
+
+impl Native {
+    pub fn new<F>(name: &str, arity: Arity, func: F) -> Native
+        where F: Fn(&mut Interpreter, Vec<SValue>) -> Result<SValue, EvalError> + 'static {
+        Native { name: String::from(name), arity, func: Box::new(func) }
+    }
+
+    pub fn call(&self, interp: &mut Interpreter, args: Vec<SValue>) -> Result<SValue, EvalError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(EvalError::Message(format!(
+                "`{}` expected {}; was given {}", self.name, self.arity, args.len())));
+        }
+        (self.func)(interp, args)
+    }
+}
+
+/// Conversion of a Scheme value to a Rust argument type. On failure,
+/// `from_svalue` describes the kind of value that was expected.
+pub trait FromSValue<'a>: Sized {
+    fn from_svalue(v: &'a SValue) -> Result<Self, String>;
+
+    /// The value of the argument when it is left out of a call, if it may be.
+    fn missing() -> Option<Self> { None }
+}
+
+/// Conversion of a Rust value into a Scheme value.
+pub trait IntoSValue {
+    fn into_svalue(self) -> SValue;
+}
+
+impl<'a> FromSValue<'a> for SValue {
+    fn from_svalue(v: &'a SValue) -> Result<SValue, String> { Ok(v.clone()) }
+}
+
+// The borrowed conversions can't be parameters of `define_fn` closures,
+// whose arguments are converted inside the procedure, but serve natives
+// defined with `define_native` that convert from their own argument list
+impl<'a> FromSValue<'a> for &'a SValue {
+    fn from_svalue(v: &'a SValue) -> Result<&'a SValue, String> { Ok(v) }
+}
+
+impl IntoSValue for SValue {
+    fn into_svalue(self) -> SValue { self }
+}
+
+impl<'a> FromSValue<'a> for f64 {
+    fn from_svalue(v: &'a SValue) -> Result<f64, String> {
+        match *v {
+            SValue::Number(f) => Ok(f),
+            _ => Err(String::from("a number")),
+        }
+    }
+}
+
+impl IntoSValue for f64 {
+    fn into_svalue(self) -> SValue { SValue::Number(self) }
+}
+
+macro_rules! integer_conversions {
+    ($($t:ty),*) => {$(
+        impl<'a> FromSValue<'a> for $t {
+            fn from_svalue(v: &'a SValue) -> Result<$t, String> {
+                match *v {
+                    SValue::Number(f) if f.fract() == 0.0
+                        && f >= <$t>::MIN as f64 && f <= <$t>::MAX as f64 => Ok(f as $t),
+                    _ => Err(format!("an integer in the range of {}", stringify!($t))),
+                }
+            }
+        }
+
+        impl IntoSValue for $t {
+            fn into_svalue(self) -> SValue { SValue::Number(self as f64) }
+        }
+    )*}
+}
+
+integer_conversions!(i32, i64, u32, u64, usize);
+
+impl<'a> FromSValue<'a> for bool {
+    fn from_svalue(v: &'a SValue) -> Result<bool, String> {
+        match *v {
+            SValue::Bool(b) => Ok(b),
+            _ => Err(String::from("a boolean")),
+        }
+    }
+}
+
+impl IntoSValue for bool {
+    fn into_svalue(self) -> SValue { SValue::Bool(self) }
+}
+
+impl<'a> FromSValue<'a> for char {
+    fn from_svalue(v: &'a SValue) -> Result<char, String> {
+        match *v {
+            SValue::Char(c) => Ok(c),
+            _ => Err(String::from("a character")),
+        }
+    }
+}
+
+impl IntoSValue for char {
+    fn into_svalue(self) -> SValue { SValue::Char(self) }
+}
+
+impl<'a> FromSValue<'a> for String {
+    fn from_svalue(v: &'a SValue) -> Result<String, String> {
+        match *v {
+            SValue::String(ref s) => Ok(s.to_string()),
+            _ => Err(String::from("a string")),
+        }
+    }
+}
+
+impl IntoSValue for String {
+    fn into_svalue(self) -> SValue { SValue::String(Rc::from(self)) }
+}
+
+// Shares the string rather than copying it
+impl<'a> FromSValue<'a> for Rc<str> {
+    fn from_svalue(v: &'a SValue) -> Result<Rc<str>, String> {
+        match *v {
+            SValue::String(ref s) => Ok(s.clone()),
+            _ => Err(String::from("a string")),
+        }
+    }
+}
+
+impl IntoSValue for Rc<str> {
+    fn into_svalue(self) -> SValue { SValue::String(self) }
+}
+
+impl<'a> FromSValue<'a> for &'a str {
+    fn from_svalue(v: &'a SValue) -> Result<&'a str, String> {
+        match *v {
+            SValue::String(ref s) => Ok(s),
+            _ => Err(String::from("a string")),
+        }
+    }
+}
+
+impl IntoSValue for &str {
+    fn into_svalue(self) -> SValue { SValue::string(self) }
+}
+
+// () is returned by procedures run only for their effect
+impl IntoSValue for () {
+    fn into_svalue(self) -> SValue { SValue::nil() }
+}
+
+impl<'a, T: for<'b> FromSValue<'b>> FromSValue<'a> for Vec<T> {
+    fn from_svalue(v: &'a SValue) -> Result<Vec<T>, String> {
+        match v.to_vec() {
+            Some(items) => items.iter().map(|x| T::from_svalue(x)).collect(),
+            None => Err(String::from("a list")),
+        }
+    }
+}
+
+impl<T: IntoSValue> IntoSValue for Vec<T> {
+    fn into_svalue(self) -> SValue {
+        self.into_iter().map(IntoSValue::into_svalue).collect()
+    }
+}
+
+// #f stands for a missing value, as with procedures like assoc
+impl<'a, T: FromSValue<'a>> FromSValue<'a> for Option<T> {
+    fn from_svalue(v: &'a SValue) -> Result<Option<T>, String> {
+        match *v {
+            SValue::Bool(false) => Ok(None),
+            ref v => T::from_svalue(v).map(Some),
+        }
+    }
+
+    fn missing() -> Option<Option<T>> { Some(None) }
+}
+
+impl<T: IntoSValue> IntoSValue for Option<T> {
+    fn into_svalue(self) -> SValue {
+        match self {
+            Some(x) => x.into_svalue(),
+            None => SValue::Bool(false),
+        }
+    }
+}
+
+// Maps are association lists: ((key . value) ...)
+impl<'a, K, V> FromSValue<'a> for HashMap<K, V>
+    where K: for<'b> FromSValue<'b> + Eq + Hash, V: for<'b> FromSValue<'b> {
+    fn from_svalue(v: &'a SValue) -> Result<HashMap<K, V>, String> {
+        let expected = || String::from("an association list");
+        let entries = v.to_vec().ok_or_else(expected)?;
+        let mut map = HashMap::new();
+        for entry in entries {
+            match entry {
+                SValue::Pair(ref kv) => {
+                    map.insert(K::from_svalue(&kv.car())?, V::from_svalue(&kv.cdr())?);
+                },
+                _ => return Err(expected()),
+            }
+        }
+        Ok(map)
+    }
+}
+
+impl<K: IntoSValue, V: IntoSValue> IntoSValue for HashMap<K, V> {
+    fn into_svalue(self) -> SValue {
+        self.into_iter()
+            .map(|(k, v)| SValue::cons(k.into_svalue(), v.into_svalue()))
+            .collect()
+    }
+}
+
+/// The remaining arguments of a variadic procedure; must be the last
+/// parameter of a closure given to `Interpreter::define_fn`.
+pub struct Rest<T>(pub Vec<T>);
+
+/// What a native closure may return: a convertible value, or a `Result`
+/// whose error becomes an evaluation error.
+pub trait NativeReturn {
+    fn into_result(self) -> Result<SValue, EvalError>;
+}
+
+impl<T: IntoSValue> NativeReturn for T {
+    fn into_result(self) -> Result<SValue, EvalError> { Ok(self.into_svalue()) }
+}
+
+impl<T: IntoSValue, E: fmt::Display> NativeReturn for Result<T, E> {
+    fn into_result(self) -> Result<SValue, EvalError> {
+        self.map(IntoSValue::into_svalue).map_err(|e| EvalError::Message(format!("{}", e)))
+    }
+}
+
+pub fn convert<'a, T: FromSValue<'a>>(name: &str, args: &'a [SValue], i: usize) -> Result<T, EvalError> {
+    match args.get(i) {
+        Some(v) => T::from_svalue(v).map_err(|expected| EvalError::Message(format!(
+            "`{}` expected {} as argument {}; was given {:?}", name, expected, i + 1, v))),
+        // Arity checks only let optional arguments be left out
+        None => T::missing().ok_or_else(|| EvalError::Message(format!(
+            "`{}` is missing argument {}", name, i + 1))),
+    }
+}
+
+/// Rust closures that can be made into Scheme procedures. `Args` is the
+/// tuple of parameter types, and only serves to tell the impls apart.
+pub trait IntoNative<Args> {
+    fn into_native(self, name: &str) -> Native;
+}
+
+macro_rules! into_native {
+    ($($arg:ident),*) => {
+        #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+        impl<Func, Ret, $($arg),*> IntoNative<($($arg,)*)> for Func
+            where Func: Fn($($arg),*) -> Ret + 'static,
+                  Ret: NativeReturn,
+                  $($arg: for<'a> FromSValue<'a>),* {
+            fn into_native(self, name: &str) -> Native {
+                let optional: &[bool] = &[$($arg::missing().is_some()),*];
+                let required = optional.iter().rposition(|o| !o).map_or(0, |i| i + 1);
+                let arity = Arity::between(required, optional.len());
+                let owned_name = String::from(name);
+                Native::new(name, arity, move |_, args| {
+                    let mut i = 0;
+                    $(let $arg = convert(&owned_name, &args, i)?; i += 1;)*
+                    self($($arg),*).into_result()
+                })
+            }
+        }
+
+        #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+        impl<Func, Ret, Last, $($arg),*> IntoNative<($($arg,)* Rest<Last>,)> for Func
+            where Func: Fn($($arg,)* Rest<Last>) -> Ret + 'static,
+                  Ret: NativeReturn,
+                  Last: for<'a> FromSValue<'a>,
+                  $($arg: for<'a> FromSValue<'a>),* {
+            fn into_native(self, name: &str) -> Native {
+                let fixed = <[&str]>::len(&[$(stringify!($arg)),*]);
+                let owned_name = String::from(name);
+                Native::new(name, Arity::at_least(fixed), move |_, args| {
+                    let mut i = 0;
+                    $(let $arg = convert(&owned_name, &args, i)?; i += 1;)*
+                    let rest = (fixed..args.len())
+                        .map(|i| convert(&owned_name, &args, i))
+                        .collect::<Result<Vec<Last>, EvalError>>()?;
+                    self($($arg,)* Rest(rest)).into_result()
+                })
+            }
+        }
+    }
+}
+
+into_native!();
+into_native!(A);
+into_native!(A, B);
+into_native!(A, B, C);
+into_native!(A, B, C, D);
+into_native!(A, B, C, D, E);
+into_native!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use eval::SValue;
+    use interpreter::Interpreter;
+    use super::*;
+
+    fn list(items: Vec<SValue>) -> SValue { items.into_iter().collect() }
+
+    fn n(x: f64) -> SValue { SValue::Number(x) }
+
+    #[test]
+    fn lists_convert_to_and_from_vecs() {
+        let v = list(vec![n(1.0), n(2.0), n(3.0)]);
+        assert_eq!(Vec::<i64>::from_svalue(&v), Ok(vec![1, 2, 3]));
+        assert_eq!(vec![1, 2, 3].into_svalue(), v);
+        assert_eq!(Vec::<i64>::from_svalue(&SValue::nil()), Ok(vec![]));
+        assert_eq!(Vec::<i64>::from_svalue(&n(1.0)), Err(String::from("a list")));
+        // An element of the wrong type names the element's expected type
+        let mixed = list(vec![n(1.0), SValue::Bool(true)]);
+        assert_eq!(Vec::<i64>::from_svalue(&mixed), Err(String::from("an integer in the range of i64")));
+    }
+
+    #[test]
+    fn false_and_missing_arguments_are_none() {
+        assert_eq!(Option::<String>::from_svalue(&SValue::Bool(false)), Ok(None));
+        assert_eq!(Option::<String>::from_svalue(&SValue::string("x")), Ok(Some(String::from("x"))));
+        assert_eq!(Option::<String>::from_svalue(&n(1.0)), Err(String::from("a string")));
+        assert_eq!(Option::<String>::missing(), Some(None));
+        assert_eq!(String::missing(), None);
+        assert_eq!(None::<f64>.into_svalue(), SValue::Bool(false));
+        // #f stays a boolean where one is expected
+        assert_eq!(Option::<bool>::from_svalue(&SValue::Bool(true)), Ok(Some(true)));
+    }
+
+    #[test]
+    fn association_lists_convert_to_and_from_maps() {
+        let alist = list(vec![
+            SValue::cons(SValue::string("a"), n(1.0)),
+            SValue::cons(SValue::string("b"), n(2.0)),
+        ]);
+        let map = HashMap::<String, u32>::from_svalue(&alist).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 1);
+        assert_eq!(map["b"], 2);
+
+        let back = HashMap::<String, u32>::from_svalue(&map.clone().into_svalue()).unwrap();
+        assert_eq!(back, map);
+
+        let not_pairs = list(vec![n(1.0)]);
+        assert_eq!(HashMap::<String, u32>::from_svalue(&not_pairs),
+                   Err(String::from("an association list")));
+        let bad_value = list(vec![SValue::cons(SValue::string("a"), n(-1.0))]);
+        assert_eq!(HashMap::<String, u32>::from_svalue(&bad_value),
+                   Err(String::from("an integer in the range of u32")));
+    }
+
+    #[test]
+    fn strings_may_be_borrowed_from_arguments() {
+        let args = vec![SValue::string("borrowed"), n(1.0)];
+        let s: &str = convert("f", &args, 0).unwrap();
+        assert_eq!(s, "borrowed");
+        let err = convert::<&str>("f", &args, 1).unwrap_err();
+        assert_eq!(err, EvalError::Message(String::from(
+            "`f` expected a string as argument 2; was given 1")));
+    }
+
+    #[test]
+    fn calls_are_checked_against_the_parameters() {
+        let mut interp = Interpreter::new();
+        let repeat = (|s: String, n: usize| s.repeat(n)).into_native("repeat");
+        assert_eq!(repeat.arity, Arity::exactly(2));
+        assert_eq!(repeat.call(&mut interp, vec![SValue::string("ab"), n(2.0)]),
+                   Ok(SValue::string("abab")));
+
+        let message = |r: Result<SValue, EvalError>| match r {
+            Err(EvalError::Message(m)) => m,
+            r => panic!("expected an error, got {:?}", r),
+        };
+        assert_eq!(message(repeat.call(&mut interp, vec![SValue::string("ab")])),
+                   "`repeat` expected 2 args; was given 1");
+        assert_eq!(message(repeat.call(&mut interp, vec![SValue::string("ab"), n(1.5)])),
+                   "`repeat` expected an integer in the range of usize as argument 2; was given 1.5");
+
+        let greet = (|name: String, greeting: Option<String>| {
+            format!("{}, {}", greeting.unwrap_or_else(|| String::from("hello")), name)
+        }).into_native("greet");
+        assert_eq!(greet.arity, Arity::between(1, 2));
+        assert_eq!(greet.call(&mut interp, vec![SValue::string("you")]),
+                   Ok(SValue::string("hello, you")));
+
+        let sum = (|first: f64, Rest(xs): Rest<f64>| first + xs.iter().sum::<f64>()).into_native("sum");
+        assert_eq!(sum.arity, Arity::at_least(1));
+        assert_eq!(message(sum.call(&mut interp, vec![])), "`sum` expected at least 1 arg; was given 0");
+        assert_eq!(message(sum.call(&mut interp, vec![n(1.0), n(2.0), SValue::Bool(true)])),
+                   "`sum` expected a number as argument 3; was given #t");
+        assert_eq!(sum.call(&mut interp, vec![n(1.0), n(2.0), n(3.0)]), Ok(n(6.0)));
+    }
+}
@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use eval;
+use eval::{EvalError, SValue};
+use gc;
+use gc::Trace;
+use interpreter::Interpreter;
+
+/// What `make-parameter` returns: a procedure of no arguments giving a
+/// value, which `parameterize` changes for as long as its body runs.
+pub struct Parameter {
+    value: RefCell<SValue>,
+    converter: Option<SValue>, // applied to each value it is given
+}
+
+impl Parameter {
+    /// A parameter holding value, which the converter is not applied to.
+    pub fn new(value: SValue, converter: Option<SValue>) -> Rc<Parameter> {
+        let parameter = Rc::new(Parameter { value: RefCell::new(value), converter });
+        gc::track(&parameter);
+        parameter
+    }
+
+    pub fn get(&self) -> SValue {
+        self.value.borrow().clone()
+    }
+
+    /// What calling the parameter gives, which takes no arguments.
+    pub fn call(&self, args: &[SValue]) -> Result<SValue, EvalError> {
+        if !args.is_empty() {
+            return Err(EvalError::Message(format!("Expected 0 args; was given {}", args.len())));
+        }
+        Ok(self.get())
+    }
+
+    /// Sets the value, returning the one it replaces.
+    pub fn replace(&self, value: SValue) -> SValue {
+        self.value.replace(value)
+    }
+
+    /// The value the parameter takes when given v.
+    pub fn convert(&self, interp: &mut Interpreter, v: SValue) -> Result<SValue, EvalError> {
+        match self.converter {
+            Some(ref converter) => eval::apply(interp, converter.clone(), vec![v]),
+            None => Ok(v),
+        }
+    }
+}
+
+/// Calls body with each parameter set to its value, then sets them back,
+/// however body is left.
+pub fn parameterize<F>(interp: &mut Interpreter, bindings: Vec<(Rc<Parameter>, SValue)>, body: F)
+                       -> Result<SValue, EvalError>
+    where F: FnOnce(&mut Interpreter) -> Result<SValue, EvalError> {
+    let saved: Vec<(Rc<Parameter>, SValue)> = bindings.into_iter()
+        .map(|(parameter, value)| {
+            let old = parameter.replace(value);
+            (parameter, old)
+        })
+        .collect();
+    let result = body(interp);
+    for (parameter, old) in saved.into_iter().rev() {
+        parameter.replace(old);
+    }
+    result
+}
+
+impl Trace for Parameter {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        match self.value.try_borrow() {
+            Ok(value) => {
+                eval::trace_value(&value, out);
+                if let Some(ref converter) = self.converter {
+                    eval::trace_value(converter, out);
+                }
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        self.replace(SValue::Nil);
+    }
+}
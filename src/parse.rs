@@ -1,15 +1,19 @@
-use std::collections::linked_list::LinkedList;
+use std::fmt;
+use std::rc::Rc;
+use util;
 use util::Token;
 use util::ClingyIter;
+use util::LexError;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Sexp {
-    List(LinkedList<Sexp>), // nil is List(vec![])
-    Symbol(String),
-    String(String),
+    List(Rc<[Sexp]>), // nil is an empty list
+    Vector(Rc<[Sexp]>),
+    Symbol(Rc<str>),
+    String(Rc<str>),
     Number(f64),
     Bool(bool),
-    // todo: Char(char), String(String)
+    Char(char),
 }
 
 fn parse_str_contents(s: &str) -> Result<String, String> {
@@ -49,19 +53,34 @@ fn parse_str_contents(s: &str) -> Result<String, String> {
     Ok(out)
 }
 
-pub fn read_sexp<'a>(mut citer: &mut ClingyIter<Token<'a>>)
-                 -> Result<Sexp, String> {
-    return if let Some(&token) = citer.value() {
+#[derive(Debug, PartialEq, Clone)]
+pub enum ReadError {
+    // The input ended inside a form; more text may complete it
+    Incomplete,
+    Invalid(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Incomplete => write!(f, "Unexpected end of input"),
+            ReadError::Invalid(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub fn read_sexp(citer: &mut ClingyIter<Token>) -> Result<Sexp, ReadError> {
+    if let Some(&token) = citer.value() {
         match token {
             Token::Symbol(sym) => {
                 citer.advance();
-                Ok(Sexp::Symbol(String::from(sym)))
+                Ok(Sexp::Symbol(Rc::from(sym)))
             },
             Token::String(s) => {
                 citer.advance();
                 match parse_str_contents(s) {
-                    Ok(string) => Ok(Sexp::String(string)),
-                    Err(e) => Err(e),
+                    Ok(string) => Ok(Sexp::String(Rc::from(string))),
+                    Err(e) => Err(ReadError::Invalid(e)),
                 }
             },
             Token::Number(num) => {
@@ -72,27 +91,115 @@ pub fn read_sexp<'a>(mut citer: &mut ClingyIter<Token<'a>>)
                 citer.advance();
                 Ok(Sexp::Bool(b))
             },
-            Token::RightParen => Err(String::from("Unexpected ')'")),
-            Token::LeftParen => {
-                let mut contents = LinkedList::new();//vec![];
+            Token::Char(c) => {
+                citer.advance();
+                Ok(Sexp::Char(c))
+            },
+            Token::Quote | Token::Backquote | Token::Comma | Token::CommaAt => {
+                let name = match token {
+                    Token::Quote => "quote",
+                    Token::Backquote => "quasiquote",
+                    Token::Comma => "unquote",
+                    _ => "unquote-splicing",
+                };
                 citer.advance();
-                while let Some(&token) = citer.value() {
-                    if let Token::RightParen = token {
-                        citer.advance();
-                        break;
-                    } else {
-                        match read_sexp(&mut citer) {
-                            Ok(sexp) => contents.push_back(sexp),
-                            Err(e) => {
-                                return Err(e);
-                            },
-                        }
+                let contents = vec![Sexp::Symbol(Rc::from(name)), read_sexp(citer)?];
+                Ok(Sexp::List(contents.into()))
+            },
+            Token::RightParen => Err(ReadError::Invalid(String::from("Unexpected ')'"))),
+            Token::LeftParen | Token::HashParen => {
+                let mut contents = vec![];
+                citer.advance();
+                loop {
+                    match citer.value() {
+                        Some(&Token::RightParen) if matches!(token, Token::HashParen) => {
+                            citer.advance();
+                            return Ok(Sexp::Vector(contents.into()));
+                        },
+                        Some(&Token::RightParen) => {
+                            citer.advance();
+                            return Ok(Sexp::List(contents.into()));
+                        },
+                        Some(_) => contents.push(read_sexp(citer)?),
+                        None => return Err(ReadError::Incomplete),
                     }
                 }
-                Ok(Sexp::List(contents))
             },
         }
     } else {
-        Err(String::from("No tokens in input"))
+        Err(ReadError::Incomplete)
+    }
+}
+
+// Reads every form in src, failing if any of them is unbalanced
+pub fn read_all(src: &str) -> Result<Vec<Sexp>, ReadError> {
+    match read_all_lines(src) {
+        Ok(forms) => Ok(forms.into_iter().map(|(_, sexp)| sexp).collect()),
+        Err((_, e)) => Err(e),
+    }
+}
+
+/// Reads every form in src as `read_all` does, with the line each begins
+/// on, counting from 1. An error comes with the line of the form it is in.
+pub fn read_all_lines(src: &str) -> Result<Vec<(usize, Sexp)>, (usize, ReadError)> {
+    let line = |offset: usize| src[..offset].matches('\n').count() + 1;
+    let (offsets, toks): (Vec<usize>, Vec<Token>) = match util::tokenize_located(src) {
+        Ok(located) => located.into_iter().unzip(),
+        Err((at, LexError::UnterminatedString)) => return Err((line(at), ReadError::Incomplete)),
+        Err((at, e)) => return Err((line(at), ReadError::Invalid(format!("{}", e)))),
+    };
+    let mut citer = ClingyIter::new(toks.iter());
+    let mut forms = vec![];
+    // Newlines are counted only since the form before, as the forms are in order
+    let (mut start, mut counted) = (1, 0);
+    while citer.value().is_some() {
+        let offset = offsets[toks.len() - citer.remaining()];
+        start += src[counted..offset].matches('\n').count();
+        counted = offset;
+        match read_sexp(&mut citer) {
+            Ok(sexp) => forms.push((start, sexp)),
+            Err(e) => return Err((start, e)),
+        }
     }
+    Ok(forms)
+}
+
+/// Accumulates source text, such as lines typed at a prompt, until it
+/// forms a sequence of complete expressions.
+pub struct Reader {
+    buffer: String,
+}
+
+impl Reader {
+    pub fn new() -> Reader {
+        Reader { buffer: String::new() }
+    }
+
+    pub fn feed(&mut self, src: &str) {
+        self.buffer.push_str(src);
+    }
+
+    /// True if nothing but whitespace has been fed since the last read.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.trim().is_empty()
+    }
+
+    pub fn source(&self) -> &str {
+        &self.buffer
+    }
+
+    /// Reads all forms fed so far. If the last one is unfinished, returns
+    /// `ReadError::Incomplete` and keeps the input so that more can be fed;
+    /// otherwise the input is consumed, even when it is invalid.
+    pub fn read_forms(&mut self) -> Result<Vec<Sexp>, ReadError> {
+        let result = read_all(&self.buffer);
+        if result != Err(ReadError::Incomplete) {
+            self.buffer.clear();
+        }
+        result
+    }
+}
+
+impl Default for Reader {
+    fn default() -> Reader { Reader::new() }
 }
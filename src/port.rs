@@ -0,0 +1,378 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Write};
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::str;
+use eval::{EvalError, SValue};
+use foreign::Foreign;
+use native::{Arity, Native};
+use parameter::Parameter;
+
+// Where an input port's characters come from
+enum Source {
+    Stdin,
+    File(BufReader<File>),
+    String(Cursor<Vec<u8>>),
+    Denied, // standing in for stdin when the console is not permitted
+}
+
+// Where an output port's characters go
+enum Sink {
+    Stdout,
+    Stderr,
+    File(BufWriter<File>),
+    String(Vec<u8>), // of UTF-8, as only whole strings are written
+    Denied,
+}
+
+enum State {
+    Reading(Source, Option<char>), // with a character peeked at, if any
+    Writing(Sink),
+    Closed,
+}
+
+/// A source of characters or a destination for them. Scheme code sees
+/// ports as foreign objects, made by `into_svalue`.
+pub struct Port {
+    input: bool,
+    state: RefCell<State>,
+}
+
+fn denied() -> String {
+    String::from("Console access is not permitted")
+}
+
+// Decodes the next UTF-8 character in r
+fn next_char(r: &mut dyn BufRead) -> io::Result<Option<char>> {
+    let first = match r.fill_buf()?.first() {
+        Some(&b) => b,
+        None => return Ok(None),
+    };
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        _ => 4,
+    };
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf[..len])?;
+    match str::from_utf8(&buf[..len]) {
+        Ok(s) => Ok(s.chars().next()),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+fn read<T, F>(source: &mut Source, f: F) -> Result<T, String>
+    where F: FnOnce(&mut dyn BufRead) -> io::Result<T> {
+    let result = match *source {
+        Source::Stdin => {
+            // Let a prompt written without a newline be seen before waiting
+            let _ = io::stdout().flush();
+            f(&mut io::stdin().lock())
+        },
+        Source::File(ref mut r) => f(r),
+        Source::String(ref mut r) => f(r),
+        Source::Denied => return Err(denied()),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Makes a port shared with the host into a Scheme value.
+pub fn wrap(port: Rc<Port>) -> SValue {
+    SValue::Foreign(Rc::new(Foreign::from_rc(port.type_name(), port)))
+}
+
+impl Port {
+    fn new(input: bool, state: State) -> Port {
+        Port { input, state: RefCell::new(state) }
+    }
+
+    pub fn stdin(console: bool) -> Port {
+        Port::new(true, State::Reading(if console { Source::Stdin } else { Source::Denied }, None))
+    }
+
+    pub fn stdout(console: bool) -> Port {
+        Port::new(false, State::Writing(if console { Sink::Stdout } else { Sink::Denied }))
+    }
+
+    pub fn stderr(console: bool) -> Port {
+        Port::new(false, State::Writing(if console { Sink::Stderr } else { Sink::Denied }))
+    }
+
+    pub fn open_input_file(path: &Path) -> io::Result<Port> {
+        let file = File::open(path)?;
+        Ok(Port::new(true, State::Reading(Source::File(BufReader::new(file)), None)))
+    }
+
+    pub fn open_output_file(path: &Path) -> io::Result<Port> {
+        let file = File::create(path)?;
+        Ok(Port::new(false, State::Writing(Sink::File(BufWriter::new(file)))))
+    }
+
+    pub fn open_input_string(s: &str) -> Port {
+        let source = Source::String(Cursor::new(s.as_bytes().to_vec()));
+        Port::new(true, State::Reading(source, None))
+    }
+
+    pub fn open_output_string() -> Port {
+        Port::new(false, State::Writing(Sink::String(vec![])))
+    }
+
+    /// What has been written to a port made by `open_output_string`.
+    pub fn output_string(&self) -> Option<String> {
+        match *self.state.borrow() {
+            State::Writing(Sink::String(ref buf)) => Some(String::from_utf8_lossy(buf).into_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.input
+    }
+
+    pub fn into_svalue(self) -> SValue {
+        wrap(Rc::new(self))
+    }
+
+    fn type_name(&self) -> &'static str {
+        if self.input { "input-port" } else { "output-port" }
+    }
+
+    fn unusable(&self, state: &State) -> String {
+        match *state {
+            State::Closed => format!("The {} is closed", self.type_name()),
+            _ if self.input => String::from("Cannot write to an input port"),
+            _ => String::from("Cannot read from an output port"),
+        }
+    }
+
+    /// The next character, or None at the end of input.
+    pub fn read_char(&self) -> Result<Option<char>, String> {
+        let mut state = self.state.borrow_mut();
+        match *state {
+            State::Reading(ref mut source, ref mut peeked) => match peeked.take() {
+                Some(c) => Ok(Some(c)),
+                None => read(source, next_char),
+            },
+            _ => Err(self.unusable(&state)),
+        }
+    }
+
+    /// The next character, left to be read again.
+    pub fn peek_char(&self) -> Result<Option<char>, String> {
+        let mut state = self.state.borrow_mut();
+        match *state {
+            State::Reading(ref mut source, ref mut peeked) => {
+                if peeked.is_none() {
+                    *peeked = read(source, next_char)?;
+                }
+                Ok(*peeked)
+            },
+            _ => Err(self.unusable(&state)),
+        }
+    }
+
+    /// The rest of the line, without its end, or None at the end of input.
+    pub fn read_line(&self) -> Result<Option<String>, String> {
+        let mut state = self.state.borrow_mut();
+        match *state {
+            State::Reading(ref mut source, ref mut peeked) => {
+                let mut line = String::new();
+                match peeked.take() {
+                    Some('\n') => return Ok(Some(line)),
+                    Some(c) => line.push(c),
+                    None => {},
+                }
+                read(source, |r| r.read_line(&mut line))?;
+                if line.is_empty() {
+                    return Ok(None);
+                }
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(line))
+            },
+            _ => Err(self.unusable(&state)),
+        }
+    }
+
+    /// Up to k characters, or None if the input has already ended.
+    pub fn read_string(&self, k: usize) -> Result<Option<String>, String> {
+        let mut s = String::new();
+        for _ in 0..k {
+            match self.read_char()? {
+                Some(c) => s.push(c),
+                None if s.is_empty() => return Ok(None),
+                None => break,
+            }
+        }
+        Ok(Some(s))
+    }
+
+    /// The text of the next datum, read no further than its end, or None
+    /// if only whitespace and comments remain. Whether the text is valid
+    /// is left to the parser.
+    pub fn read_datum(&self) -> Result<Option<String>, String> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            // Between tokens
+            match self.peek_char()? {
+                Some(c) if c.is_whitespace() => {
+                    self.read_char()?;
+                    continue;
+                },
+                Some(';') => {
+                    self.read_line()?;
+                    continue;
+                },
+                None if text.is_empty() => return Ok(None),
+                None => return Ok(Some(text)),
+                Some(_) => {},
+            }
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            let c = self.read_char()?.unwrap();
+            text.push(c);
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                // A vector
+                '#' if self.peek_char()? == Some('(') => {
+                    text.push(self.read_char()?.unwrap());
+                    depth += 1;
+                },
+                // The datum these quote or unquote follows
+                '\'' | '`' => continue,
+                ',' => {
+                    if self.peek_char()? == Some('@') {
+                        text.push(self.read_char()?.unwrap());
+                    }
+                    continue;
+                },
+                '"' => {
+                    let mut escaped = false;
+                    while let Some(c) = self.read_char()? {
+                        text.push(c);
+                        match c {
+                            '\\' => escaped = !escaped,
+                            '"' if !escaped => break,
+                            _ => escaped = false,
+                        }
+                    }
+                },
+                _ => {
+                    // The character of #\ is taken whatever it is
+                    if c == '#' && self.peek_char()? == Some('\\') {
+                        text.push(self.read_char()?.unwrap());
+                        text.extend(self.read_char()?);
+                    }
+                    while let Some(c) = self.peek_char()? {
+                        if c.is_whitespace() || "()\";'`,".contains(c) {
+                            break;
+                        }
+                        text.push(c);
+                        self.read_char()?;
+                    }
+                },
+            }
+            if depth <= 0 {
+                return Ok(Some(text));
+            }
+        }
+    }
+
+    fn sink<T, F>(&self, f: F) -> Result<T, String>
+        where F: FnOnce(&mut dyn Write) -> io::Result<T> {
+        let mut state = self.state.borrow_mut();
+        let result = match *state {
+            State::Writing(Sink::Stdout) => f(&mut io::stdout()),
+            State::Writing(Sink::Stderr) => f(&mut io::stderr()),
+            State::Writing(Sink::File(ref mut w)) => f(w),
+            State::Writing(Sink::String(ref mut w)) => f(w),
+            State::Writing(Sink::Denied) => return Err(denied()),
+            _ => return Err(self.unusable(&state)),
+        };
+        result.map_err(|e| e.to_string())
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<(), String> {
+        self.sink(|w| w.write_all(s.as_bytes()))
+    }
+
+    /// Writes out anything buffered.
+    pub fn flush(&self) -> Result<(), String> {
+        self.sink(|w| w.flush())
+    }
+
+    /// Flushes an output port, and makes the port unusable. Closing it
+    /// again does nothing.
+    pub fn close(&self) -> Result<(), String> {
+        let writing = match *self.state.borrow() {
+            State::Writing(Sink::Denied) | State::Reading(..) | State::Closed => false,
+            State::Writing(_) => true,
+        };
+        let flushed = if writing { self.flush() } else { Ok(()) };
+        *self.state.borrow_mut() = State::Closed;
+        flushed
+    }
+}
+
+/// The ports an interpreter reads and writes when not given one, and the
+/// files it has opened, which are flushed when the program exits. The
+/// current ports are parameters, so that `parameterize` can change them.
+pub struct Ports {
+    pub input: Rc<Parameter>,
+    pub output: Rc<Parameter>,
+    pub error: Rc<Parameter>,
+    files: Vec<Weak<Port>>,
+}
+
+// A parameter holding port, which only accepts ports of the same direction
+fn current(name: &'static str, port: Port) -> Rc<Parameter> {
+    let input = port.is_input();
+    let converter = Native::new(name, Arity::exactly(1), move |_, args| {
+        match args[0].downcast::<Port>() {
+            Some(ref port) if port.is_input() == input => Ok(args[0].clone()),
+            _ => Err(EvalError::Message(format!("`{}` expected {}; was given {:?}",
+                name, if input { "an input port" } else { "an output port" }, args[0]))),
+        }
+    });
+    Parameter::new(wrap(Rc::new(port)), Some(SValue::Native(Rc::new(converter))))
+}
+
+impl Ports {
+    /// The standard streams, or stand-ins refusing to be used when the
+    /// console is not permitted.
+    pub fn new(console: bool) -> Ports {
+        Ports {
+            input: current("current-input-port", Port::stdin(console)),
+            output: current("current-output-port", Port::stdout(console)),
+            error: current("current-error-port", Port::stderr(console)),
+            files: vec![],
+        }
+    }
+
+    pub fn open(&mut self, port: Port) -> SValue {
+        let port = Rc::new(port);
+        self.files.retain(|file| file.strong_count() > 0);
+        self.files.push(Rc::downgrade(&port));
+        wrap(port)
+    }
+
+    pub fn flush_all(&self) {
+        let files = self.files.iter().filter_map(Weak::upgrade);
+        let current = vec![self.output.get().downcast::<Port>(), self.error.get().downcast::<Port>()];
+        for port in files.chain(current.into_iter().flatten()) {
+            if !port.is_input() {
+                let _ = port.flush();
+            }
+        }
+    }
+}
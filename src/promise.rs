@@ -0,0 +1,151 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use eval;
+use eval::{EvalError, SValue};
+use gc;
+use gc::Trace;
+use interpreter::Interpreter;
+
+/// What `delay`, `delay-force` and `make-promise` return: a value computed
+/// when first forced, and remembered after.
+pub struct Promise {
+    state: RefCell<State>,
+    stream: bool, // made by the stream procedures, for `stream?`
+}
+
+#[derive(Clone)]
+enum State {
+    Done(SValue),
+    Delayed(SValue), // a thunk giving the value
+    Lazy(SValue),    // a thunk giving another promise, whose value is this one's
+    // Took part in a chain of delay-forces, whose value is the first one's
+    Shared(Rc<Promise>),
+}
+
+impl Promise {
+    fn make(state: State, stream: bool) -> SValue {
+        let promise = Rc::new(Promise { state: RefCell::new(state), stream });
+        gc::track(&promise);
+        SValue::Promise(promise)
+    }
+
+    /// A promise already holding v.
+    pub fn done(v: SValue) -> SValue {
+        Promise::make(State::Done(v), false)
+    }
+
+    /// A promise of the value thunk gives.
+    pub fn delayed(thunk: SValue) -> SValue {
+        Promise::make(State::Delayed(thunk), false)
+    }
+
+    /// A promise of the value of the promise thunk gives.
+    pub fn lazy(thunk: SValue) -> SValue {
+        Promise::make(State::Lazy(thunk), false)
+    }
+
+    /// As `done`, for a stream: the empty list, or a pair of a promise of
+    /// the first element and the stream of the rest.
+    pub fn stream_done(v: SValue) -> SValue {
+        Promise::make(State::Done(v), true)
+    }
+
+    /// As `lazy`, for a stream.
+    pub fn stream_lazy(thunk: SValue) -> SValue {
+        Promise::make(State::Lazy(thunk), true)
+    }
+
+    /// Whether this promise was made as a stream.
+    pub fn is_stream(&self) -> bool {
+        self.stream
+    }
+
+    /// The value, if the promise has been forced.
+    pub fn value(&self) -> Option<SValue> {
+        match *self.state.borrow() {
+            State::Done(ref v) => Some(v.clone()),
+            State::Shared(ref p) => p.value(),
+            _ => None,
+        }
+    }
+}
+
+// The promise that holds the state of p
+fn resolve(mut p: Rc<Promise>) -> Rc<Promise> {
+    loop {
+        let next = match *p.state.borrow() {
+            State::Shared(ref q) => Some(q.clone()),
+            _ => None,
+        };
+        match next {
+            Some(q) => p = q,
+            None => return p,
+        }
+    }
+}
+
+/// The value of v if it is a promise, computing it if need be, or else v.
+///
+/// A delay-force chain is followed in a loop rather than by recursion: the
+/// promise being forced takes over the state of the one its thunk gave,
+/// which from then on shares it, so a chain of any length needs neither
+/// stack nor memory in proportion.
+pub fn force(interp: &mut Interpreter, v: &SValue) -> Result<SValue, EvalError> {
+    let mut p = match *v {
+        SValue::Promise(ref p) => p.clone(),
+        _ => return Ok(v.clone()),
+    };
+    loop {
+        p = resolve(p);
+        let state = p.state.borrow().clone();
+        match state {
+            State::Done(v) => return Ok(v),
+            State::Delayed(thunk) => {
+                let v = eval::apply(interp, thunk, vec![])?;
+                // Forcing it again from within the thunk may have set it
+                if p.value().is_none() {
+                    *p.state.borrow_mut() = State::Done(v);
+                }
+            },
+            State::Lazy(thunk) => {
+                let q = eval::apply(interp, thunk, vec![])?;
+                if p.value().is_some() {
+                    continue;
+                }
+                match q {
+                    SValue::Promise(q) => {
+                        let q = resolve(q);
+                        if Rc::ptr_eq(&p, &q) {
+                            return Err(EvalError::from("`force` found a promise whose value is itself"));
+                        }
+                        let taken = q.state.replace(State::Shared(p.clone()));
+                        *p.state.borrow_mut() = taken;
+                    },
+                    v => *p.state.borrow_mut() = State::Done(v),
+                }
+            },
+            State::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+impl Trace for Promise {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        match self.state.try_borrow() {
+            Ok(state) => {
+                match *state {
+                    State::Done(ref v) | State::Delayed(ref v) | State::Lazy(ref v) => {
+                        eval::trace_value(v, out)
+                    },
+                    State::Shared(ref p) => out.push(gc::address(p)),
+                }
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        *self.state.borrow_mut() = State::Done(SValue::Nil);
+    }
+}
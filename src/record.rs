@@ -0,0 +1,75 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use eval;
+use eval::SValue;
+use gc;
+use gc::Trace;
+
+/// A type made by `define-record-type`. Each definition makes a distinct
+/// type, even one with the same name and fields as another.
+pub struct RecordType {
+    pub name: Rc<str>,
+    pub fields: Vec<Rc<str>>,
+}
+
+impl RecordType {
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| &**f == field)
+    }
+}
+
+/// An instance of a record type, with a value for each of its fields.
+pub struct Record {
+    rtype: Rc<RecordType>,
+    fields: Vec<RefCell<SValue>>,
+}
+
+impl Record {
+    pub fn new(rtype: &Rc<RecordType>, values: Vec<SValue>) -> Rc<Record> {
+        let record = Rc::new(Record {
+            rtype: rtype.clone(),
+            fields: values.into_iter().map(RefCell::new).collect(),
+        });
+        gc::track(&record);
+        record
+    }
+
+    pub fn record_type(&self) -> &Rc<RecordType> {
+        &self.rtype
+    }
+
+    pub fn is(&self, rtype: &Rc<RecordType>) -> bool {
+        Rc::ptr_eq(&self.rtype, rtype)
+    }
+
+    pub fn get(&self, index: usize) -> SValue {
+        self.fields[index].borrow().clone()
+    }
+
+    pub fn set(&self, index: usize, value: SValue) {
+        *self.fields[index].borrow_mut() = value;
+    }
+
+    /// The fields' names and values, in the order they were declared.
+    pub fn entries(&self) -> Vec<(Rc<str>, SValue)> {
+        self.rtype.fields.iter().cloned().zip(self.fields.iter().map(|v| v.borrow().clone())).collect()
+    }
+}
+
+impl Trace for Record {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        for field in &self.fields {
+            match field.try_borrow() {
+                Ok(value) => eval::trace_value(&value, out),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn clear(&self) {
+        for field in &self.fields {
+            *field.borrow_mut() = SValue::Nil;
+        }
+    }
+}
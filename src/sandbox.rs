@@ -0,0 +1,97 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Where scripts may read and write files.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileAccess {
+    None,
+    /// Reading files within a directory. Paths are taken relative to it,
+    /// even absolute ones, and may not lead out of it.
+    ReadOnly(PathBuf),
+    ReadWrite(PathBuf),
+    Unrestricted,
+}
+
+/// The groups of primitives an interpreter gives its scripts, beyond the
+/// pure ones (arithmetic, lists, strings and the like) that it always has.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Capabilities {
+    /// Standard input, output and error, which `display`, `read-line` and
+    /// the like use by default. Without it, those ports refuse to be used.
+    pub console: bool,
+    /// Ending the process and seeing its arguments: `exit` and `command-line`.
+    pub process: bool,
+    /// Opening files, as with `open-input-file` and `with-output-to-file`.
+    pub files: FileAccess,
+}
+
+impl Capabilities {
+    /// Everything; what `Interpreter::new` gives.
+    pub fn all() -> Capabilities {
+        Capabilities { console: true, process: true, files: FileAccess::Unrestricted }
+    }
+
+    /// Nothing beyond computing values.
+    pub fn pure() -> Capabilities {
+        Capabilities { console: false, process: false, files: FileAccess::None }
+    }
+
+    /// Checks that a script may open the file at `path`, for writing or
+    /// only reading, and returns the path to open.
+    pub fn file_path(&self, path: &str, write: bool) -> Result<PathBuf, String> {
+        let (root, writable) = match self.files {
+            FileAccess::None => {
+                return Err(format!("Access to files is not permitted: {}", path));
+            },
+            FileAccess::ReadOnly(ref root) => (root, false),
+            FileAccess::ReadWrite(ref root) => (root, true),
+            FileAccess::Unrestricted => return Ok(PathBuf::from(path)),
+        };
+        if write && !writable {
+            return Err(format!("Writing files is not permitted: {}", path));
+        }
+        let outside = || format!("Access outside the permitted directory: {}", path);
+
+        let mut resolved = root.clone();
+        let mut depth = 0;
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => {
+                    resolved.push(name);
+                    depth += 1;
+                },
+                Component::ParentDir if depth > 0 => {
+                    resolved.pop();
+                    depth -= 1;
+                },
+                Component::ParentDir => return Err(outside()),
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {},
+            }
+        }
+        // A symbolic link inside the root could still lead out of it
+        match (real_path(&resolved), fs::canonicalize(root)) {
+            (Ok(real), Ok(real_root)) if real.starts_with(&real_root) => Ok(resolved),
+            (Err(e), _) | (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => Ok(resolved),
+            _ => Err(outside()),
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities { Capabilities::all() }
+}
+
+// Resolves links in a path whose last component need not exist yet, as
+// when creating a file
+fn real_path(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => Ok(fs::canonicalize(parent)?.join(name)),
+                _ => fs::canonicalize(path),
+            }
+        },
+        result => result,
+    }
+}
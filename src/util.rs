@@ -1,5 +1,4 @@
-use std::ops::Index;
-use std::ops::Range;
+use std::fmt;
 use std::str::FromStr;
 use std::slice::Iter;
 
@@ -8,9 +7,15 @@ pub enum Token<'a> {
     Symbol(&'a str),
     Number(f64),
     Bool(bool), // #t and #f
+    Char(char), // #\a, #\space
     String(&'a str),
     LeftParen,
+    HashParen, // the #( opening a vector
     RightParen,
+    Quote, // the ' in 'x
+    Backquote, // the ` in `(a ,b)
+    Comma, // the , in `(a ,b)
+    CommaAt, // the ,@ in `(a ,@b)
 }
 
 #[derive(Debug, Clone)]
@@ -18,8 +23,10 @@ enum ParsingState<'a> {
     Ready,
     Symbol(usize),
     Hash(usize),
+    Char(usize), // from the # of #\
     String(usize, Option<usize>),
-    Error(LexError<'a>),
+    Comment,
+    Error(usize, LexError<'a>), // at the start of the token in error
 }
 
 #[derive(Debug, Clone)]
@@ -27,72 +34,118 @@ pub enum LexError<'a> {
     IllegalCharacter(char),
     IllegalNumber(&'a str),
     IllegalHash(&'a str),
+    UnterminatedString,
+}
+
+impl<'a> fmt::Display for LexError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexError::IllegalCharacter(c) => write!(f, "Illegal character: {:?}", c),
+            LexError::IllegalNumber(s) => write!(f, "Illegal number: {}", s),
+            LexError::IllegalHash(s) => write!(f, "Illegal # syntax: {}", s),
+            LexError::UnterminatedString => write!(f, "Unterminated string"),
+        }
+    }
 }
 
 pub fn is_symbol_char(ch: char) -> bool {
-    let others = String::from("!$%&*+-./:<=>?@^_~");
-    let mut pat = String::new();
-    pat.push(ch);
-    ch.is_alphanumeric() || others.contains(&pat[0..1])
+    ch.is_alphanumeric() || "!$%&*+-./:<=>?@^_~".contains(ch)
+}
+
+const CHAR_NAMES: [(&str, char); 9] = [
+    ("space", ' '), ("newline", '\n'), ("tab", '\t'), ("return", '\r'), ("null", '\0'),
+    ("alarm", '\u{7}'), ("backspace", '\u{8}'), ("delete", '\u{7f}'), ("escape", '\u{1b}'),
+];
+
+/// The name a character is written with after #\, if it has one.
+pub fn char_name(c: char) -> Option<&'static str> {
+    CHAR_NAMES.iter().find(|&&(_, named)| named == c).map(|&(name, _)| name)
+}
+
+// The character of #\x: x is a single character, a name, or xHH in hex
+fn char_literal(slice: &str) -> Result<Token<'_>, LexError<'_>> {
+    let text = &slice[2..];
+    let mut chars = text.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(Token::Char(c));
+    }
+    if let Some(&(_, c)) = CHAR_NAMES.iter().find(|&&(name, _)| name == text) {
+        return Ok(Token::Char(c));
+    }
+    text.strip_prefix('x')
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(char::from_u32)
+        .map(Token::Char)
+        .ok_or(LexError::IllegalHash(slice))
+}
+
+// Classifies a complete run of symbol characters (or a #-prefixed run)
+fn atom(slice: &str) -> Result<Token<'_>, LexError<'_>> {
+    match slice {
+        "#t" | "#true" => Ok(Token::Bool(true)),
+        "#f" | "#false" => Ok(Token::Bool(false)),
+        _ if slice.starts_with('#') => Err(LexError::IllegalHash(slice)),
+        _ => {
+            if let Ok(num) = f64::from_str(slice) {
+                Ok(Token::Number(num))
+            } else if slice.starts_with(|c: char| c.is_ascii_digit()) {
+                Err(LexError::IllegalNumber(slice))
+            } else {
+                Ok(Token::Symbol(slice))
+            }
+        }
+    }
 }
 
 // lexer for s-expressions
-pub fn tokenize<'a>(src: &'a String) -> Result<Vec<Token<'a>>, LexError<'a>> {
-    let mut tokens: Vec<Token> = vec![];
+pub fn tokenize(src: &str) -> Result<Vec<Token<'_>>, LexError<'_>> {
+    match tokenize_located(src) {
+        Ok(tokens) => Ok(tokens.into_iter().map(|(_, tok)| tok).collect()),
+        Err((_, e)) => Err(e),
+    }
+}
+
+/// Tokenizes src as `tokenize` does, giving the offset in src at which
+/// each token, or the token in error, begins.
+pub fn tokenize_located(src: &str) -> Result<Vec<(usize, Token<'_>)>, (usize, LexError<'_>)> {
+    let mut tokens: Vec<(usize, Token)> = vec![];
     let mut parsing = ParsingState::Ready;
 
     for (i, c) in src.char_indices() {
         match parsing {
-            ParsingState::Error(_) => break,
-
-            ParsingState::Hash(start) => {
-                if i == start + 2 {
-                    let slice = &src[start..i];
-                    match slice {
-                        "#t" => {
-                            tokens.push(Token::Bool(true));
-                            parsing = ParsingState::Ready;
-                        },
-                        "#f" => {
-                            tokens.push(Token::Bool(true));
-                            parsing = ParsingState::Ready;
-                        },
-                        _ => {
-                            parsing = ParsingState::Error(
-                                LexError::IllegalHash(&slice));
-                        },
-                    }
-                }
+            ParsingState::Error(..) => break,
+
+            ParsingState::Hash(start) if c == '\\' && i == start + 1 => {
+                parsing = ParsingState::Char(start);
+                continue;
             },
 
-            ParsingState::Symbol(start) => {
-                if !is_symbol_char(c) {
-                    let range = Range { start: start, end: i };
-                    let slice = src.index(range);
-                    match slice {
-                        "#t" => {
-                            tokens.push(Token::Bool(true));
-                            parsing = ParsingState::Ready;
-                        },
-                        "#f" => {
-                            tokens.push(Token::Bool(false));
-                            parsing = ParsingState::Ready;
-                        },
-                        _ => {
-                            if let Ok(num) = f64::from_str(slice) {
-                                tokens.push(Token::Number(num));
-                                parsing = ParsingState::Ready;
-                            } else {
-                                if i64::from_str(&slice[..1]).is_ok() {
-                                    parsing = ParsingState::Error(LexError::IllegalNumber(slice));
-                                } else {
-                                    tokens.push(Token::Symbol(slice));
-                                    parsing = ParsingState::Ready;
-                                }
-                            }
-                        }
-                    }
-                }
+            ParsingState::Hash(start) if c == '(' && i == start + 1 => {
+                tokens.push((start, Token::HashParen));
+                parsing = ParsingState::Ready;
+                continue;
+            },
+
+            // The first character after #\ is taken whatever it is
+            ParsingState::Char(start) if i > start + 2 && !is_symbol_char(c) => {
+                parsing = match char_literal(&src[start..i]) {
+                    Ok(tok) => {
+                        tokens.push((start, tok));
+                        ParsingState::Ready
+                    },
+                    Err(e) => ParsingState::Error(start, e),
+                };
+            },
+
+            ParsingState::Symbol(start) | ParsingState::Hash(start)
+                if !is_symbol_char(c) => {
+                parsing = match atom(&src[start..i]) {
+                    Ok(tok) => {
+                        tokens.push((start, tok));
+                        ParsingState::Ready
+                    },
+                    Err(e) => ParsingState::Error(start, e),
+                };
             },
 
             ParsingState::String(start, o_esc) => {
@@ -101,8 +154,10 @@ pub fn tokenize<'a>(src: &'a String) -> Result<Vec<Token<'a>>, LexError<'a>> {
                         if c == '\\' {
                             parsing = ParsingState::String(start, Some(i))
                         } else if c == '"' {
-                            tokens.push(Token::String(&src[start..i]));
+                            tokens.push((start - 1, Token::String(&src[start..i])));
+                            // The closing quote must not be re-read below
                             parsing = ParsingState::Ready;
+                            continue;
                         }
                     },
                     Some(_backslash_pos) => {
@@ -112,37 +167,57 @@ pub fn tokenize<'a>(src: &'a String) -> Result<Vec<Token<'a>>, LexError<'a>> {
                 }
             },
 
+            ParsingState::Comment if c == '\n' => {
+                parsing = ParsingState::Ready;
+            },
+
             _ => {},
         }
 
-        match parsing {
-            ParsingState::Ready => {
-                match c {
-                    '(' => tokens.push(Token::LeftParen),
-                    ')' => tokens.push(Token::RightParen),
-                    '#' => {
-                        parsing = ParsingState::Hash(i);
-                    },
-                    '"' => {
-                        parsing = ParsingState::String(i + 1, None);
-                    }
-                    _ => {
-                        parsing =
-                            if is_symbol_char(c) {
-                                ParsingState::Symbol(i)
-                            } else if c.is_whitespace() {
-                                ParsingState::Ready
-                            } else {
-                                ParsingState::Error(LexError::IllegalCharacter(c))
-                            };
-                    },
+        if let ParsingState::Ready = parsing {
+            match c {
+                '(' => tokens.push((i, Token::LeftParen)),
+                ')' => tokens.push((i, Token::RightParen)),
+                '\'' => tokens.push((i, Token::Quote)),
+                '`' => tokens.push((i, Token::Backquote)),
+                ',' => tokens.push((i, Token::Comma)),
+                '@' if matches!(tokens.last(), Some(&(at, Token::Comma)) if at + 1 == i) => {
+                    tokens.last_mut().unwrap().1 = Token::CommaAt;
+                },
+                ';' => {
+                    parsing = ParsingState::Comment;
+                },
+                '#' => {
+                    parsing = ParsingState::Hash(i);
+                },
+                '"' => {
+                    parsing = ParsingState::String(i + 1, None);
                 }
-            },
-            _ => {},
+                _ => {
+                    parsing =
+                        if is_symbol_char(c) {
+                            ParsingState::Symbol(i)
+                        } else if c.is_whitespace() {
+                            ParsingState::Ready
+                        } else {
+                            ParsingState::Error(i, LexError::IllegalCharacter(c))
+                        };
+                },
+            }
         }
     }
     match parsing {
-        ParsingState::Error(e) => Err(e),
+        ParsingState::Error(at, e) => Err((at, e)),
+        ParsingState::String(start, _) => Err((start - 1, LexError::UnterminatedString)),
+        ParsingState::Symbol(start) | ParsingState::Hash(start) => {
+            tokens.push((start, atom(&src[start..]).map_err(|e| (start, e))?));
+            Ok(tokens)
+        },
+        ParsingState::Char(start) if src.len() > start + 2 => {
+            tokens.push((start, char_literal(&src[start..]).map_err(|e| (start, e))?));
+            Ok(tokens)
+        },
+        ParsingState::Char(start) => Err((start, LexError::IllegalHash(&src[start..]))),
         _ => Ok(tokens),
     }
 }
@@ -154,21 +229,18 @@ pub struct ClingyIter<'a, T> where T: 'a {
 
 impl<'a, T> ClingyIter<'a, T> {
     pub fn new(iter: Iter<'a, T>) -> ClingyIter<'a, T> {
-        let mut citer = ClingyIter { iter: iter, item: None };
+        let mut citer = ClingyIter { iter, item: None };
         citer.advance();
         citer
     }
     pub fn advance(&mut self) {
         self.item = self.iter.next();
-        //match self.iter.next() {
-        //    Some(thing) => self.item = Some(*thing),
-        //    None => self.item = None,
-        //}
     }
-    pub fn value(&'a self) -> Option<&'a T> {
-        match self.item {
-            Some(ref x) => Some(x),
-            None => None,
-        }
+    pub fn value(&self) -> Option<&'a T> {
+        self.item
+    }
+    /// How many items are left, counting the current one.
+    pub fn remaining(&self) -> usize {
+        self.iter.len() + self.item.map_or(0, |_| 1)
     }
 }
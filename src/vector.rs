@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use eval;
+use eval::SValue;
+use gc;
+use gc::Trace;
+
+/// A fixed-length array of values, written `#(1 2 3)`, whose elements can
+/// be replaced in place.
+pub struct Vector {
+    items: RefCell<Vec<SValue>>,
+}
+
+impl Vector {
+    pub fn new(items: Vec<SValue>) -> Rc<Vector> {
+        let vector = Rc::new(Vector { items: RefCell::new(items) });
+        gc::track(&vector);
+        vector
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.borrow().is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<SValue> {
+        self.items.borrow().get(index).cloned()
+    }
+
+    /// Replaces the element at index, returning false if there is none.
+    pub fn set(&self, index: usize, value: SValue) -> bool {
+        match self.items.borrow_mut().get_mut(index) {
+            Some(item) => {
+                *item = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// A copy of the elements.
+    pub fn to_vec(&self) -> Vec<SValue> {
+        self.items.borrow().clone()
+    }
+}
+
+impl Trace for Vector {
+    fn trace(&self, out: &mut Vec<*const ()>) -> bool {
+        match self.items.try_borrow() {
+            Ok(items) => {
+                for item in items.iter() {
+                    eval::trace_value(item, out);
+                }
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn clear(&self) {
+        self.items.borrow_mut().clear();
+    }
+
+    fn size(&self) -> usize {
+        self.items.try_borrow().map_or(1, |items| 1 + items.len())
+    }
+}
@@ -0,0 +1,154 @@
+use std::iter;
+use std::mem;
+use std::rc::Rc;
+use compile::{Op, Template};
+use eval;
+use eval::{Body, Closure, Env, EvalError, SValue, SymTable};
+use interpreter::Interpreter;
+
+// A call in progress: the code, where it has got to, and its frame
+struct Activation {
+    template: Rc<Template>,
+    env: Env,
+    pc: usize,
+}
+
+/// Runs compiled top-level code in the scope env.
+pub fn run(interp: &mut Interpreter, template: Rc<Template>, env: Env) -> Result<SValue, EvalError> {
+    execute(interp, Activation { template, env, pc: 0 })
+}
+
+/// Applies a compiled procedure, closed over env, to args.
+pub fn call(interp: &mut Interpreter, env: &Env, template: &Rc<Template>, args: Vec<SValue>)
+            -> Result<SValue, EvalError> {
+    let frame = make_frame(env, template, args)?;
+    interp.push_call()?;
+    let result = execute(interp, Activation { template: template.clone(), env: frame, pc: 0 });
+    interp.pop_call();
+    result
+}
+
+// Binds the arguments in a new frame, with a slot for each internal define
+fn make_frame(env: &Env, template: &Template, args: Vec<SValue>) -> Result<Env, EvalError> {
+    let params = &template.params;
+    eval::check_arity(params, args.len())?;
+    let mut args = args.into_iter();
+    let mut values: Vec<SValue> = args.by_ref().take(params.names.len()).collect();
+    if params.rest.is_some() {
+        values.push(args.collect());
+    }
+    let items = template.frame.iter().cloned()
+        .zip(values.into_iter().chain(iter::repeat(SValue::Unbound)))
+        .collect();
+    Ok(SymTable::child(env, items).into_env())
+}
+
+fn scope(env: &Env, depth: usize) -> Env {
+    let mut env = env.clone();
+    for _ in 0..depth {
+        let parent = env.borrow().parent().expect("compiled code ran in too shallow a scope");
+        env = parent;
+    }
+    env
+}
+
+fn unbound(name: &str) -> EvalError {
+    EvalError::Message(format!("Symbol is not bound: {}", name))
+}
+
+fn execute(interp: &mut Interpreter, act: Activation) -> Result<SValue, EvalError> {
+    let depth = interp.call_depth();
+    let result = execute_loop(interp, act);
+    // Calls cut short by an error never returned
+    interp.set_call_depth(depth);
+    result
+}
+
+// Calls to compiled procedures, tail or not, are made within this loop,
+// so that Scheme recursion doesn't use the Rust stack
+fn execute_loop(interp: &mut Interpreter, mut act: Activation) -> Result<SValue, EvalError> {
+    let mut stack: Vec<SValue> = vec![];
+    let mut callers: Vec<Activation> = vec![];
+    loop {
+        interp.step()?;
+        let op = act.template.code[act.pc];
+        act.pc += 1;
+        match op {
+            Op::Const(i) => stack.push(act.template.consts[i].clone()),
+            Op::Local(depth, i) => {
+                let v = act.env.borrow().get(depth, i);
+                if let SValue::Unbound = v {
+                    return Err(unbound(&act.env.borrow().name_at(depth, i)));
+                }
+                stack.push(v);
+            },
+            Op::SetLocal(depth, i) => {
+                let v = stack.pop().unwrap();
+                act.env.borrow_mut().set_at(depth, i, v);
+                stack.push(SValue::nil());
+            },
+            Op::Free(depth, i) => {
+                let name = &act.template.names[i];
+                let found = scope(&act.env, depth).borrow().lookup(name);
+                stack.push(found.ok_or_else(|| unbound(name))?);
+            },
+            Op::SetFree(depth, i) => {
+                let name = &act.template.names[i];
+                let v = stack.pop().unwrap();
+                if !scope(&act.env, depth).borrow_mut().set(name, v) {
+                    return Err(unbound(name));
+                }
+                stack.push(SValue::nil());
+            },
+            Op::Define(i) => {
+                let v = stack.pop().unwrap();
+                act.env.borrow_mut().assign(&act.template.names[i], v);
+                stack.push(SValue::nil());
+            },
+            Op::Closure(i) => {
+                let template = act.template.templates[i].clone();
+                stack.push(SValue::Lambda(Closure::new(&act.env, Body::Compiled(template))));
+            },
+            Op::Jump(target) => act.pc = target,
+            Op::JumpUnless(target) => {
+                if !stack.pop().unwrap().is_true() {
+                    act.pc = target;
+                }
+            },
+            Op::JumpUnlessIs(i, target) => {
+                if stack.pop().unwrap() != act.template.consts[i] {
+                    act.pc = target;
+                }
+            },
+            Op::Pop => {
+                stack.pop();
+            },
+            Op::Call(argc) | Op::TailCall(argc) => {
+                let args = stack.split_off(stack.len() - argc);
+                let f = stack.pop().unwrap();
+                if let SValue::Lambda(ref closure) = f {
+                    if let Body::Compiled(ref template) = closure.body {
+                        let frame = make_frame(&closure.env, template, args)?;
+                        let callee = Activation { template: template.clone(), env: frame, pc: 0 };
+                        if let Op::TailCall(_) = op {
+                            act = callee;
+                        } else {
+                            interp.push_call()?;
+                            callers.push(mem::replace(&mut act, callee));
+                        }
+                        continue;
+                    }
+                }
+                // Anything after a tail call only jumps on to Return
+                stack.push(eval::apply(interp, f, args)?);
+            },
+            Op::Return => match callers.pop() {
+                Some(caller) => {
+                    interp.pop_call();
+                    act = caller;
+                },
+                None => return Ok(stack.pop().unwrap()),
+            },
+        }
+    }
+}
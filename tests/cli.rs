@@ -0,0 +1,104 @@
+extern crate scheme;
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use common::scratch_dir;
+
+fn scheme(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_scheme"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn expressions_run_in_order() {
+    let out = scheme(&["-e", "(define x 2)", "-e", "(display (* x 21))"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "42");
+}
+
+#[test]
+fn expressions_ignore_stdin() {
+    let out = scheme(&["-e", "(display 1)"], "(display 2)");
+    assert_eq!(stdout(&out), "1");
+}
+
+#[test]
+fn piped_input_is_run_as_a_program() {
+    let out = scheme(&[], "(display \"piped\")\n(newline)");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "piped\n");
+
+    // `-` says so explicitly, and takes arguments after it
+    let out = scheme(&["-", "a", "b"], "(write (command-line))");
+    assert_eq!(stdout(&out), "(\"-\" \"a\" \"b\")");
+}
+
+#[test]
+fn files_get_their_arguments() {
+    let dir = scratch_dir("args", &[("prog.scm", "(write (cdr (command-line)))")]);
+    let prog = dir.join("prog.scm");
+    let prog = prog.to_str().unwrap();
+
+    let out = scheme(&[prog, "x", "-e", "y"], "");
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(stdout(&out), "(\"x\" \"-e\" \"y\")");
+
+    // After `--` even a leading dash is an argument
+    let out = scheme(&["--", prog, "-i"], "");
+    assert_eq!(stdout(&out), "(\"-i\")");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn expressions_get_the_positional_arguments() {
+    let out = scheme(&["-e", "(write (cdr (command-line)))", "--", "-x", "y"], "");
+    assert_eq!(stdout(&out), "(\"-x\" \"y\")");
+}
+
+#[test]
+fn exit_sets_the_status() {
+    let out = scheme(&["-e", "(display 1)", "-e", "(exit 7)", "-e", "(display 2)"], "");
+    assert_eq!(out.status.code(), Some(7));
+    assert_eq!(stdout(&out), "1");
+
+    assert_eq!(scheme(&[], "(exit #f)").status.code(), Some(1));
+    assert_eq!(scheme(&[], "(exit)").status.code(), Some(0));
+}
+
+#[test]
+fn errors_exit_with_status_one() {
+    let out = scheme(&["-e", "(car 1)"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with("Error: "));
+
+    let out = scheme(&["-e", "(display 1) (display"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Parse error"));
+
+    let out = scheme(&[], "(display 1)\n(undefined-procedure)\n(display 2)");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stdout(&out), "1");
+}
+
+#[test]
+fn bad_options_exit_with_status_two() {
+    let out = scheme(&["--frobnicate"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unknown option"));
+    assert_eq!(scheme(&["-e"], "").status.code(), Some(2));
+}
@@ -0,0 +1,33 @@
+// Helpers shared by the integration tests; each uses only some of them
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use scheme::{Engine, Interpreter};
+
+pub const ENGINES: [Engine; 2] = [Engine::Bytecode, Engine::TreeWalker];
+
+// A fresh directory holding the given files, named for the test binary and
+// the process so that tests running at once don't share one
+pub fn scratch_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let test = env::current_exe().unwrap().file_stem().unwrap().to_string_lossy().into_owned();
+    let dir = env::temp_dir().join(format!("scheme-{}-{}-{}", test, name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for &(path, text) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    dir
+}
+
+pub fn eval(interp: &mut Interpreter, src: &str) -> String {
+    format!("{:?}", interp.eval_str(src).unwrap())
+}
+
+pub fn error(interp: &mut Interpreter, src: &str) -> String {
+    format!("{}", interp.eval_str(src).unwrap_err())
+}
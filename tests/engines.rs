@@ -0,0 +1,542 @@
+extern crate scheme;
+
+use scheme::{Engine, EvalError, Interpreter};
+
+// Runs src with each engine, checking that they agree, and returns the
+// printed value of the last expression or the error message
+fn run(src: &str) -> Result<String, String> {
+    let mut results = [Engine::TreeWalker, Engine::Bytecode].iter().map(|&engine| {
+        let mut interp = Interpreter::with_engine(engine);
+        match interp.eval_str(src) {
+            Ok(v) => Ok(format!("{:?}", v)),
+            Err(EvalError::Message(e)) => Err(e),
+            Err(e) => Err(format!("{}", e)),
+        }
+    });
+    let (tree, bytecode) = (results.next().unwrap(), results.next().unwrap());
+    assert_eq!(tree, bytecode, "engines disagree on {}", src);
+    tree
+}
+
+fn check(src: &str, expected: &str) {
+    assert_eq!(run(src), Ok(String::from(expected)), "evaluating {}", src);
+}
+
+fn check_err(src: &str, expected: &str) {
+    assert_eq!(run(src), Err(String::from(expected)), "evaluating {}", src);
+}
+
+#[test]
+fn literals_and_quote() {
+    check("42", "42");
+    check("#t", "#t");
+    check("\"hi\"", "\"hi\"");
+    check("'sym", "sym");
+    check("'(1 (2 \"three\") . 4)", "(1 (2 \"three\") . 4)");
+    check("'()", "()");
+    check("#\\a", "#\\a");
+    check("'(#\\space #\\( #\\x41 #\\λ)", "(#\\space #\\( #\\A #\\λ)");
+    check_err("(quote)", "`quote` expected 1 arg; was given 0");
+    check_err("()", "Unexpected ()");
+}
+
+#[test]
+fn definitions_and_assignment() {
+    check("(define x 1) x", "1");
+    check("(define x 1) (set! x (+ x 1)) x", "2");
+    check("(define x 1) (define x 2) x", "2");
+    check("(define x 1)", "()");
+    check_err("y", "Symbol is not bound: y");
+    check_err("(set! y 1)", "Symbol is not bound: y");
+    check_err("(define 5 1)", "Expected symbol after `define`");
+    check_err("(define x)", "Expected value in define statement");
+    check_err("(set! x)", "Expected symbol and value after `set!`");
+}
+
+#[test]
+fn conditionals_and_sequencing() {
+    check("(if #t 1 2)", "1");
+    check("(if #f 1 2)", "2");
+    check("(if 0 'yes 'no)", "yes");
+    check("(if #f #f)", "()");
+    check("(begin 1 2 3)", "3");
+    check("(begin)", "()");
+    check_err("(if #t)", "`if` expected 2 or 3 args");
+}
+
+#[test]
+fn procedures() {
+    check("((lambda (x y) (+ x y)) 1 2)", "3");
+    check("(define (f . xs) xs) (f 1 2 3)", "(1 2 3)");
+    check("(define (f a . xs) (list a xs)) (f 1)", "(1 ())");
+    check("((lambda xs xs))", "()");
+    check("(define (f) (define a 1) (define b (+ a 1)) (* a b)) (f)", "2");
+    check("(define (f x) (define x 5) x) (f 1)", "5");
+    // Internal defines are unbound until they run, even in a closure
+    check_err("(define (f) (define a b) (define b 1) a) (f)", "Symbol is not bound: b");
+    check_err("(define (f) (define (g) b) (define a (g)) (define b 1) a) (f)", "Symbol is not bound: b");
+    check("(define (f) (define (g) b) (define b 1) (g)) (f)", "1");
+    check("(define (f) 1 2 3) (f)", "3");
+    check("(lambda (x) x)", "#<procedure>");
+    check("car", "#<procedure car>");
+    check_err("((lambda (x) x))", "Expected 1 args; was given 0");
+    check_err("((lambda (x . r) x))", "Expected at least 1 args; was given 0");
+    check_err("(1 2)", "Expected callable value");
+    check_err("(lambda)", "Expected argument list after `lambda`");
+    check_err("(lambda (x))", "Expected body after argument list in lambda");
+    check_err("(lambda (1) 1)", "Expected symbol in argument list");
+}
+
+#[test]
+fn closures_and_scope() {
+    check("
+        (define (make-counter)
+          (define n 0)
+          (lambda () (set! n (+ n 1)) n))
+        (define a (make-counter))
+        (define b (make-counter))
+        (a) (a) (b)
+        (list (a) (b))", "(3 2)");
+    check("(define x 'global) (define (f x) (lambda () x)) ((f 'local))", "local");
+    check("(define x 1) (define (get) x) (define x 2) (get)", "2");
+    check("(define (adder n) (lambda (m) (+ n m))) ((adder 3) 4)", "7");
+    check("
+        (define (outer)
+          (define v 1)
+          (define (inner) (set! v (+ v 10)) v)
+          (inner)
+          v)
+        (outer)", "11");
+}
+
+#[test]
+fn recursion() {
+    check("(define (fact n) (if (= n 0) 1 (* n (fact (- n 1))))) (fact 10)", "3628800");
+    check("
+        (define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))
+        (fib 15)", "610");
+    check("
+        (define (even? n) (if (= n 0) #t (odd? (- n 1))))
+        (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+        (list (even? 10) (odd? 7) (even? 3))", "(#t #t #f)");
+}
+
+#[test]
+fn lists_and_mutation() {
+    check("(define l (list 1 2 3)) (set-car! (cdr l) 'x) l", "(1 x 3)");
+    check("(define l (list 1 2)) (set-cdr! (cdr l) l) l", "#0=(1 2 . #0#)");
+    check("(length (cons 1 (cons 2 '())))", "2");
+    check("(list (null? '()) (pair? '()) (pair? (cons 1 2)))", "(#t #f #t)");
+    check_err("(car 1)", "`car` expected a pair; was given 1");
+    // Printing follows neither the car nor the cdr by recursion
+    check("
+        (define (nest n x) (if (= n 0) x (nest (- n 1) (list x))))
+        (define deep (nest 100000 'x))
+        (define out (open-output-string))
+        (write (list deep (vector deep)) out)
+        (string-length (get-output-string out))", "400008");
+}
+
+#[test]
+fn equal_on_circular_and_deep_lists() {
+    let rings = "
+        (define (ring . items) (define l (apply list items)) (set-cdr! (last-pair l) l) l)
+        (define (last-pair l) (if (pair? (cdr l)) (last-pair (cdr l)) l))";
+    check(&format!("{} (equal? (ring 1 2) (ring 1 2))", rings), "#t");
+    check(&format!("{} (equal? (ring 1 2) (ring 1 3))", rings), "#f");
+    check(&format!("{} (equal? (ring 1 2) (list 1 2 1 2))", rings), "#f");
+    check(&format!("{} (define r (ring 1 2)) (equal? r (cdr (cdr r)))", rings), "#t");
+    // Pointing back through the car rather than the cdr
+    check("
+        (define a (list 1 2)) (set-car! a a)
+        (define b (list 1 2)) (set-car! b b)
+        (define c (list 1 3)) (set-car! c c)
+        (list (equal? a b) (equal? a c))", "(#t #f)");
+    check("
+        (define (nest n x) (if (= n 0) x (nest (- n 1) (list x))))
+        (list (equal? (nest 30000 1) (nest 30000 1)) (equal? (nest 30000 1) (nest 30000 2)))", "(#t #f)");
+}
+
+#[test]
+fn vectors() {
+    check("(list #(1 \"a\" (b)) '#() (vector 1 2) (make-vector 2 'x) (list->vector '(3 4)))",
+          "(#(1 \"a\" (b)) #() #(1 2) #(x x) #(3 4))");
+    check("(define v (make-vector 3 0)) (vector-set! v 1 'y) (list v (vector-ref v 1) (vector-length v))",
+          "(#(0 y 0) y 3)");
+    check("(define v (vector 1 2 3 4)) (vector-fill! v 0 2)
+           (list v (vector->list v) (vector->list v 1 3) (vector-copy v 3))", "(#(1 2 0 0) (1 2 0 0) (2 0) #(0))");
+    check("(define v (vector 1 2)) (list (vector? v) (vector? '(1 2)) (eq? v (vector-copy v)) (equal? v (vector-copy v)))",
+          "(#t #f #f #t)");
+    check("(define v (vector 1 2)) (vector-set! v 1 v) v", "#0=#(1 #0#)");
+    // Vectors that contain themselves, directly or through a list
+    check("
+        (define (selfish x) (define v (vector x #f)) (vector-set! v 1 v) v)
+        (define (listed x) (define v (vector x #f)) (vector-set! v 1 (list v)) v)
+        (list (equal? (selfish 1) (selfish 1)) (equal? (selfish 1) (selfish 2))
+              (equal? (listed 1) (listed 1)) (equal? (listed 1) (selfish 1))
+              (equal? #(1 2) #(1 2 3)))", "(#t #f #t #f #f)");
+    check_err("(vector-ref (vector 1 2) 2)", "`vector-ref` was given index 2 of a vector of length 2");
+    check_err("(vector->list #(1 2) 2 1)", "`vector->list` was given the range 2 to 1 of a vector of length 2");
+    check_err("(vector-length '(1))", "`vector-length` expected a vector; was given (1)");
+}
+
+#[test]
+fn natives_and_higher_order_procedures() {
+    check("(double 21)", "42");
+    check("(define (twice f x) (f (f x))) (twice double 5)", "20");
+    check("(define (compose f g) (lambda (x) (f (g x)))) ((compose double -) 4)", "-8");
+    check("(string-append \"a\" \"b\")", "\"ab\"");
+    check_err("(+ 1 'a)", "`+` expected a number as argument 2; was given a");
+}
+
+#[test]
+fn procedures_cross_engines() {
+    let mut interp = Interpreter::with_engine(Engine::TreeWalker);
+    interp.eval_str("(define (tree-add a b) (+ a b))").unwrap();
+    interp.set_engine(Engine::Bytecode);
+    interp.eval_str("(define (vm-twice f x) (f (f x 1) 1))").unwrap();
+    assert_eq!(format!("{:?}", interp.eval_str("(vm-twice tree-add 1)").unwrap()), "3");
+    interp.set_engine(Engine::TreeWalker);
+    assert_eq!(format!("{:?}", interp.eval_str("(vm-twice tree-add 5)").unwrap()), "7");
+}
+
+#[test]
+fn bytecode_runs_deep_tail_calls() {
+    let mut interp = Interpreter::with_engine(Engine::Bytecode);
+    let v = interp.eval_str("
+        (define (loop n acc) (if (= n 0) acc (loop (- n 1) (+ acc 1))))
+        (loop 100000 0)").unwrap();
+    assert_eq!(format!("{:?}", v), "100000");
+}
+
+#[test]
+fn bytecode_reports_syntax_errors_before_running() {
+    let mut interp = Interpreter::with_engine(Engine::Bytecode);
+    let err = interp.eval_str("(define (f) (if #t)) (exit 3)").unwrap_err();
+    assert_eq!(err, EvalError::from("`if` expected 2 or 3 args"));
+    let err = interp.eval_str("(begin (define x 1) (lambda (1) x))").unwrap_err();
+    assert_eq!(err, EvalError::from("Expected symbol in argument list"));
+    assert_eq!(interp.lookup_global("x"), None);
+}
+
+#[test]
+fn constant_tests_and_discarded_values() {
+    check("(if '() 'a 'b)", "a");
+    check("(begin 1 (begin 2 'x (lambda () 3)) (if #f 4 5))", "5");
+    check("(define n 0) (begin 1 (set! n 7) 2) n", "7");
+}
+
+#[test]
+fn calls_of_primitives_on_constants() {
+    check("(define (f) (+ 1 (* 2 3) (string-length \"ab\"))) (f)", "9");
+    check("(define (f) (if (< 1 2) 'yes 'no)) (f)", "yes");
+    check("(define (f) (+ 1 (* 2 3))) (f) (set! * -) (f)", "0");
+    check("(define (f) (+ 1 2)) (define (+ a b) (* a b)) (f)", "2");
+    check("(define (f +) (+ 1 2)) (f -)", "-1");
+    check("(define (f) (car 1)) (guard (e (#t 'caught)) (f))", "caught");
+}
+
+#[test]
+fn raising_and_guarding() {
+    check("(guard (e (#t (list 'caught e))) (raise 'oops))", "(caught oops)");
+    check("(guard (e ((pair? e) 'pair) ((eq? e 'sym) (list e))) (+ 1 (raise 'sym)))", "(sym)");
+    let assq = "(define (assq k al) (if (null? al) #f (if (eq? (car (car al)) k) (car al) (assq k (cdr al)))))";
+    check(&format!("{} (guard (e ((assq 'a e) => cdr) ((assq 'b e))) (raise (list (cons 'b 23))))", assq),
+          "(b . 23)");
+    check(&format!("{} (guard (e ((assq 'a e) => cdr) ((assq 'b e))) (raise (list (cons 'a 42))))", assq),
+          "42");
+    check("(guard (e (else (error-object-message e))) (car 5))",
+          "\"`car` expected a pair; was given 5\"");
+    check("(guard (e ((error-object? e) (list (error-object-message e) (error-object-irritants e))))
+             (error \"bad thing:\" 1 'two))",
+          "(\"bad thing:\" (1 two))");
+    check("(guard (e (#f 'no)) 'fine)", "fine");
+    // Without a clause that applies, the condition goes on outward
+    check("(guard (outer (#t (list 'outer outer))) (guard (inner ((pair? inner) 'inner)) (raise 'x)))",
+          "(outer x)");
+    check_err("(guard (e ((pair? e) 'p)) (raise 'x))", "Uncaught exception: x");
+    check_err("(error \"Something failed:\" 42 \"text\")", "Something failed: 42 \"text\"");
+}
+
+#[test]
+fn exception_handlers() {
+    check("(with-exception-handler (lambda (e) (* e 10)) (lambda () (+ 1 (raise-continuable 4))))", "41");
+    check("(define seen '())
+           (guard (e (#t (list e seen)))
+             (with-exception-handler (lambda (e) (set! seen (cons e seen))) (lambda () (raise 'boom))))",
+          "(boom (boom))");
+    // A guard inside a handler's extent catches first
+    check("(with-exception-handler (lambda (e) 'handler)
+             (lambda () (guard (e (#t (list 'guard e))) (raise-continuable 'c))))",
+          "(guard c)");
+}
+
+#[test]
+fn eval_and_environments() {
+    check("(eval '(+ 1 2) (scheme-report-environment 5))", "3");
+    check("(eval (list '* 2 3))", "6");
+    check("(eval (cons 'list (cons 1 (cons \"two\" '()))) (environment '(scheme base)))", "(1 \"two\")");
+    check("(define z 4) (eval '(begin (define w (+ z 1)) w) (interaction-environment))", "5");
+    check("(eval '(begin (define w 5) (set! w (+ w 1))) (interaction-environment)) w", "6");
+    // Definitions go no further than the environment they are made in
+    check("(define env (scheme-report-environment 5))
+           (eval '(define (sq x) (* x x)) env)
+           (list (eval '(sq 7) env) (guard (e (#t 'unbound)) sq))",
+          "(49 unbound)");
+    check("(define y 1) (guard (e (#t 'hidden)) (eval 'y (scheme-report-environment 5)))", "hidden");
+    check("(eval '(if #t 'yes 'no) (null-environment 5))", "yes");
+    check_err("(eval '(car '(1)) (null-environment 5))", "Symbol is not bound: car");
+    check_err("(eval 'car (environment))", "Symbol is not bound: car");
+    check_err("(environment '(scheme nonsense))", "Found no library (scheme nonsense)");
+    // Each import set gives only what it names
+    check("(eval 'write (environment '(scheme write)))", "#<procedure write>");
+    check_err("(eval '(car '(1)) (environment '(scheme write)))", "Symbol is not bound: car");
+    check("(eval '(c:char-upcase #\\a) (environment '(prefix (scheme char) c:)))", "#\\A");
+    check_err("(eval (list car ''(1)))", "Cannot evaluate #<procedure car>, which has no written form");
+    check_err("(define l (list 1 2)) (set-cdr! (cdr l) l) (eval l)", "Cannot evaluate circular data");
+}
+
+#[test]
+fn records() {
+    let point = "(define-record-type <point> (make-point x y) point? (x point-x set-point-x!) (y point-y))";
+    check(&format!("{} (make-point 1 \"two\")", point), "#<point x: 1 y: \"two\">");
+    check(&format!("{} (define p (make-point 1 2)) (list (point? p) (point? '(1 2)) (point-x p) (point-y p))", point),
+          "(#t #f 1 2)");
+    check(&format!("{} (define p (make-point 1 2)) (set-point-x! p 5) (list (point-x p) p)", point),
+          "(5 #<point x: 5 y: 2>)");
+    check(&format!("{} <point>", point), "#<record-type point>");
+    // A field the constructor leaves out is unspecified until set
+    check("(define-record-type box (make-box) box? (v unbox set-box!)) (define b (make-box))
+           (list b (begin (set-box! b b) b))", "(#0=#<box v: #0#> #0#)");
+    check("(define (f) (define-record-type cell (make-cell v) cell? (v cell-v)) (cell-v (make-cell 'inner))) (f)",
+          "inner");
+    // Each definition makes a new type
+    check("(define-record-type a (make-a) a?) (define x (make-a))
+           (define-record-type a (make-a) a?) (list (a? x) (a? (make-a)))", "(#f #t)");
+    check_err(&format!("{} (point-x (cons 1 2))", point),
+              "`point-x` expected a record of type point; was given (1 . 2)");
+    check_err(&format!("{} (define-record-type other (make-other) other?) (point-y (make-other))", point),
+              "`point-y` expected a record of type point; was given #<other>");
+    check_err(&format!("{} (make-point 1)", point), "`make-point` expected 2 args; was given 1");
+    check(&format!("{} (guard (e ((error-object? e) 'wrong-type)) (set-point-x! 'p 1))", point), "wrong-type");
+    check_err("(define-record-type p (make-p z) p? (x p-x))", "`make-p` names z, which is not a field of p");
+    check_err("(define-record-type p (make-p) p? (x p-x) (x p-x2))", "Record type p has two fields named x");
+    check_err("(define-record-type p (make-p) p? (x))",
+              "Expected (define-record-type name (constructor field...) predicate (field accessor [modifier])...)");
+}
+
+#[test]
+fn multiple_values() {
+    check("(call-with-values (lambda () (values 1 2 3)) list)", "(1 2 3)");
+    check("(call-with-values (lambda () (values)) list)", "()");
+    check("(call-with-values (lambda () 5) list)", "(5)");
+    check("(+ 1 (values 2))", "3");
+    check("(receive (a . rest) (values 1 2 3) (list a rest))", "(1 (2 3))");
+    check("(receive all (values 1 2) all)", "(1 2)");
+    // The expressions of let-values don't see its variables; those of
+    // let*-values see the ones before them
+    check("(define x 10) (let-values (((a b) (values 1 2)) ((x) (values x))) (list a b x))", "(1 2 10)");
+    check("(define a 10) (let-values (((a) (values 1)) ((b) (values a))) (list a b))", "(1 10)");
+    check("(let*-values (((a b) (values 1 2)) ((c . d) (values (+ a b) a))) (list a b c d))", "(1 2 3 (1))");
+    check("(let-values () (define z 1) z)", "1");
+    check("(define x 10) (define-values (x y . z) (values (+ x 1) 2 3 4)) (list x y z)", "(11 2 (3 4))");
+    check("(define (f) (define-values (p q) (values 1 2)) (+ p q)) (f)", "3");
+    check("(define-values all (values 1 2)) all", "(1 2)");
+    check("(values 1 \"two\")", "1 \"two\"");
+    check_err("(receive (a b) (values 1 2 3) a)", "Expected 2 args; was given 3");
+    check_err("(define-values (a b) (values 1))", "Expected 2 args; was given 1");
+    check_err("(let-values ((a)) a)", "Expected ((formals expression)...) body... after `let-values`");
+    check_err("(define-values (a 1) (values 1 2))", "Expected formals in `define-values`");
+}
+
+#[test]
+fn promises() {
+    check("(define n 0) (define p (delay (begin (set! n (+ n 1)) n))) (list (force p) (force p) n)", "(1 1 1)");
+    check("(define p (delay 1)) (list (promise? p) (promise? 1) p)", "(#t #f #<promise>)");
+    check("(list (force (make-promise 5)) (force 7))", "(5 7)");
+    check("(define p (delay 1)) (eq? p (make-promise p))", "#t");
+    // A long chain of delay-forces is forced without growing the stack
+    check("(define (loop n) (if (= n 0) (delay 'done) (delay-force (loop (- n 1))))) (force (loop 30000))",
+          "done");
+    check("(define p (delay-force (delay 3))) (list (force p) (force p))", "(3 3)");
+    // The first value computed wins when a promise forces itself (R7RS 4.2.5)
+    check("(define x 5) (define p (delay (begin (set! x (+ x 1)) (if (> x 10) x (force p))))) (force p)", "11");
+    check("(define p (delay (error \"no\"))) (guard (e (#t 'caught)) (force p))", "caught");
+    check_err("(define p (delay-force p)) (force p)", "`force` found a promise whose value is itself");
+    check_err("(delay 1 2)", "`delay` expected 1 arg; was given 2");
+}
+
+#[test]
+fn streams() {
+    let ints = "(define (ints n) (stream-cons n (ints (+ n 1))))";
+    check(&format!("{} (stream->list (stream-take 5 (ints 0)))", ints), "(0 1 2 3 4)");
+    check(&format!("{} (stream->list 3 (ints 10))", ints), "(10 11 12)");
+    check(&format!("{} (stream->list (stream-map + (ints 0) (list->stream '(10 20 30))))", ints), "(10 21 32)");
+    check(&format!("{} (stream->list 2 (stream-filter (lambda (x) (> x 50000)) (ints 0)))", ints), "(50001 50002)");
+    check(&format!("{} (define s (stream-cdr (ints 0))) (list (stream-car s) (stream-pair? s) (stream-null? s))", ints),
+          "(1 #t #f)");
+    // Elements are computed only when needed, and only once
+    check("(define n 0) (define s (stream-cons (begin (set! n (+ n 1)) 'a) (error \"never\")))
+           (list (stream-car s) (stream-car s) n)", "(a a 1)");
+    check("(list (stream-null? stream-null) (stream? stream-null) (stream? '()) (stream->list stream-null))",
+          "(#t #t #f ())");
+    check(&format!("{} (list (stream? (ints 0)) (stream? (stream-cdr (ints 0))) (stream? (list->stream '(1)))
+                         (stream? (stream-map - (ints 0))) (stream? (delay 1)) (stream? (make-promise 1)))", ints),
+          "(#t #t #t #t #f #f)");
+    check_err("(stream-car stream-null)", "`stream-car` expected a stream pair; was given the empty stream");
+    check_err("(stream-cdr (make-promise 5))", "`stream-cdr` expected a stream; was given #<promise>");
+}
+
+#[test]
+fn parameters() {
+    check("(define p (make-parameter 1)) (list (p) (parameterize ((p 2)) (p)) (p))", "(1 2 1)");
+    check("(define p (make-parameter 1)) (define q (make-parameter 'a))
+           (parameterize ((p 2) (q 'b)) (parameterize ((p 3)) (list (p) (q))))", "(3 b)");
+    // The converter applies to the initial value and to each new one
+    check("(define p (make-parameter 1 (lambda (x) (* x 10)))) (list (p) (parameterize ((p 2)) (p)))", "(10 20)");
+    // Values are seen by procedures called within, not only lexically
+    check("(define depth (make-parameter 0)) (define (show) (depth))
+           (parameterize ((depth 5)) (show))", "5");
+    check("(define p (make-parameter 'outer)) (guard (e (#t (list e (p)))) (parameterize ((p 'inner)) (raise (p))))",
+          "(inner outer)");
+    check("(define p (make-parameter 1)) p", "#<parameter>");
+    check_err("(define p (make-parameter 1)) (p 2)", "Expected 0 args; was given 1");
+    check_err("(parameterize ((car 1)) 1)", "`parameterize` expected a parameter; was given #<procedure car>");
+    check_err("(define p (make-parameter 1 (lambda (x) (if (eq? x 'bad) (error \"no\") x))))
+               (parameterize ((p 'bad)) 1)", "no");
+    check_err("(parameterize (p) 1)", "Expected ((parameter value)...) body... after `parameterize`");
+}
+
+#[test]
+fn dynamic_wind() {
+    let log = "(define log '()) (define (note x) (set! log (cons x log)))";
+    check(&format!("{} (list (dynamic-wind (lambda () (note 'before)) (lambda () (note 'during) 'result)
+                                           (lambda () (note 'after)))
+                             log)", log),
+          "(result (after during before))");
+    check(&format!("{} (guard (e (#t (list e log)))
+                         (dynamic-wind (lambda () (note 'before)) (lambda () (raise 'oops)) (lambda () (note 'after))))",
+                   log),
+          "(oops (after before))");
+    check(&format!("{} (guard (e (#t log))
+                         (dynamic-wind (lambda () (note 1))
+                                       (lambda () (dynamic-wind (lambda () (note 2)) (lambda () (car 1)) (lambda () (note 3))))
+                                       (lambda () (note 4))))", log),
+          "(4 3 2 1)");
+    // exit runs the after thunks on its way out
+    let mut interp = Interpreter::new();
+    let err = interp.eval_str("(define done #f) (dynamic-wind (lambda () 0) (lambda () (exit 3)) (lambda () (set! done #t)))")
+        .unwrap_err();
+    assert_eq!(err, EvalError::Exit(3));
+    assert_eq!(format!("{:?}", interp.eval_str("done").unwrap()), "#t");
+}
+
+#[test]
+fn do_loops() {
+    check("(do ((i 0 (+ i 1)) (acc '() (cons i acc))) ((= i 5) acc))", "(4 3 2 1 0)");
+    // A variable without a step keeps what the body sets it to
+    check("(do ((i 0 (+ i 1)) (sum 0)) ((= i 4) sum) (set! sum (+ sum i)))", "6");
+    check("(do ((i 0 (+ i 1))) ((= i 3)))", "()");
+    // The inits are evaluated outside the loop's scope
+    check("(define i 10) (do ((i 0 (+ i 1)) (j i)) ((= i 2) (list i j)))", "(2 10)");
+    // Compiled, a loop runs in constant stack
+    let mut interp = Interpreter::with_engine(Engine::Bytecode);
+    let v = interp.eval_str("(define (count-to n) (do ((i 0 (+ i 1))) ((= i n) i))) (count-to 100000)").unwrap();
+    assert_eq!(format!("{:?}", v), "100000");
+    check_err("(do ((i 0)) ())", "Expected ((variable init [step])...) (test expression...) command... after `do`");
+    check_err("(do ((1 0)) (#t))", "Expected ((variable init [step])...) (test expression...) command... after `do`");
+}
+
+#[test]
+fn higher_order_list_procedures() {
+    check("(list (map + '(1 2 3) '(10 20)) (map (lambda (x) (* x x)) '(1 2 3)))", "((11 22) (1 4 9))");
+    check("(define acc '()) (for-each (lambda (a b) (set! acc (cons (list a b) acc))) '(1 2) '(x y z)) acc",
+          "((2 y) (1 x))");
+    check("(list (apply + 1 2 '(3 4)) (apply list '()))", "(10 ())");
+    check("(list (filter (lambda (x) (> x 2)) '(1 2 3 4)) (remove (lambda (x) (> x 2)) '(1 2 3 4)))",
+          "((3 4) (1 2))");
+    check("(call-with-values (lambda () (partition (lambda (x) (> x 2)) '(1 2 3 4))) list)", "((3 4) (1 2))");
+    check("(list (reduce + 0 '(1 2 3)) (reduce + 0 '()) (reduce list 0 '(1 2 3)))", "(6 0 (3 (2 1)))");
+    check("(list (fold cons '() '(1 2 3)) (fold-left list '() '(1 2 3)) (fold-right cons '() '(1 2 3)))",
+          "((3 2 1) (((() 1) 2) 3) (1 2 3))");
+    check("(list (fold + 0 '(1 2) '(10 20)) (fold-right list 'end '(1 2) '(a b)))", "(33 (1 a (2 b end)))");
+    check("(append-map (lambda (x y) (list x y)) '(1 2) '(a b))", "(1 a 2 b)");
+    check("(list (list-index (lambda (x) (> x 2)) '(1 2 3 4)) (list-index (lambda (x) #f) '(1)))", "(2 #f)");
+    check("(list (any (lambda (x) (if (> x 2) (* x 10) #f)) '(1 2 3 4)) (any (lambda (x) x) '()))", "(30 #f)");
+    check("(list (every (lambda (x) x) '(1 2 3)) (every (lambda (x) (> x 1)) '(1 2)) (every car '()))", "(3 #f #t)");
+    check("(list (iota 5) (iota 3 1) (iota 3 1 2))", "((0 1 2 3 4) (1 2 3) (1 3 5))");
+    check("(list (delete 2 '(1 2 3 2)) (delete \"b\" '(\"a\" \"b\")) (delete 2 '(1 2 3 4) <))",
+          "((1 3) (\"a\") (1 2))");
+    check("(define l (list 1 2)) (define c (list-copy l)) (set-car! c 'x) (list l c (list-copy '(1 . 2)) (list-copy 5))",
+          "((1 2) (x 2) (1 . 2) 5)");
+    check("(list (last '(1 2 3)) (assoc \"b\" '((\"a\" . 1) (\"b\" . 2))) (assoc 'z '((a . 1))))",
+          "(3 (\"b\" . 2) #f)");
+    check("(assoc 2.5 '((1 . a) (2 . b) (3 . c)) <)", "(3 . c)");
+    check("(length (map (lambda (x) x) (iota 5000)))", "5000");
+    check_err("(map car 5)", "`map` expected a list as argument 2; was given 5");
+    check_err("(apply + 1 2)", "`apply` expected a list as its last argument; was given 2");
+    check_err("(last '())", "`last` expected a non-empty list; was given ()");
+    check_err("(append-map (lambda (x) x) '(1))", "`append-map` expected its procedure to give lists; was given 1");
+    check_err("(assoc 1 '(1 2))", "`assoc` expected an association list; was given (1 2)");
+}
+
+#[test]
+fn sorting() {
+    check("(list (sort < '(3 1 2)) (list-sort > '(3 1 2)) (sort < '()))", "((1 2 3) (3 2 1) ())");
+    // Elements that compare equal keep their order
+    check("(sort (lambda (a b) (< (car a) (car b))) '((2 a) (1 b) (2 c) (1 d) (0 e)))",
+          "((0 e) (1 b) (1 d) (2 a) (2 c))");
+    check("(define l (list 3 1 2)) (define s (sort! < l)) (list l (eq? l s))", "((1 2 3) #t)");
+    check("(list (merge < '(1 3 5) '(2 3 4 6)) (merge < '() '(1)))", "((1 2 3 3 4 5 6) (1))");
+    check("(merge (lambda (a b) (< (car a) (car b))) '((1 a) (2 a)) '((1 b) (2 b)))",
+          "((1 a) (1 b) (2 a) (2 b))");
+    check("(list (sorted? < '(1 2 2 3)) (sorted? < '(2 1)) (sorted? < '()))", "(#t #f #t)");
+    check("(sort (lambda (a b) (< (string-length a) (string-length b))) '(\"pear\" \"apple\" \"fig\"))",
+          "(\"fig\" \"pear\" \"apple\")");
+    check("(define l (sort > (iota 5000))) (list (car l) (length l) (sorted? > l))", "(4999 5000 #t)");
+    check("(define v (vector 5 3 1 4 2)) (list (vector-sort < v) (vector-sort < v 1 4) v)",
+          "(#(1 2 3 4 5) #(1 3 4) #(5 3 1 4 2))");
+    check("(define v (vector '(2 a) '(1 b) '(2 c) '(1 d)))
+           (vector-sort! v (lambda (a b) (< (car a) (car b)))) v", "#((1 b) (1 d) (2 a) (2 c))");
+    check("(define v (vector 9 3 2 1 0)) (vector-sort! v < 1 4) v", "#(9 1 2 3 0)");
+    check("(define v #(1 3 5 7 9)) (list (vector-binary-search v 7 -) (vector-binary-search v 4 -)
+                                        (vector-binary-search v 1 -) (vector-binary-search v 1 - 1 5))",
+          "(3 #f 0 #f)");
+    check_err("(sort < 5)", "`sort` expected a list as argument 2; was given 5");
+    check_err("(vector-sort < '(1))", "`vector-sort` expected a vector; was given (1)");
+    check_err("(sort < '(1 a))", "`<` expected a number as argument 1; was given a");
+}
+
+#[test]
+fn pattern_matching() {
+    check("(match '(1 (2 3) 4) ((a (b . c) . d) (list a b c d)))", "(1 2 (3) (4))");
+    check("(match '(1 2) ((a) 'one) ((a b) 'two) (_ 'more))", "two");
+    check("(list (match 1 (1 'one) (_ 'other)) (match \"a\" (\"a\" 'a)) (match '() (() 'empty)))", "(one a empty)");
+    // Literals compare with equal?, not eq?
+    check("(list (match (list 1 \"a\") ('(1 \"a\") 'same) (_ 'different))
+                 (match (string-append \"a\" \"b\") (\"ab\" 'same) (_ 'different))
+                 (match (list 1 2) ('(1 3) 'same) (_ 'different)))", "(same same different)");
+    check("(match '(set x 1) (('set var val) (list var val)))", "(x 1)");
+    check("(match '(1 2 3 4 5) ((a b ... c) (list a b c)))", "(1 (2 3 4) 5)");
+    check("(match '((a 1) (b 2)) (((k v) ...) (list k v)))", "((a b) (1 2))");
+    check("(match '((1 2 3) (4)) (((a b ...) ...) (list a b)))", "((1 4) ((2 3) ()))");
+    check("(match '(1 2 . 3) ((a ...) 'proper) ((a . b) 'dotted))", "dotted");
+    check("(match 5 ((? char? c) 'char) ((? (lambda (n) (> n 3)) n) (* n 2)))", "10");
+    check("(match 3 ((or 1 2) 'small) ((and n (not 0)) (list 'other n)))", "(other 3)");
+    check("(match '(2 7) ((or (a 1) (2 a)) a))", "7");
+    check("(match '(add 1 2) ((quasiquote (add (unquote a) (unquote b))) (+ a b)))", "3");
+    check("(match '(mul (x) 4) (`(add ,a ,b) 'add) (`(mul (,v) ,n) (list v n)))", "(x 4)");
+    check("(define-record-type point (make-point x y) point? (x point-x) (y point-y))
+           (match (make-point 1 2) (($ point 0 y) 'on-axis) (($ point x y) (+ x y)))", "3");
+    // Pattern variables don't hide the procedures the match uses
+    check("(match '(1 2) ((car . cdr) (list car cdr)))", "(1 (2))");
+    // nor are the temporaries of variables named like others with a count
+    check("(match '(1 2 3 4 5 6 7) ((car1 b c d e f g) (list car1 b c d e f g)))", "(1 2 3 4 5 6 7)");
+    // Compiled, matching a repeated pattern runs in constant stack
+    let mut interp = Interpreter::with_engine(Engine::Bytecode);
+    let v = interp.eval_str("(match (iota 5000) ((x ... y) (list (length x) y)))").unwrap();
+    assert_eq!(format!("{:?}", v), "(4999 4999)");
+    check_err("(match '(1 2) ((a) a))", "`match` found no clause matching (1 2)");
+    check_err("(match 1 ((... a) a))", "Invalid pattern in `match`: (... a)");
+    check_err("(match)", "Expected an expression and (pattern body...) clauses after `match`");
+}
@@ -0,0 +1,73 @@
+extern crate scheme;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use scheme::{EvalError, Foreign, Interpreter, SValue};
+
+struct Counter {
+    count: RefCell<u32>,
+}
+
+struct Other;
+
+fn with_counter() -> Interpreter {
+    let mut interp = Interpreter::new();
+    interp.define_fn("make-counter", || Foreign::new("counter", Counter { count: RefCell::new(0) }));
+    interp.define_fn("counter-bump!", |c: Rc<Counter>| {
+        *c.count.borrow_mut() += 1;
+        *c.count.borrow()
+    });
+    interp.define_foreign_predicate::<Counter>("counter?");
+    interp.define_global("other", SValue::foreign("other", Other));
+    interp
+}
+
+#[test]
+fn downcasts_only_to_the_wrapped_type() {
+    let v = SValue::foreign("counter", Counter { count: RefCell::new(3) });
+    assert_eq!(*v.downcast::<Counter>().unwrap().count.borrow(), 3);
+    assert!(v.downcast::<Other>().is_none());
+    assert!(v.downcast::<u32>().is_none());
+    assert!(v.is_foreign::<Counter>());
+    assert!(!v.is_foreign::<Other>());
+
+    // Values that aren't foreign at all have nothing to give back
+    assert!(SValue::Number(3.0).downcast::<f64>().is_none());
+    assert!(!SValue::Number(3.0).is_foreign::<f64>());
+}
+
+#[test]
+fn natives_take_foreign_values_as_rc() {
+    let mut interp = with_counter();
+    let result = interp.eval_str("(define c (make-counter)) (counter-bump! c) (counter-bump! c)").unwrap();
+    assert_eq!(result, SValue::Number(2.0));
+    assert_eq!(format!("{:?}", interp.lookup_global("c").unwrap()), "#<counter>");
+
+    // The host sees the same value that Scheme code changed
+    let c = interp.lookup_global("c").unwrap().downcast::<Counter>().unwrap();
+    assert_eq!(*c.count.borrow(), 2);
+}
+
+#[test]
+fn natives_reject_foreign_values_of_other_types() {
+    let mut interp = with_counter();
+    for arg in &["other", "5"] {
+        match interp.eval_str(&format!("(counter-bump! {})", arg)) {
+            Err(EvalError::Message(m)) => {
+                assert!(m.starts_with("`counter-bump!` expected a foreign "), "{}", m);
+                assert!(m.contains("Counter as argument 1"), "{}", m);
+            },
+            r => panic!("expected an error, got {:?}", r),
+        }
+    }
+}
+
+#[test]
+fn foreign_predicates_recognize_their_type() {
+    let mut interp = with_counter();
+    assert_eq!(interp.eval_str("(counter? (make-counter))").unwrap(), SValue::Bool(true));
+    assert_eq!(interp.eval_str("(counter? other)").unwrap(), SValue::Bool(false));
+    assert_eq!(interp.eval_str("(counter? 'counter)").unwrap(), SValue::Bool(false));
+    assert!(interp.eval_str("(counter?)").is_err());
+}
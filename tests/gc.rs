@@ -0,0 +1,143 @@
+extern crate scheme;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use scheme::{Foreign, Interpreter, SValue};
+
+// Each test runs on its own thread, and so has a heap of its own
+
+fn reclaimed_by(interp: &mut Interpreter, src: &str) -> usize {
+    interp.eval_str(src).unwrap();
+    interp.gc()
+}
+
+#[test]
+fn reclaims_closures_stored_in_their_own_scope() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define (make) (define (loop) 1) loop)").unwrap();
+    let reclaimed = reclaimed_by(&mut interp, "(make) (make) (make)");
+    // Each call leaves a frame and the closure stored in it
+    assert_eq!(reclaimed, 6);
+    assert_eq!(interp.gc(), 0);
+}
+
+#[test]
+fn reclaims_circular_lists() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define (ring) (define c (list 1 2 3)) (set-cdr! (cdr (cdr c)) c) 0)").unwrap();
+    let before = interp.heap_stats();
+    let reclaimed = reclaimed_by(&mut interp, "(ring)");
+    // The frame of the call goes by refcounting; only the pairs are left
+    assert_eq!(reclaimed, 3);
+    let after = interp.heap_stats();
+    assert_eq!(after.live, before.live);
+    assert_eq!(after.collections, before.collections + 1);
+    assert_eq!(after.reclaimed, before.reclaimed + 3);
+}
+
+#[test]
+fn reclaims_records_that_refer_to_each_other() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("
+        (define-record-type node (make-node next) node? (next node-next set-node-next!))
+        (define (pair-up) (define a (make-node '())) (set-node-next! a (make-node a)) 0)
+        (define kept (make-node '()))
+        (set-node-next! kept kept)").unwrap();
+    interp.gc();
+    assert_eq!(reclaimed_by(&mut interp, "(pair-up)"), 2);
+    assert_eq!(interp.eval_str("(eq? kept (node-next kept))").unwrap(), SValue::Bool(true));
+}
+
+#[test]
+fn reclaims_vectors_that_contain_themselves() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define (knot) (define v (make-vector 2 0)) (vector-set! v 0 v) 0)").unwrap();
+    interp.gc();
+    // The vector; the frame goes by refcounting
+    assert_eq!(reclaimed_by(&mut interp, "(knot)"), 1);
+}
+
+#[test]
+fn reclaims_promises_that_refer_to_themselves() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define (knot) (define p (delay p)) 0)").unwrap();
+    interp.gc();
+    // The frame, the thunk and the promise
+    assert_eq!(reclaimed_by(&mut interp, "(knot)"), 3);
+    // Once forced, only the promise is left, holding itself as its value
+    interp.eval_str("(define (knot) (define p (delay p)) (force p) 0)").unwrap();
+    interp.gc();
+    assert_eq!(reclaimed_by(&mut interp, "(knot)"), 1);
+}
+
+#[test]
+fn keeps_reachable_cycles_intact() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("
+        (define c (list 1 2 3))
+        (set-cdr! (cdr (cdr c)) c)
+        (define (make-counter) (define n 0) (define (next) (set! n (+ n 1)) n) next)
+        (define counter (make-counter))
+        (counter)").unwrap();
+    assert_eq!(interp.gc(), 0);
+    assert_eq!(interp.eval_str("(car (cdr (cdr (cdr c))))").unwrap(), SValue::Number(1.0));
+    assert_eq!(interp.eval_str("(counter)").unwrap(), SValue::Number(2.0));
+}
+
+#[test]
+fn values_held_by_the_host_are_roots() {
+    let mut interp = Interpreter::new();
+    let ring = interp.eval_str("(define c (list 1 2)) (set-cdr! (cdr c) c) c").unwrap();
+    interp.eval_str("(set! c 0)").unwrap();
+    assert_eq!(interp.gc(), 0);
+    assert_eq!(format!("{:?}", ring), "#0=(1 2 . #0#)");
+    drop(ring);
+    assert_eq!(interp.gc(), 2);
+}
+
+#[test]
+fn runs_finalizers_of_foreign_values_in_garbage() {
+    let finalized = Rc::new(Cell::new(false));
+    let flag = finalized.clone();
+    let mut interp = Interpreter::new();
+    let handle = Foreign::new("handle", ()).with_finalizer(move |_: &()| flag.set(true));
+    interp.define_global("handle", SValue::Foreign(Rc::new(handle)));
+    interp.eval_str("(define c (list handle)) (set-cdr! c c) (set! handle 0) (set! c 0)").unwrap();
+    assert!(!finalized.get());
+    interp.gc();
+    assert!(finalized.get());
+}
+
+#[test]
+fn collects_automatically_as_garbage_accumulates() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("
+        (define (make) (define (loop) 1) loop)
+        (define (churn n) (if (= n 0) 0 (begin (make) (churn (- n 1)))))").unwrap();
+    for _ in 0..100 {
+        interp.eval_str("(churn 100)").unwrap();
+    }
+    let stats = interp.heap_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.live < 4096 + 200);
+}
+
+#[test]
+fn drops_long_lists_without_recursing() {
+    let list = SValue::list((0..1_000_000).map(|i| SValue::Number(i as f64)).collect());
+    drop(list);
+    // Nested through the car as well as the cdr
+    let mut nested = SValue::Nil;
+    for _ in 0..100_000 {
+        nested = SValue::cons(nested, SValue::Nil);
+    }
+    drop(nested);
+    let mut interp = Interpreter::new();
+    let v = interp.eval_str("
+        (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))
+        (define x (build 100000 '()))
+        (set! x 0)
+        x");
+    assert_eq!(v, Ok(SValue::Number(0.0)));
+}
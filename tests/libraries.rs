@@ -0,0 +1,186 @@
+extern crate scheme;
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use scheme::{Capabilities, FileAccess, Interpreter};
+
+use common::{error, eval, scratch_dir, ENGINES};
+
+const COUNTER: &str = r#"
+    (define-library (util counter)
+      (export make-counter (rename count current-count))
+      (import (scheme base))
+      (begin
+        (define count 0)
+        (define (make-counter)
+          (set! count (+ count 1))
+          (lambda () count))))
+"#;
+
+#[test]
+fn libraries_export_what_they_name() {
+    for &engine in &ENGINES {
+        let mut interp = Interpreter::with_engine(engine);
+        interp.eval_str(COUNTER).unwrap();
+        // Nothing is bound until it is imported
+        assert!(interp.lookup_global("make-counter").is_none());
+        interp.eval_str("(import (util counter))").unwrap();
+        assert_eq!(eval(&mut interp, "((make-counter))"), "1");
+        assert_eq!(eval(&mut interp, "current-count"), "0");
+        assert!(interp.lookup_global("count").is_none());
+        // The library's own definitions are what its procedures see
+        interp.eval_str("(define count 100)").unwrap();
+        assert_eq!(eval(&mut interp, "((make-counter))"), "2");
+    }
+}
+
+#[test]
+fn import_sets_choose_and_rename() {
+    let mut interp = Interpreter::new();
+    interp.eval_str(COUNTER).unwrap();
+    interp.eval_str("(define-library (shapes) (export square cube) (import (scheme base))
+                       (begin (define (square x) (* x x)) (define (cube x) (* x x x))))").unwrap();
+    let bound = |interp: &mut Interpreter, names: &[&str]| -> Vec<bool> {
+        names.iter().map(|name| interp.lookup_global(name).is_some()).collect()
+    };
+
+    interp.eval_str("(import (only (shapes) square))").unwrap();
+    assert_eq!(bound(&mut interp, &["square", "cube"]), [true, false]);
+    interp.eval_str("(import (prefix (except (shapes) square) s:))").unwrap();
+    assert_eq!(bound(&mut interp, &["s:cube", "s:square", "cube"]), [true, false, false]);
+    interp.eval_str("(import (rename (prefix (util counter) c-) (c-make-counter new-counter)))").unwrap();
+    assert_eq!(bound(&mut interp, &["new-counter", "c-make-counter", "c-current-count"]), [true, false, true]);
+    assert_eq!(eval(&mut interp, "(list (square 3) (s:cube 2))"), "(9 8)");
+
+    assert_eq!(error(&mut interp, "(import (only (shapes) circle))"), "circle is not among the names imported");
+    assert_eq!(error(&mut interp, "(import (rename (shapes) (cube square) (oval x)))"),
+               "oval is not among the names imported");
+    assert_eq!(error(&mut interp, "(import (nowhere))"), "Found no library (nowhere)");
+    assert_eq!(error(&mut interp, "(import 5)"), "Expected an import set; found Number(5.0)");
+}
+
+#[test]
+fn libraries_see_only_what_they_import() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define helper 1)").unwrap();
+    assert_eq!(error(&mut interp, "(define-library (bare) (export x) (begin (define x (+ 1 2))))"),
+               "Symbol is not bound: +");
+    assert_eq!(error(&mut interp, "(define-library (uses-global) (export x) (import (scheme base))
+                                     (begin (define x helper)))"),
+               "Symbol is not bound: helper");
+    assert_eq!(error(&mut interp, "(define-library (missing) (export ghost) (import (scheme base)))"),
+               "(missing) exports ghost, which it does not define");
+    assert_eq!(error(&mut interp, "(define-library (odd) (provide x))"),
+               "Expected a library declaration; found List([Symbol(\"provide\"), Symbol(\"x\")])");
+    // Core syntax needs no import
+    interp.eval_str("(define-library (syntax) (export f) (begin (define (f x) (if x 'yes 'no))))").unwrap();
+    assert_eq!(eval(&mut interp, "(import (syntax)) (f #f)"), "no");
+}
+
+#[test]
+fn standard_libraries_hold_the_primitives() {
+    let mut interp = Interpreter::new();
+    let env = |set: &str| format!("(environment '{})", set);
+    assert_eq!(eval(&mut interp, &format!("(eval '(char-upcase #\\a) {})", env("(scheme char)"))), "#\\A");
+    let e = error(&mut interp, &format!("(eval '(car '(1)) {})", env("(scheme char)")));
+    assert_eq!(e, "Symbol is not bound: car");
+    let e = error(&mut interp, &format!("(eval '(display 1) {})", env("(scheme base)")));
+    assert_eq!(e, "Symbol is not bound: display");
+    assert_eq!(eval(&mut interp, &format!("(eval '(car '(1 2)) {})", env("(scheme r5rs)"))), "1");
+    assert_eq!(eval(&mut interp, &format!("(eval '(gc) {})", env("(interpreter)"))), "0");
+    assert_eq!(eval(&mut interp, &format!("(eval '(stream-car (stream-cons 1 stream-null)) {})", env("(srfi 41)"))),
+               "1");
+    assert_eq!(eval(&mut interp, &format!("(eval '(force (delay 2)) {})", env("(scheme lazy)"))), "2");
+    let e = error(&mut interp, &format!("(eval '(filter car '()) {})", env("(scheme base)")));
+    assert_eq!(e, "Symbol is not bound: filter");
+    let set = "(environment '(scheme base) '(srfi 1))";
+    assert_eq!(eval(&mut interp, &format!("(eval '(map + (iota 3) (iota 3)) {})", set)), "(0 2 4)");
+    let set = "(environment '(scheme base) '(srfi 132))";
+    assert_eq!(eval(&mut interp, &format!("(eval '(sort < (list 2 1)) {})", set)), "(1 2)");
+    assert_eq!(eval(&mut interp, &format!("(eval '(vector-sort < (vector 2 1)) {})", set)), "#(1 2)");
+    // A standard library without procedures here yet can still be imported
+    interp.eval_str("(import (scheme time) (scheme lazy))").unwrap();
+
+    // Only what the capabilities allow is there
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    let e = error(&mut interp, &format!("(eval '(exit) {})", env("(scheme process-context)")));
+    assert_eq!(e, "Symbol is not bound: exit");
+}
+
+#[test]
+fn libraries_are_found_on_the_search_path() {
+    let dir = scratch_dir("search", &[
+        ("main.scm", "(import (geometry shapes))\n(define result (area 3))"),
+        ("geometry/shapes.sld", r#"
+            (define-library (geometry shapes)
+              (export area)
+              (import (scheme base) (geometry constants))
+              (include "shapes-body.scm"))
+        "#),
+        ("geometry/shapes-body.scm", "(define (area r) (* pi r r))"),
+        ("geometry/constants.sld", r#"
+            (define-library (geometry constants)
+              (export pi)
+              (include-library-declarations "constants-decls.scm"))
+        "#),
+        ("geometry/constants-decls.scm", "(import (scheme base))\n(begin (define pi 3))"),
+        ("vendor/extra/tools.sld", "(define-library (tools) (export tool) (begin (define tool 'hammer)))"),
+        ("wrong/name.sld", "(define-library (other) (export) (begin))"),
+        ("loop/a.sld", "(define-library (loop a) (import (loop b)))"),
+        ("loop/b.sld", "(define-library (loop b) (import (loop a)))"),
+    ]);
+    for &engine in &ENGINES {
+        let mut interp = Interpreter::with_engine(engine);
+        interp.eval_file(dir.join("main.scm")).unwrap();
+        assert_eq!(eval(&mut interp, "result"), "27");
+        assert!(interp.lookup_global("pi").is_none());
+    }
+
+    let caps = Capabilities { files: FileAccess::ReadOnly(dir.clone()), ..Capabilities::pure() };
+    let mut interp = Interpreter::with_capabilities(caps);
+    assert_eq!(eval(&mut interp, "(import (geometry shapes)) (area 1)"), "3");
+    assert_eq!(error(&mut interp, "(import (tools))"), "Found no library (tools)");
+    interp.set_library_path(vec![PathBuf::new(), PathBuf::from("vendor/extra")]);
+    assert_eq!(eval(&mut interp, "(import (tools)) tool"), "hammer");
+    assert_eq!(error(&mut interp, "(import (wrong name))"), "wrong/name.sld does not define the library (wrong name)");
+    let e = error(&mut interp, "(import (loop a))");
+    assert!(e.contains("loop/a.sld -> loop/b.sld -> loop/a.sld"), "{}", e);
+
+    // Without file access only the standard libraries are there
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    interp.set_library_path(vec![dir.clone()]);
+    assert_eq!(error(&mut interp, "(import (geometry shapes))"), "Found no library (geometry shapes)");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cond_expand_chooses_by_feature() {
+    for &engine in &ENGINES {
+        let mut interp = Interpreter::with_engine(engine);
+        assert_eq!(eval(&mut interp, "(cond-expand (no-such-feature 1) (r7rs 2) (else 3))"), "2");
+        assert_eq!(eval(&mut interp, "(cond-expand ((and r7rs (not full-unicode)) 1) (else 3))"), "3");
+        assert_eq!(eval(&mut interp, "(cond-expand ((or nope (library (scheme char))) 'has) (else 'lacks))"), "has");
+        assert_eq!(eval(&mut interp, "(cond-expand ((library (nowhere)) 'has) (else 'lacks))"), "lacks");
+        assert_eq!(eval(&mut interp, "(cond-expand (nope 1))"), "()");
+        assert_eq!(eval(&mut interp, "(define (f) (cond-expand (r7rs 'inner))) (f)"), "inner");
+        assert_eq!(eval(&mut interp, "(eq? (car (features)) 'r7rs)"), "#t");
+        // At the top level, the chosen forms may import
+        interp.eval_str("(cond-expand ((not r7rs) (import (nowhere))) (else (import (prefix (scheme base) b:))))")
+            .unwrap();
+        assert_eq!(eval(&mut interp, "(b:car '(1))"), "1");
+        interp.eval_str(r#"
+            (define-library (portable)
+              (export which)
+              (cond-expand
+                (r7rs (import (scheme base)) (begin (define which 'r7rs)))
+                (else (begin (define which 'other)))))
+            (import (portable))
+        "#).unwrap();
+        assert_eq!(eval(&mut interp, "which"), "r7rs");
+        assert_eq!(error(&mut interp, "(cond-expand ((maybe x) 1))"),
+                   "Expected a feature requirement; found List([Symbol(\"maybe\"), Symbol(\"x\")])");
+    }
+}
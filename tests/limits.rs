@@ -0,0 +1,254 @@
+extern crate scheme;
+
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use scheme::{Engine, EvalError, Interpreter, Limit, Limits, SValue};
+
+use common::ENGINES;
+
+fn limited(engine: Engine, limits: Limits) -> Interpreter {
+    let mut interp = Interpreter::with_engine(engine);
+    interp.set_limits(limits);
+    interp
+}
+
+#[test]
+fn fuel_bounds_runaway_loops() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { fuel: Some(10_000), ..Limits::default() });
+        interp.eval_str("(define (spin n) (if (= n 0) 0 (begin (spin (- n 1)) (spin (- n 1)))))")
+            .unwrap();
+        let err = interp.eval_str("(spin 40)").unwrap_err();
+        assert_eq!(err, EvalError::Limit(Limit::Fuel));
+        // The count starts over for each evaluation
+        assert_eq!(interp.eval_str("(+ 1 2)"), Ok(SValue::Number(3.0)));
+    }
+}
+
+#[test]
+fn depth_bounds_recursion() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { depth: Some(100), ..Limits::default() });
+        interp.eval_str("(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))").unwrap();
+        assert_eq!(interp.eval_str("(sum 50)"), Ok(SValue::Number(1275.0)));
+        assert_eq!(interp.eval_str("(sum 500)"), Err(EvalError::Limit(Limit::Depth)));
+        assert_eq!(interp.eval_str("(sum 50)"), Ok(SValue::Number(1275.0)));
+    }
+}
+
+#[test]
+fn deep_recursion_stops_short_of_overflowing_the_native_stack() {
+    let recursive = "
+        (define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))
+        (define (g n) (if (= n 0) 0 (+ 1 (car (map g (list (- n 1)))))))";
+    // Test threads have Rust's default stack of 2 MiB
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { depth: Some(1_000_000), ..Limits::default() });
+        interp.eval_str(recursive).unwrap();
+        // Only the tree-walker recurses on the native stack for each call
+        let expected = match engine {
+            Engine::Bytecode => Ok(SValue::Number(100_000.0)),
+            Engine::TreeWalker => Err(EvalError::Limit(Limit::Depth)),
+        };
+        assert_eq!(interp.eval_str("(f 100000)"), expected);
+        assert_eq!(interp.eval_str("(g 100000)"), Err(EvalError::Limit(Limit::Depth)));
+        assert_eq!(interp.eval_str("(g 50)"), Ok(SValue::Number(50.0)));
+    }
+
+    // With more stack, more of it may be used
+    let deeper = thread::Builder::new().stack_size(64 << 20).spawn(move || {
+        for &engine in &ENGINES {
+            let mut interp = limited(engine, Limits { stack: Some(48 << 20), ..Limits::default() });
+            interp.eval_str(recursive).unwrap();
+            assert_eq!(interp.eval_str("(list (f 2000) (g 2000))").unwrap(),
+                       SValue::list(vec![SValue::Number(2000.0), SValue::Number(2000.0)]));
+        }
+    });
+    deeper.unwrap().join().unwrap();
+}
+
+#[test]
+fn self_application_is_stopped() {
+    let limits = Limits { depth: Some(200), fuel: Some(1_000_000), ..Limits::default() };
+    for &engine in &ENGINES {
+        let err = limited(engine, limits).eval_str("((lambda (x) (x x)) (lambda (x) (x x)))");
+        assert!(matches!(err, Err(EvalError::Limit(_))), "{:?}", err);
+    }
+}
+
+#[test]
+fn tail_calls_are_not_limited_by_depth() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { depth: Some(10), ..Limits::default() });
+        let v = interp.eval_str("
+            (define (loop n) (if (= n 0) 'done (loop (- n 1))))
+            (loop 10000)");
+        assert_eq!(v, Ok(SValue::symbol("done")));
+    }
+}
+
+#[test]
+fn tail_calls_run_in_constant_stack_without_limits() {
+    for &engine in &ENGINES {
+        let mut interp = Interpreter::with_engine(engine);
+        let v = interp.eval_str("
+            (define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))
+            (count 100000 0)");
+        assert_eq!(v, Ok(SValue::Number(100000.0)));
+    }
+}
+
+#[test]
+fn heap_bounds_live_data_but_not_garbage() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { heap: Some(5000), ..Limits::default() });
+        interp.eval_str("
+            (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))
+            (define (churn n) (if (= n 0) 0 (begin (build 10 '()) (churn (- n 1)))))").unwrap();
+        assert_eq!(interp.eval_str("(define kept (build 100 '())) (length kept)"),
+                   Ok(SValue::Number(100.0)));
+        assert_eq!(interp.eval_str("(churn 50)"), Ok(SValue::Number(0.0)));
+        assert_eq!(interp.eval_str("(set! kept (build 300 '()))"), Ok(SValue::nil()));
+    }
+    let mut interp = limited(Engine::Bytecode, Limits { heap: Some(5000), ..Limits::default() });
+    let err = interp.eval_str("
+        (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))
+        (build 100000 '())");
+    assert_eq!(err, Err(EvalError::Limit(Limit::Heap)));
+}
+
+#[test]
+fn heap_counts_only_what_the_interpreter_allocated() {
+    let mut other = Interpreter::new();
+    other.eval_str("
+        (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))
+        (define kept (build 20000 '()))").unwrap();
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { heap: Some(5000), ..Limits::default() });
+        interp.eval_str("(define (f n) (if (= n 0) 'done (f (- n 1))))").unwrap();
+        assert_eq!(interp.eval_str("(f 5000)"), Ok(SValue::Symbol("done".into())));
+    }
+    assert_eq!(other.eval_str("(length kept)"), Ok(SValue::Number(20000.0)));
+}
+
+#[test]
+fn heap_counts_what_natives_make_by_its_size() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { heap: Some(10_000), ..Limits::default() });
+        for src in &["(define v (make-vector 10000000 0))", "(define l (iota 10000000))",
+                     "(define s \"0123456789\") (define (grow) (set! s (string-append s s)) (grow)) (grow)"] {
+            assert_eq!(interp.eval_str(src), Err(EvalError::Limit(Limit::Heap)), "{}", src);
+        }
+        // Vectors count their elements for as long as they are live
+        interp.eval_str("(define v (make-vector 6000 0))").unwrap();
+        assert_eq!(interp.eval_str("(define w (vector-copy v))"), Err(EvalError::Limit(Limit::Heap)));
+        interp.eval_str("(set! v #f)").unwrap();
+        assert_eq!(interp.eval_str("(vector-length (make-vector 6000 0))"),
+                   Ok(SValue::Number(6000.0)));
+    }
+}
+
+#[test]
+fn time_bounds_long_evaluations() {
+    for &engine in &ENGINES {
+        let limits = Limits { time: Some(Duration::from_millis(50)), ..Limits::default() };
+        let mut interp = limited(engine, limits);
+        interp.eval_str("(define (spin n) (if (= n 0) 0 (begin (spin (- n 1)) (spin (- n 1)))))")
+            .unwrap();
+        let start = Instant::now();
+        assert_eq!(interp.eval_str("(spin 40)"), Err(EvalError::Limit(Limit::Time)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
+
+#[test]
+fn primitives_over_long_lists_are_stopped() {
+    for &engine in &ENGINES {
+        let mut interp = Interpreter::with_engine(engine);
+        interp.eval_str("(define l (iota 100000)) (define m (list-copy l))").unwrap();
+        interp.set_limits(Limits { fuel: Some(10_000), ..Limits::default() });
+        for src in &["(length l)", "(equal? l m)", "(delete 'x l)", "(list-sort > l)",
+                     "(write l (open-output-string))", "(iota 100000)"] {
+            assert_eq!(interp.eval_str(src), Err(EvalError::Limit(Limit::Fuel)), "{}", src);
+        }
+
+        let limits = Limits { time: Some(Duration::from_millis(200)), ..Limits::default() };
+        let mut interp = limited(engine, limits);
+        let start = Instant::now();
+        assert_eq!(interp.eval_str("(length (iota 30000000))"), Err(EvalError::Limit(Limit::Time)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
+
+#[test]
+fn calls_back_from_natives_share_the_budget() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { fuel: Some(3000), ..Limits::default() });
+        interp.define_native("call-twice", scheme::Arity::exactly(1), |interp, args| {
+            interp.call(&args[0], vec![])?;
+            interp.call(&args[0], vec![])
+        });
+        interp.eval_str("(define (burn n) (if (= n 0) 0 (burn (- n 1))))").unwrap();
+        let err = interp.eval_str("(call-twice (lambda () (burn 50)))");
+        assert!(err.is_ok(), "{:?}", err);
+        let err = interp.eval_str("(call-twice (lambda () (burn 1000)))");
+        assert_eq!(err, Err(EvalError::Limit(Limit::Fuel)));
+    }
+}
+
+#[test]
+fn another_thread_can_interrupt_an_evaluation() {
+    for &engine in &ENGINES {
+        let mut interp = Interpreter::with_engine(engine);
+        interp.eval_str("(define (spin n) (if (= n 0) 0 (begin (spin (- n 1)) (spin (- n 1)))))")
+            .unwrap();
+        // An interrupt made before an evaluation starts stops it, and only it
+        interp.interrupt_handle().interrupt();
+        assert_eq!(interp.eval_str("(spin 2)"), Err(EvalError::Interrupted));
+        assert_eq!(interp.eval_str("(spin 2)"), Ok(SValue::Number(0.0)));
+        interp.interrupt_handle().interrupt();
+        interp.interrupt_handle().clear();
+        assert_eq!(interp.eval_str("(spin 2)"), Ok(SValue::Number(0.0)));
+
+        let handle = interp.interrupt_handle();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+        assert_eq!(interp.eval_str("(spin 60)"), Err(EvalError::Interrupted));
+        canceller.join().unwrap();
+        // The definitions made before are still there
+        assert_eq!(interp.eval_str("(spin 3)"), Ok(SValue::Number(0.0)));
+    }
+}
+
+#[test]
+fn guards_cannot_catch_limits() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { fuel: Some(10_000), ..Limits::default() });
+        interp.eval_str("(define (spin n) (if (= n 0) 0 (begin (spin (- n 1)) (spin (- n 1)))))")
+            .unwrap();
+        let err = interp.eval_str("(guard (e (#t 'caught)) (spin 40))").unwrap_err();
+        assert_eq!(err, EvalError::Limit(Limit::Fuel));
+    }
+}
+
+#[test]
+fn limits_skip_dynamic_wind_but_restore_parameters() {
+    for &engine in &ENGINES {
+        let mut interp = limited(engine, Limits { fuel: Some(10_000), ..Limits::default() });
+        interp.eval_str("
+            (define (spin n) (if (= n 0) 0 (begin (spin (- n 1)) (spin (- n 1)))))
+            (define p (make-parameter 'outer))
+            (define after-ran #f)").unwrap();
+        let err = interp.eval_str("(parameterize ((p 'inner))
+                                     (dynamic-wind (lambda () 0) (lambda () (spin 40)) (lambda () (set! after-ran #t))))")
+            .unwrap_err();
+        assert_eq!(err, EvalError::Limit(Limit::Fuel));
+        assert_eq!(interp.eval_str("(list (p) after-ran)").unwrap(),
+                   SValue::list(vec![SValue::symbol("outer"), SValue::Bool(false)]));
+    }
+}
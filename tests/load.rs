@@ -0,0 +1,157 @@
+extern crate scheme;
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use scheme::{Capabilities, Engine, EvalError, FileAccess, Interpreter};
+
+use common::{error, eval, scratch_dir, ENGINES};
+
+fn in_dir(dir: &Path, engine: Engine) -> Interpreter {
+    let caps = Capabilities { files: FileAccess::ReadOnly(dir.to_path_buf()), ..Capabilities::pure() };
+    let mut interp = Interpreter::with_capabilities(caps);
+    interp.set_engine(engine);
+    interp
+}
+
+#[test]
+fn loaded_files_find_others_relative_to_themselves() {
+    let dir = scratch_dir("relative", &[
+        ("main.scm", "(load \"lib/shapes.scm\")\n(define total (area 2 3))"),
+        ("lib/shapes.scm", "(load \"arith.scm\")\n(define (area w h) (times w h))"),
+        ("lib/arith.scm", "(define (times a b) (* a b))"),
+    ]);
+    for &engine in &ENGINES {
+        let mut interp = in_dir(&dir, engine);
+        interp.eval_str("(load \"main.scm\")").unwrap();
+        assert_eq!(eval(&mut interp, "total"), "6");
+        // Once loading is done, paths are from the root again
+        assert!(interp.eval_str("(load \"arith.scm\")").is_err());
+        assert_eq!(eval(&mut interp, "(begin (load \"lib/arith.scm\") (times 3 3))"), "9");
+
+        // A host's file is the base for the paths in it as well
+        let mut interp = Interpreter::with_engine(engine);
+        interp.eval_file(dir.join("main.scm")).unwrap();
+        assert_eq!(eval(&mut interp, "(area 4 5)"), "20");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn load_can_evaluate_in_another_environment() {
+    let dir = scratch_dir("environment", &[("defs.scm", "(define secret 42)")]);
+    let mut interp = in_dir(&dir, Engine::Bytecode);
+    interp.eval_str(r#"
+        (define env (scheme-report-environment 5))
+        (load "defs.scm" env)
+    "#).unwrap();
+    assert_eq!(eval(&mut interp, "(eval 'secret env)"), "42");
+    assert!(interp.lookup_global("secret").is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn includes_splice_forms_where_they_appear() {
+    let dir = scratch_dir("include", &[
+        ("body.scm", "(define inner 1)\n(+ inner n)"),
+        ("loud.scm", "(DEFINE (Twice X) (* 2 X))\n(define Name \"Kept\")"),
+        ("nested/outer.scm", "(include \"inner.scm\")"),
+        ("nested/inner.scm", "(define nested 'found)"),
+    ]);
+    for &engine in &ENGINES {
+        let mut interp = in_dir(&dir, engine);
+        interp.eval_str("(define (f n) (include \"body.scm\"))").unwrap();
+        assert_eq!(eval(&mut interp, "(f 10)"), "11");
+        assert!(interp.lookup_global("inner").is_none());
+
+        // include-ci folds the case of symbols, but not of strings
+        interp.eval_str("(include-ci \"loud.scm\")").unwrap();
+        assert_eq!(eval(&mut interp, "(twice 4)"), "8");
+        assert_eq!(eval(&mut interp, "name"), "\"Kept\"");
+        assert!(interp.eval_str("(include \"loud.scm\")").is_err());
+
+        interp.eval_str("(include \"nested/outer.scm\")").unwrap();
+        assert_eq!(eval(&mut interp, "nested"), "found");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cycles_are_reported() {
+    let dir = scratch_dir("cycles", &[
+        ("a.scm", "(load \"b.scm\")"),
+        ("b.scm", "(define b 1)\n(load \"a.scm\")"),
+        ("self.scm", "(include \"self.scm\")"),
+    ]);
+    let mut interp = in_dir(&dir, Engine::Bytecode);
+    let e = error(&mut interp, "(load \"a.scm\")");
+    assert!(e.contains("a.scm -> b.scm -> a.scm"), "{}", e);
+    let e = error(&mut interp, "(include \"self.scm\")");
+    assert!(e.contains("self.scm -> self.scm"), "{}", e);
+    // Each file is left, so loading works again afterwards
+    interp.eval_str("(load \"b.scm\")").unwrap_err();
+    assert_eq!(eval(&mut interp, "b"), "1");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn errors_name_the_file_and_line() {
+    let dir = scratch_dir("errors", &[
+        ("main.scm", "(define x 1)\n\n(load \"lib/bad.scm\")\n"),
+        ("lib/bad.scm", "(define y 2)\n; a comment\n(display\n  nope)\n"),
+        ("unbalanced.scm", "(define ok 1)\n(define (broken)\n  (+ 1 2)\n"),
+        ("lexing.scm", "(define a 1)\n\n(define s #q)"),
+        ("includer.scm", "\n(include \"unbalanced.scm\")"),
+        ("raising.scm", "\n(raise 'oops)"),
+    ]);
+    for &engine in &ENGINES {
+        let mut interp = in_dir(&dir, engine);
+        let e = error(&mut interp, "(load \"main.scm\")");
+        assert_eq!(e, "lib/bad.scm, line 3: Symbol is not bound: nope\n  from main.scm, line 3");
+        assert_eq!(error(&mut interp, "(load \"unbalanced.scm\")"),
+                   "unbalanced.scm, line 2: Parse error: Unexpected end of input");
+        assert_eq!(error(&mut interp, "(load \"lexing.scm\")"),
+                   "lexing.scm, line 3: Parse error: Illegal # syntax: #q");
+        assert_eq!(error(&mut interp, "(load \"includer.scm\")"),
+                   "unbalanced.scm, line 2: Parse error: Unexpected end of input\n  from includer.scm, line 2");
+        assert_eq!(error(&mut interp, "(load \"raising.scm\")"),
+                   "raising.scm, line 2: Uncaught exception: oops");
+
+        // Handlers see the error itself, without where it came from
+        assert_eq!(eval(&mut interp, r#"
+            (guard (e ((error-object? e) (error-object-message e)))
+              (load "main.scm"))
+        "#), "\"Symbol is not bound: nope\"");
+        assert_eq!(eval(&mut interp, "(guard (e (#t e)) (load \"raising.scm\"))"), "oops");
+        assert_eq!(eval(&mut interp, "(guard (e ((file-error? e) 'missing)) (load \"none.scm\"))"), "missing");
+    }
+    let mut interp = Interpreter::new();
+    let e = interp.eval_source("(define x 1)\n(car x)", "script.scm").unwrap_err();
+    assert!(format!("{}", e).starts_with("script.scm, line 2: "), "{}", e);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exits_pass_through_loads() {
+    let dir = scratch_dir("exit", &[("quit.scm", "(exit 3)")]);
+    let mut interp = Interpreter::new();
+    let path = dir.join("quit.scm");
+    assert_eq!(interp.eval_str(&format!("(load {:?})", path.to_str().unwrap())), Err(EvalError::Exit(3)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sandboxes_confine_what_is_loaded() {
+    let dir = scratch_dir("sandbox", &[("inside/a.scm", "(load \"../../outside.scm\")")]);
+    let mut interp = in_dir(&dir, Engine::Bytecode);
+    let e = error(&mut interp, "(load \"inside/a.scm\")");
+    assert!(e.contains("Access outside the permitted directory"), "{}", e);
+
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    assert!(interp.lookup_global("load").is_none());
+    let e = error(&mut interp, "(include \"inside/a.scm\")");
+    assert!(e.contains("Access to files is not permitted"), "{}", e);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,155 @@
+extern crate scheme;
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use scheme::{Capabilities, ErrorKind, ErrorObject, EvalError, FileAccess, Interpreter, SValue};
+
+use common::{eval, scratch_dir};
+
+fn in_dir(dir: &Path) -> Interpreter {
+    let caps = Capabilities { files: FileAccess::ReadWrite(dir.to_path_buf()), ..Capabilities::pure() };
+    Interpreter::with_capabilities(caps)
+}
+
+#[test]
+fn writing_and_reading_files() {
+    let dir = scratch_dir("files", &[]);
+    let mut interp = in_dir(&dir);
+    interp.eval_str(r#"
+        (define out (open-output-file "notes.txt"))
+        (display "one " out)
+        (write "two" out)
+        (newline out)
+        (write-char #\λ out)
+        (write-string "\nlast" out)
+        (close-port out)
+    "#).unwrap();
+    assert_eq!(fs::read_to_string(dir.join("notes.txt")).unwrap(), "one \"two\"\nλ\nlast");
+
+    interp.eval_str(r#"(define in (open-input-file "notes.txt"))"#).unwrap();
+    assert_eq!(eval(&mut interp, "(read-line in)"), "\"one \\\"two\\\"\"");
+    assert_eq!(eval(&mut interp, "(peek-char in)"), "#\\λ");
+    assert_eq!(eval(&mut interp, "(read-char in)"), "#\\λ");
+    assert_eq!(eval(&mut interp, "(read-char in)"), "#\\newline");
+    assert_eq!(eval(&mut interp, "(read-string 10 in)"), "\"last\"");
+    assert_eq!(interp.eval_str("(read-line in)"), Ok(SValue::Eof));
+    assert_eq!(eval(&mut interp, "(eof-object? (read-char in))"), "#t");
+    interp.eval_str("(close-port in)").unwrap();
+    assert!(interp.eval_str("(read-char in)").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn current_ports_are_redirected_for_a_call() {
+    let dir = scratch_dir("redirect", &[]);
+    let mut interp = in_dir(&dir);
+    interp.eval_str(r#"
+        (with-output-to-file "out.txt" (lambda () (display "hello") (newline)))
+        (call-with-output-file "more.txt" (lambda (port) (write 'more port)))
+    "#).unwrap();
+    assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "hello\n");
+    assert_eq!(fs::read_to_string(dir.join("more.txt")).unwrap(), "more");
+
+    assert_eq!(eval(&mut interp, r#"(with-input-from-file "out.txt" read-line)"#), "\"hello\"");
+    assert_eq!(eval(&mut interp, r#"(call-with-input-file "more.txt" read-char)"#), "#\\m");
+    // Outside the call, the console is refused again
+    assert!(interp.eval_str("(display 1)").is_err());
+
+    // The current ports are parameters
+    assert_eq!(eval(&mut interp, r#"
+        (define out (open-output-string))
+        (parameterize ((current-output-port out)) (display "to a string") (write-char #\!))
+        (get-output-string out)"#), "\"to a string!\"");
+    assert!(interp.eval_str("(display 1)").is_err());
+    assert_eq!(eval(&mut interp, "(guard (e (#t (error-object-message e))) (parameterize ((current-input-port out)) 1))"),
+               "\"`current-input-port` expected an input port; was given #<output-port>\"");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_are_flushed_when_the_interpreter_is_dropped() {
+    let dir = scratch_dir("flush", &[]);
+    let mut interp = in_dir(&dir);
+    interp.eval_str(r#"(define out (open-output-file "kept.txt")) (display "kept" out)"#).unwrap();
+    drop(interp);
+    assert_eq!(fs::read_to_string(dir.join("kept.txt")).unwrap(), "kept");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ports_check_their_direction_and_the_sandbox() {
+    let dir = scratch_dir("misuse", &[]);
+    let mut interp = in_dir(&dir);
+    assert!(interp.eval_str(r#"(open-input-file "missing.txt")"#).is_err());
+    assert!(interp.eval_str(r#"(open-output-file "../escape.txt")"#).is_err());
+    interp.eval_str(r#"(define out (open-output-file "x.txt"))"#).unwrap();
+    assert_eq!(eval(&mut interp, "(list (output-port? out) (input-port? out))"), "(#t #f)");
+    assert!(interp.eval_str("(read-char out)").is_err());
+    assert!(interp.eval_str("(read-char 5)").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn string_ports_need_no_capabilities() {
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    interp.eval_str(r#"
+        (define in (open-input-string "first line\nλ2"))
+        (define out (open-output-string))
+    "#).unwrap();
+    assert_eq!(eval(&mut interp, "(read-line in)"), "\"first line\"");
+    assert_eq!(eval(&mut interp, "(list (read-char in) (read-string 5 in) (read-char in))"),
+               "(#\\λ \"2\" #<eof>)");
+    assert_eq!(eval(&mut interp, r#"(write 'sym out) (display " and " out) (get-output-string out)"#),
+               "\"sym and \"");
+    assert!(interp.eval_str("(get-output-string in)").is_err());
+
+    assert_eq!(eval(&mut interp, r#"(with-output-to-string (lambda () (write "q") (write-char #\!)))"#),
+               "\"\\\"q\\\"!\"");
+    assert_eq!(eval(&mut interp, "(call-with-output-string (lambda (port) (display 42 port)))"), "\"42\"");
+    // The current output port is the console's again afterwards
+    assert!(interp.eval_str("(display 1)").is_err());
+}
+
+#[test]
+fn reading_data() {
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    interp.eval_str(r#"
+        (define in (open-input-string "(name \"a (b)\" #\\) 1.5) sym ; comment\n 'q (x . y) #(1 #(2)) rest"))
+    "#).unwrap();
+    assert_eq!(eval(&mut interp, "(read in)"), "(name \"a (b)\" #\\) 1.5)");
+    assert_eq!(eval(&mut interp, "(read in)"), "sym");
+    assert_eq!(eval(&mut interp, "(read in)"), "(quote q)");
+    assert_eq!(eval(&mut interp, "(read in)"), "(x . y)");
+    assert_eq!(eval(&mut interp, "(read in)"), "#(1 #(2))");
+    // Reading stops at the end of the datum
+    assert_eq!(eval(&mut interp, "(list (read in) (read-char in))"), "(rest #<eof>)");
+    assert_eq!(interp.eval_str("(read in)"), Ok(SValue::Eof));
+    assert_eq!(eval(&mut interp, "(eq? (car (read (open-input-string \"(a)\"))) 'a)"), "#t");
+}
+
+#[test]
+fn malformed_data_raises_read_errors() {
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    let caught = "(guard (e ((read-error? e) 'read-error)) (read (open-input-string {})))";
+    for src in &["\"(1 2\"", "\")\"", "\"#z\"", "\"(1 . )\""] {
+        assert_eq!(eval(&mut interp, &caught.replace("{}", src)), "read-error", "reading {}", src);
+    }
+    match interp.eval_str("(read (open-input-string \"(1 2\"))") {
+        Err(EvalError::Raise(v)) => {
+            assert_eq!(v.downcast::<ErrorObject>().unwrap().kind, ErrorKind::Read);
+        },
+        other => panic!("expected a read error, got {:?}", other),
+    }
+}
+
+#[test]
+fn failing_to_open_a_file_raises_a_file_error() {
+    let dir = scratch_dir("file-error", &[]);
+    let mut interp = in_dir(&dir);
+    let src = r#"(guard (e ((file-error? e) 'file-error)) (open-input-file "missing.txt"))"#;
+    assert_eq!(eval(&mut interp, src), "file-error");
+    fs::remove_dir_all(&dir).unwrap();
+}
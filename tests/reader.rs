@@ -0,0 +1,88 @@
+extern crate scheme;
+
+use std::rc::Rc;
+
+use scheme::parse::{ReadError, Reader, Sexp};
+
+fn sym(s: &str) -> Sexp {
+    Sexp::Symbol(Rc::from(s))
+}
+
+fn list(items: Vec<Sexp>) -> Sexp {
+    Sexp::List(items.into())
+}
+
+#[test]
+fn unfinished_forms_wait_for_more_input() {
+    let mut reader = Reader::new();
+    reader.feed("(define x\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Incomplete));
+    assert!(!reader.is_empty());
+    reader.feed("  1) (+ x\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Incomplete));
+    reader.feed("x)\n");
+    assert_eq!(reader.read_forms(), Ok(vec![
+        list(vec![sym("define"), sym("x"), Sexp::Number(1.0)]),
+        list(vec![sym("+"), sym("x"), sym("x")]),
+    ]));
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn strings_may_span_lines() {
+    let mut reader = Reader::new();
+    reader.feed("(display \"one (\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Incomplete));
+    reader.feed("two\")\n");
+    assert_eq!(reader.read_forms(),
+               Ok(vec![list(vec![sym("display"), Sexp::String(Rc::from("one (\ntwo"))])]));
+}
+
+#[test]
+fn parens_in_comments_are_ignored() {
+    let mut reader = Reader::new();
+    reader.feed("; (not a form\n");
+    assert_eq!(reader.read_forms(), Ok(vec![]));
+    reader.feed("(+ 1 ; ((\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Incomplete));
+    reader.feed("2) ; )\n");
+    assert_eq!(reader.read_forms(), Ok(vec![list(vec![sym("+"), Sexp::Number(1.0), Sexp::Number(2.0)])]));
+}
+
+#[test]
+fn a_trailing_quote_waits_for_its_datum() {
+    let mut reader = Reader::new();
+    reader.feed("'\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Incomplete));
+    reader.feed("x\n");
+    assert_eq!(reader.read_forms(), Ok(vec![list(vec![sym("quote"), sym("x")])]));
+}
+
+#[test]
+fn unbalanced_close_parens_are_errors_and_discarded() {
+    let mut reader = Reader::new();
+    reader.feed("1)\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Invalid(String::from("Unexpected ')'"))));
+    assert!(reader.is_empty());
+    reader.feed("2\n");
+    assert_eq!(reader.read_forms(), Ok(vec![Sexp::Number(2.0)]));
+}
+
+#[test]
+fn backquote_and_commas_abbreviate_quasiquote() {
+    let mut reader = Reader::new();
+    reader.feed("`(a ,b ,@c) 'd\n");
+    assert_eq!(reader.read_forms(), Ok(vec![
+        list(vec![sym("quasiquote"), list(vec![
+            sym("a"),
+            list(vec![sym("unquote"), sym("b")]),
+            list(vec![sym("unquote-splicing"), sym("c")]),
+        ])]),
+        list(vec![sym("quote"), sym("d")]),
+    ]));
+    // A comma waits for its datum as a quote does
+    reader.feed(",\n");
+    assert_eq!(reader.read_forms(), Err(ReadError::Incomplete));
+    reader.feed("@x\n");
+    assert_eq!(reader.read_forms(), Ok(vec![list(vec![sym("unquote"), sym("@x")])]));
+}
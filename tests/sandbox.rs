@@ -0,0 +1,81 @@
+extern crate scheme;
+
+mod common;
+
+use std::fs;
+
+use scheme::{Capabilities, FileAccess, Interpreter, SValue};
+
+use common::scratch_dir;
+
+fn unbound(interp: &mut Interpreter, name: &str) -> bool {
+    interp.eval_str(name).is_err()
+}
+
+fn console_refused(interp: &mut Interpreter) -> bool {
+    interp.eval_str("(display \"\")").is_err() && interp.eval_str("(read-char)").is_err()
+}
+
+#[test]
+fn pure_interpreters_only_compute() {
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    for name in &["exit", "command-line", "open-input-file", "with-output-to-file"] {
+        assert!(unbound(&mut interp, name), "{} is bound", name);
+    }
+    assert!(console_refused(&mut interp));
+    let v = interp.eval_str("(define (sq x) (* x x)) (list (sq 3) (string-append \"a\" \"b\"))");
+    assert_eq!(format!("{:?}", v.unwrap()), "(9 \"ab\")");
+}
+
+#[test]
+fn capabilities_are_granted_separately() {
+    let caps = Capabilities { console: true, ..Capabilities::pure() };
+    let mut interp = Interpreter::with_capabilities(caps);
+    assert!(!console_refused(&mut interp));
+    assert!(unbound(&mut interp, "exit"));
+
+    let caps = Capabilities { process: true, ..Capabilities::pure() };
+    let mut interp = Interpreter::with_capabilities(caps);
+    assert!(console_refused(&mut interp));
+    assert!(!unbound(&mut interp, "exit"));
+    assert_eq!(interp.eval_str("(command-line)"), Ok(SValue::Nil));
+}
+
+#[test]
+fn report_environments_hold_only_what_was_granted() {
+    let mut interp = Interpreter::with_capabilities(Capabilities::pure());
+    interp.define_fn("host-secret", || 42);
+    assert!(interp.eval_str("(eval '(exit) (scheme-report-environment 5))").is_err());
+    assert!(interp.eval_str("(eval '(host-secret) (scheme-report-environment 5))").is_err());
+    assert_eq!(interp.eval_str("(eval '(host-secret) (interaction-environment))"), Ok(SValue::Number(42.0)));
+}
+
+#[test]
+fn file_paths_are_confined_to_the_root() {
+    let root = scratch_dir("confined", &[("sub/c.txt", "")]);
+    let caps = Capabilities { files: FileAccess::ReadOnly(root.clone()), ..Capabilities::pure() };
+    assert_eq!(caps.file_path("a.txt", false), Ok(root.join("a.txt")));
+    assert_eq!(caps.file_path("sub/../b.txt", false), Ok(root.join("b.txt")));
+    assert_eq!(caps.file_path("/sub/c.txt", false), Ok(root.join("sub/c.txt")));
+    assert!(caps.file_path("../outside.txt", false).is_err());
+    assert!(caps.file_path("sub/../../outside.txt", false).is_err());
+    assert!(caps.file_path("a.txt", true).is_err());
+
+    let caps = Capabilities { files: FileAccess::ReadWrite(root.clone()), ..Capabilities::pure() };
+    assert_eq!(caps.file_path("a.txt", true), Ok(root.join("a.txt")));
+    assert!(Capabilities::pure().file_path("a.txt", false).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn links_out_of_the_root_are_refused() {
+    let root = scratch_dir("links", &[]);
+    let outside = scratch_dir("links-target", &[]);
+    std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+    let caps = Capabilities { files: FileAccess::ReadWrite(root.clone()), ..Capabilities::pure() };
+    assert!(caps.file_path("escape/secret.txt", false).is_err());
+    assert!(caps.file_path("escape/new.txt", true).is_err());
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&outside).unwrap();
+}